@@ -0,0 +1,712 @@
+//! Converting uploads over HTTP, the `POST /convert` route of [`crate::server`].
+//!
+//! The request body is a raster image (PNG, JPEG, ...), a 2dj or a 2db, the query says what
+//! it's converted to:
+//!
+//! - `format`: `2db` (the default), `2dj` or `png`.
+//! - `size=WxH` scales the image first, `width` or `height` alone keep its aspect ratio.
+//! - `palette`, `colors`, `quantizer`, `dither` and `dither-strength` work like the command
+//!   line arguments of the same name, a palette can't be a file though.
+//! - `label` and `tooltip` replace those of the image.
+//! - `version`, `compression` and `checksums` override the binary format options the server
+//!   was started with.
+//!
+//! A 2dj or 2db body keeps its own palette unless `palette`, `colors`, `quantizer` or
+//! `dither` asks for it to be quantized again.
+//!
+//! Uploads are limited to [`MAX_BODY_LENGTH`] bytes and to images of at most
+//! [`MAX_UPLOAD_PIXELS`] pixels, checked before the image is decoded.
+//!
+//! Failures are answered with `{"error": {"kind": ..., "message": ..., ...}}`. The kind is
+//! that of the [`PosterError`] with its fields alongside, or one of `invalid_query` (with the
+//! offending `parameter`), `unsupported_input` and `too_large`.
+
+use crate::compression::Compression;
+use crate::error::PosterError;
+use crate::export::{render_image, ExportOptions};
+use crate::import::{import_image, parse_palette, Dither, ImportOptions, Quantizer};
+use crate::poster::{
+    decode_2db, decode_2dj, detect_format, peek_2db_size, DecodeOptions, FormatVersion, Img2d,
+    PosterFormat, MAX_PALETTE_LENGTH,
+};
+use crate::server::{encode, percent_decode, Loaded, Reply, ServeOptions, Target, MAX_BODY_LENGTH};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageReader};
+use serde_json::json;
+use std::io::Cursor;
+
+/// Largest width or height an upload can be scaled to.
+const MAX_DIMENSION: u32 = 4096;
+
+/// Largest number of pixels an uploaded image can have. A compressed 2db or a PNG can declare
+/// far more than its size suggests, so this is checked before decoding it.
+pub const MAX_UPLOAD_PIXELS: u64 = MAX_DIMENSION as u64 * MAX_DIMENSION as u64;
+
+fn error_reply(status: u16, error: serde_json::Value) -> Reply {
+    return Reply {
+        status,
+        content_type: "application/json",
+        body: serde_json::to_vec_pretty(&json!({ "error": error }))
+            .expect("serializing JSON can't fail"),
+        etag: None,
+        last_modified: None,
+    };
+}
+
+fn query_error(parameter: &str, message: &str) -> Reply {
+    return error_reply(
+        400,
+        json!({
+            "kind": "invalid_query",
+            "parameter": parameter,
+            "message": message,
+        }),
+    );
+}
+
+/// `error` as a JSON object of its kind, its message and the fields of its variant.
+pub fn error_json(error: &PosterError) -> serde_json::Value {
+    let mut value = match error {
+        PosterError::Truncated {
+            field,
+            offset,
+            needed,
+            available,
+        } => json!({
+            "field": field,
+            "offset": offset,
+            "needed": needed,
+            "available": available,
+        }),
+        PosterError::Charset {
+            field,
+            offset,
+            character,
+        } => json!({ "field": field, "offset": offset, "character": character }),
+        PosterError::StringTooLong { field, length, max } => {
+            json!({ "field": field, "length": length, "max": max })
+        }
+        PosterError::PaletteOverflow { length, max } => json!({ "length": length, "max": max }),
+        PosterError::PixelOutOfRange {
+            index,
+            value,
+            palette_length,
+        } => json!({
+            "index": index,
+            "value": value,
+            "palette_length": palette_length,
+        }),
+        PosterError::GridMismatch {
+            columns,
+            rows,
+            pages,
+        } => json!({ "columns": columns, "rows": rows, "pages": pages }),
+        PosterError::Invalid(violations) => json!({
+            "violations": violations
+                .iter()
+                .map(|violation| json!({
+                    "page": violation.page.map(|page| page + 1),
+                    "message": violation.to_string(),
+                }))
+                .collect::<Vec<serde_json::Value>>(),
+        }),
+        PosterError::UnsupportedVersion { offset, version } => {
+            json!({ "offset": offset, "version": version })
+        }
+        PosterError::UnsupportedFlags { offset, flags } => {
+            json!({ "offset": offset, "flags": flags })
+        }
+        PosterError::FormatMismatch { expected, found } => json!({
+            "expected": expected.extension(),
+            "found": found.extension(),
+        }),
+        PosterError::UnsupportedCompression { offset, id } => {
+            json!({ "offset": offset, "id": id })
+        }
+        PosterError::CorruptPixels {
+            offset,
+            compression,
+        } => json!({ "offset": offset, "compression": compression.name() }),
+        PosterError::PixelCountMismatch {
+            offset,
+            declared,
+            expected,
+        } => json!({ "offset": offset, "declared": declared, "expected": expected }),
+        PosterError::ChecksumMismatch {
+            field,
+            offset,
+            stored,
+            computed,
+        } => json!({
+            "field": field,
+            "offset": offset,
+            "stored": stored,
+            "computed": computed,
+        }),
+        PosterError::VersionTooOld { feature } => json!({ "feature": feature }),
+        PosterError::PageLengthMismatch {
+            offset,
+            declared,
+            consumed,
+        } => json!({ "offset": offset, "declared": declared, "consumed": consumed }),
+        PosterError::ImageTooLarge { width, height } => json!({ "width": width, "height": height }),
+        PosterError::TrailingBytes { offset, count } => json!({ "offset": offset, "count": count }),
+        PosterError::Json(_) | PosterError::Image(_) | PosterError::Io(_) => json!({}),
+    };
+    value["kind"] = error.kind().into();
+    value["message"] = error.to_string().into();
+
+    return value;
+}
+
+fn too_large(message: &str) -> Reply {
+    return error_reply(413, json!({ "kind": "too_large", "message": message }));
+}
+
+/// Answers uploads of more than [`MAX_UPLOAD_PIXELS`] pixels, `None` for those that fit.
+fn check_upload_size(width: u32, height: u32) -> Option<Reply> {
+    if width as u64 * height as u64 <= MAX_UPLOAD_PIXELS {
+        return None;
+    }
+
+    return Some(too_large(&format!(
+        "{}x{} pixels is more than the {} pixels uploads are limited to",
+        width, height, MAX_UPLOAD_PIXELS
+    )));
+}
+
+/// Answers a failed conversion, blaming the server only for I/O errors.
+fn poster_error(error: &PosterError) -> Reply {
+    let status = match error {
+        PosterError::Io(_) => 500,
+        _ => 422,
+    };
+
+    return error_reply(status, error_json(error));
+}
+
+/// Parses a `WxH` size.
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once(['x', 'X'])?;
+
+    return Some((parse_dimension(width)?, parse_dimension(height)?));
+}
+
+fn parse_dimension(dimension: &str) -> Option<u32> {
+    return dimension
+        .parse::<u32>()
+        .ok()
+        .filter(|dimension| (1..=MAX_DIMENSION).contains(dimension));
+}
+
+fn parse_flag(flag: &str) -> Option<bool> {
+    return match flag.to_ascii_lowercase().as_str() {
+        "" | "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        _ => None,
+    };
+}
+
+/// The size an image of `width` by `height` is scaled to, filling in a missing dimension from
+/// the aspect ratio.
+fn scaled_size(width: u32, height: u32, requested: (Option<u32>, Option<u32>)) -> (u32, u32) {
+    let scale = |value: u32, to: u32, from: u32| -> u32 {
+        let scaled = value as u64 * to as u64 / from.max(1) as u64;
+        return scaled.clamp(1, MAX_DIMENSION as u64) as u32;
+    };
+
+    return match requested {
+        (Some(new_width), Some(new_height)) => (new_width, new_height),
+        (Some(new_width), None) => (new_width, scale(height, new_width, width)),
+        (None, Some(new_height)) => (scale(width, new_height, height), new_height),
+        (None, None) => (width, height),
+    };
+}
+
+/// Converts the image in `body` as the `query` of a `POST /convert` asks, see the module
+/// documentation.
+pub fn convert(query: &str, body: &[u8], options: &ServeOptions) -> Reply {
+    if body.len() > MAX_BODY_LENGTH {
+        return too_large(&format!("uploads are limited to {} bytes", MAX_BODY_LENGTH));
+    }
+
+    let mut target = Target::Poster(PosterFormat::Binary);
+    let mut size: (Option<u32>, Option<u32>) = (None, None);
+    let mut import = options.import.clone();
+    let mut encode_options = options.encode.clone();
+    // Whether the query asks for the palette of a poster to be generated again
+    let mut requantize = false;
+    let mut label: Option<String> = None;
+    let mut tooltip: Option<String> = None;
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = match percent_decode(value, true) {
+            Some(t) => t,
+            None => return query_error(key, "Invalid percent encoding."),
+        };
+        match key {
+            "format" => {
+                target = match Target::from_name(&value) {
+                    Some(Target::Poster(format)) if format.is_array() => {
+                        return query_error(
+                            key,
+                            "Uploads are single images, valid formats are (2dj,2db,png).",
+                        )
+                    }
+                    Some(t) => t,
+                    None => {
+                        return query_error(key, "Invalid format, valid formats are (2dj,2db,png).")
+                    }
+                }
+            }
+            "size" => {
+                size = match parse_size(&value) {
+                    Some((width, height)) => (Some(width), Some(height)),
+                    None => {
+                        return query_error(
+                            key,
+                            &format!(
+                                "Invalid size, has to be WxH with sides from 1 to {}.",
+                                MAX_DIMENSION
+                            ),
+                        )
+                    }
+                }
+            }
+            "width" | "height" => {
+                let dimension = match parse_dimension(&value) {
+                    Some(t) => Some(t),
+                    None => {
+                        return query_error(
+                            key,
+                            &format!("Invalid {}, has to be from 1 to {}.", key, MAX_DIMENSION),
+                        )
+                    }
+                };
+                if key == "width" {
+                    size.0 = dimension;
+                } else {
+                    size.1 = dimension;
+                }
+            }
+            "palette" => {
+                import.palette = match parse_palette(&value) {
+                    Some(t) => Some(t),
+                    None => {
+                        return query_error(
+                            key,
+                            "Invalid palette, has to be \"cc\" or a list of RRGGBB hex colors.",
+                        )
+                    }
+                };
+                requantize = true;
+            }
+            "colors" => {
+                import.max_colors = match value.parse::<usize>() {
+                    Ok(colors) if (1..=MAX_PALETTE_LENGTH).contains(&colors) => colors,
+                    _ => {
+                        return query_error(
+                            key,
+                            &format!(
+                                "Invalid colors, has to be from 1 to {}.",
+                                MAX_PALETTE_LENGTH
+                            ),
+                        )
+                    }
+                };
+                import.palette = None;
+                requantize = true;
+            }
+            "quantizer" => {
+                import.quantizer =
+                    match Quantizer::from_name(&value) {
+                        Some(t) => t,
+                        None => return query_error(
+                            key,
+                            "Invalid quantizer, valid quantizers are (median-cut,kmeans,octree).",
+                        ),
+                    };
+                requantize = true;
+            }
+            "dither" => {
+                import.dither = match Dither::from_name(&value) {
+                    Some(t) => t,
+                    None => return query_error(key, "Invalid dither, valid dithers are (none,floyd-steinberg,atkinson,sierra,bayer4,bayer8)."),
+                };
+                requantize = true;
+            }
+            "dither-strength" => {
+                import.dither_strength = match value.parse::<f32>() {
+                    Ok(strength) if strength.is_finite() && strength >= 0.0 => strength,
+                    _ => {
+                        return query_error(
+                            key,
+                            "Invalid dither strength, has to be a number of at least 0.",
+                        )
+                    }
+                }
+            }
+            "label" => label = Some(value),
+            "tooltip" => tooltip = Some(value),
+            "version" => {
+                encode_options.version = match value
+                    .parse::<u8>()
+                    .ok()
+                    .and_then(FormatVersion::from_number)
+                {
+                    Some(t) => t,
+                    None => return query_error(key, "Invalid version, valid versions are (1,2)."),
+                }
+            }
+            "compression" => {
+                encode_options.compression = match Compression::from_name(&value) {
+                    Some(t) => t,
+                    None => {
+                        return query_error(
+                            key,
+                            "Invalid compression, valid compressions are (none,rle,deflate).",
+                        )
+                    }
+                }
+            }
+            "checksums" => {
+                encode_options.checksums = match parse_flag(&value) {
+                    Some(t) => t,
+                    None => return query_error(key, "Invalid checksums, has to be true or false."),
+                }
+            }
+            _ => return query_error(key, "Unknown parameter."),
+        }
+    }
+
+    // Raster formats have unambiguous signatures, posters are only recognised by decoding
+    let source: Result<DynamicImage, Img2d> = if image::guess_format(body).is_ok() {
+        let reader = || ImageReader::new(Cursor::new(body)).with_guessed_format();
+        let dimensions = reader().map_err(PosterError::from).and_then(|reader| {
+            return Ok(reader.into_dimensions()?);
+        });
+        match dimensions {
+            Ok((width, height)) => {
+                if let Some(reply) = check_upload_size(width, height) {
+                    return reply;
+                }
+            }
+            Err(e) => return poster_error(&e),
+        }
+        match reader().map_err(PosterError::from).and_then(|reader| {
+            return Ok(reader.decode()?);
+        }) {
+            Ok(t) => Ok(t),
+            Err(e) => return poster_error(&e),
+        }
+    } else {
+        let format = detect_format(body);
+        if format == Some(PosterFormat::Binary) {
+            match peek_2db_size(body) {
+                Ok((width, height)) => {
+                    if let Some(reply) = check_upload_size(width, height) {
+                        return reply;
+                    }
+                }
+                Err(e) => return poster_error(&e),
+            }
+        }
+        let decoded = match format {
+            Some(PosterFormat::Json) => decode_2dj(body),
+            Some(PosterFormat::Binary) => decode_2db(body, &DecodeOptions::default()),
+            Some(_) => {
+                return error_reply(
+                    415,
+                    json!({
+                        "kind": "unsupported_input",
+                        "message": "image arrays can't be converted, upload a single image",
+                    }),
+                )
+            }
+            None => {
+                return error_reply(
+                    415,
+                    json!({
+                        "kind": "unsupported_input",
+                        "message": "the body is neither a raster image, a 2dj nor a 2db",
+                    }),
+                )
+            }
+        };
+        match decoded {
+            Ok(poster) => {
+                // A 2dj holds every pixel, but its width and height are still only claims
+                if let Some(reply) = check_upload_size(poster.width, poster.height) {
+                    return reply;
+                }
+                Err(poster)
+            }
+            Err(e) => return poster_error(&e),
+        }
+    };
+
+    let mut image = match source {
+        Ok(raster) => {
+            let (width, height) = scaled_size(raster.width(), raster.height(), size);
+            let raster = if (width, height) != (raster.width(), raster.height()) {
+                raster.resize_exact(width, height, FilterType::Triangle)
+            } else {
+                raster
+            };
+            match import_image(&raster, &import) {
+                Ok(t) => t,
+                Err(e) => return poster_error(&e),
+            }
+        }
+        Err(poster) if !requantize && size == (None, None) => poster,
+        Err(poster) => {
+            // Posters are pixel art, keep their edges sharp and, unless told otherwise, their
+            // colours exact
+            let (width, height) = scaled_size(poster.width, poster.height, size);
+            let rendered = match render_image(&poster, &ExportOptions::default()) {
+                Ok(t) => {
+                    DynamicImage::ImageRgba8(t).resize_exact(width, height, FilterType::Nearest)
+                }
+                Err(e) => return poster_error(&e),
+            };
+            let import = if requantize {
+                import
+            } else {
+                ImportOptions {
+                    palette: Some(poster.palette.clone()),
+                    dither: Dither::None,
+                    ..import
+                }
+            };
+            match import_image(&rendered, &import) {
+                Ok(converted) => Img2d {
+                    label: poster.label,
+                    tooltip: poster.tooltip,
+                    ..converted
+                },
+                Err(e) => return poster_error(&e),
+            }
+        }
+    };
+    if label.is_some() {
+        image.label = label;
+    }
+    if tooltip.is_some() {
+        image.tooltip = tooltip;
+    }
+
+    return match encode(&Loaded::Image(image), target, &encode_options) {
+        Ok(body) => Reply {
+            status: 200,
+            content_type: target.content_type(),
+            body,
+            etag: None,
+            last_modified: None,
+        },
+        Err(e) => poster_error(&e),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poster::{decode_2db, encode_2db, EncodeOptions};
+
+    fn options() -> ServeOptions {
+        return ServeOptions {
+            root: std::env::temp_dir(),
+            address: "127.0.0.1:0".to_string(),
+            encode: EncodeOptions::default(),
+            import: ImportOptions::default(),
+        };
+    }
+
+    fn poster_json() -> Vec<u8> {
+        return br#"{"label":"castle","tooltip":null,"palette":[0,16777215],"pixels":[0,1,1,0],"width":2,"height":2}"#.to_vec();
+    }
+
+    fn error(reply: &Reply) -> serde_json::Value {
+        assert_eq!(reply.content_type, "application/json");
+        let value: serde_json::Value = serde_json::from_slice(&reply.body).unwrap();
+
+        return value["error"].clone();
+    }
+
+    #[test]
+    fn converts_to_2db_by_default() {
+        let reply = convert("", &poster_json(), &options());
+        assert_eq!(reply.status, 200);
+        let image = decode_2db(reply.body.as_slice(), &DecodeOptions { strict: true }).unwrap();
+        assert_eq!(image.label.as_deref(), Some("castle"));
+        assert_eq!(image.pixels, vec![0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn applies_query_parameters() {
+        let reply = convert(
+            "format=2dj&label=gate%20house&tooltip=a+b&size=4x2",
+            &poster_json(),
+            &options(),
+        );
+        assert_eq!(reply.status, 200);
+        let image = decode_2dj(reply.body.as_slice()).unwrap();
+        assert_eq!(image.label.as_deref(), Some("gate house"));
+        assert_eq!(image.tooltip.as_deref(), Some("a b"));
+        assert_eq!((image.width, image.height), (4, 2));
+        assert_eq!(image.pixels, vec![0, 0, 1, 1, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn overrides_binary_options() {
+        let reply = convert(
+            "version=2&compression=rle&checksums",
+            &poster_json(),
+            &options(),
+        );
+        assert_eq!(reply.status, 200);
+        assert_eq!(&reply.body[..6], &[0x89, b'2', b'D', b'B', 2, 0b11]);
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let cases = [
+            ("bogus=1", "bogus"),
+            ("format=2dba", "format"),
+            ("format=gif", "format"),
+            ("size=0x4", "size"),
+            ("width=5000", "width"),
+            ("colors=256", "colors"),
+            ("quantizer=best", "quantizer"),
+            ("dither=noise", "dither"),
+            ("dither-strength=-1", "dither-strength"),
+            ("version=3", "version"),
+            ("compression=zip", "compression"),
+            ("checksums=maybe", "checksums"),
+            ("label=%zz", "label"),
+        ];
+        for (query, parameter) in cases {
+            let reply = convert(query, &poster_json(), &options());
+            assert_eq!(reply.status, 400, "{}", query);
+            let error = error(&reply);
+            assert_eq!(error["kind"], "invalid_query", "{}", query);
+            assert_eq!(error["parameter"], parameter, "{}", query);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_bodies() {
+        let reply = convert("", b"hello", &options());
+        assert_eq!(reply.status, 415);
+        assert_eq!(error(&reply)["kind"], "unsupported_input");
+
+        let array = br#"{"width":1,"height":1,"title":null,"pages":[]}"#;
+        let reply = convert("", array, &options());
+        assert_eq!(reply.status, 415);
+        assert_eq!(error(&reply)["kind"], "unsupported_input");
+    }
+
+    #[test]
+    fn reports_poster_errors_by_kind() {
+        let mut bytes: Vec<u8> = Vec::new();
+        let encode_options = EncodeOptions {
+            version: FormatVersion::V2,
+            ..Default::default()
+        };
+        let image = decode_2dj(poster_json().as_slice()).unwrap();
+        encode_2db(&image, &mut bytes, &encode_options).unwrap();
+        bytes.truncate(bytes.len() - 1);
+
+        let reply = convert("", &bytes, &options());
+        assert_eq!(reply.status, 422);
+        let error = error(&reply);
+        assert_eq!(error["kind"], "truncated");
+        assert_eq!(error["field"], "pixels");
+        assert_eq!(error["available"], 3);
+    }
+
+    #[test]
+    fn rejects_large_bodies() {
+        let reply = convert("", &vec![0; MAX_BODY_LENGTH + 1], &options());
+        assert_eq!(reply.status, 413);
+        assert_eq!(error(&reply)["kind"], "too_large");
+    }
+
+    #[test]
+    fn rejects_huge_posters_before_decoding() {
+        // A v2 2db with absent strings claiming 65536x65536 pixels and nothing else
+        let mut bytes = vec![0x89, b'2', b'D', b'B', 2, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+        bytes.extend(65536u32.to_le_bytes());
+        bytes.extend(65536u32.to_le_bytes());
+
+        let reply = convert("", &bytes, &options());
+        assert_eq!(reply.status, 413);
+        assert_eq!(error(&reply)["kind"], "too_large");
+    }
+
+    #[test]
+    fn rejects_huge_rasters_before_decoding() {
+        // A 100000x100000 PNG without any image data
+        let mut header: Vec<u8> = Vec::new();
+        header.extend(100000u32.to_be_bytes());
+        header.extend(100000u32.to_be_bytes());
+        header.extend([8, 6, 0, 0, 0]);
+        let mut bytes: Vec<u8> = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [(b"IHDR", &header[..]), (b"IDAT", &[]), (b"IEND", &[])] {
+            let mut chunk: Vec<u8> = kind.to_vec();
+            chunk.extend(data);
+            bytes.extend((data.len() as u32).to_be_bytes());
+            bytes.extend(&chunk);
+            bytes.extend(crc32fast::hash(&chunk).to_be_bytes());
+        }
+
+        let reply = convert("", &bytes, &options());
+        assert_eq!(reply.status, 413);
+        assert_eq!(error(&reply)["kind"], "too_large");
+    }
+
+    #[test]
+    fn error_json_carries_kind_message_and_fields() {
+        let errors = [
+            PosterError::Truncated {
+                field: "width",
+                offset: 10,
+                needed: 4,
+                available: 1,
+            },
+            PosterError::Charset {
+                field: "label",
+                offset: 2,
+                character: '€',
+            },
+            PosterError::GridMismatch {
+                columns: 1,
+                rows: 1,
+                pages: 2,
+            },
+            PosterError::PixelCountMismatch {
+                offset: 28,
+                declared: 100,
+                expected: 4,
+            },
+            PosterError::ImageTooLarge {
+                width: 5,
+                height: 6,
+            },
+            PosterError::Io(std::io::Error::other("disk on fire")),
+        ];
+        for error in errors.iter() {
+            let value = error_json(error);
+            assert_eq!(value["kind"], error.kind());
+            assert_eq!(value["message"], error.to_string());
+        }
+
+        assert_eq!(error_json(&errors[0])["offset"], 10);
+        assert_eq!(error_json(&errors[1])["character"], "€");
+        assert_eq!(error_json(&errors[2])["pages"], 2);
+        assert_eq!(error_json(&errors[3])["declared"], 100);
+        assert_eq!(error_json(&errors[4])["height"], 6);
+        assert_eq!(poster_error(&errors[4]).status, 422);
+        assert_eq!(poster_error(&errors[5]).status, 500);
+    }
+}
@@ -0,0 +1,643 @@
+//! Converting whole directory trees at once, spread across every core.
+//!
+//! Inputs are directories (converted recursively), files or glob patterns. Every file is
+//! written to the same place below the output directory as it has below the directory or
+//! the fixed start of the pattern it was found through.
+
+use crate::error::PosterError;
+use crate::export::{write_contact_sheet, write_page_pngs, write_png};
+use crate::export::{ContactSheetOptions, ExportOptions};
+use crate::import::{decode_image, is_image_path, ImportOptions};
+use crate::poster::{
+    decode_2db, decode_2dba, decode_2dj, decode_2dja, detect_format, detect_magic, write_2db,
+    write_2dba, write_2dj, write_2dja, DecodeOptions, EncodeOptions, Img2d, Img2dArray, ImgFormat,
+    PosterFormat,
+};
+use crate::watch::WatchRoot;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+
+pub struct BatchOptions {
+    /// Directory the converted tree is written to.
+    pub output: PathBuf,
+    pub format: ImgFormat,
+    pub decode: DecodeOptions,
+    pub import: ImportOptions,
+    pub encode: EncodeOptions,
+    pub export: ExportOptions,
+    /// Export image arrays as one contact sheet instead of a PNG per page.
+    pub sheet: Option<ContactSheetOptions>,
+}
+
+/// A file [`find_inputs`] found.
+pub struct BatchInput {
+    pub file: PathBuf,
+    /// Where the file is in the tree being mirrored.
+    pub relative: PathBuf,
+}
+
+pub enum Outcome {
+    /// Written to these files.
+    Converted(Vec<PathBuf>),
+    /// Left alone for this reason.
+    Skipped(String),
+    Failed(PosterError),
+}
+
+pub struct BatchResult {
+    pub input: PathBuf,
+    pub outcome: Outcome,
+}
+
+fn is_glob(pattern: &str) -> bool {
+    return pattern.contains(['*', '?', '[']);
+}
+
+/// The leading components of `pattern` without wildcards, what its matches are relative to.
+fn glob_base(pattern: &str) -> PathBuf {
+    return Path::new(pattern)
+        .components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect();
+}
+
+fn is_hidden(path: &Path) -> bool {
+    return path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+}
+
+/// Whether `path` is `exclude` or below it, once symlinks are followed.
+fn is_excluded(path: &Path, exclude: Option<&Path>) -> bool {
+    return exclude.is_some_and(|exclude| {
+        path.canonicalize()
+            .is_ok_and(|path| path.starts_with(exclude))
+    });
+}
+
+/// Adds every file below `directory` to `inputs`, leaving out hidden files and `exclude`.
+fn walk(
+    base: &Path,
+    directory: &Path,
+    exclude: Option<&Path>,
+    inputs: &mut Vec<BatchInput>,
+) -> io::Result<()> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| !is_hidden(path))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            if is_excluded(&path, exclude) {
+                continue;
+            }
+            walk(base, &path, exclude, inputs)?;
+        } else if path.is_file() {
+            inputs.push(BatchInput {
+                relative: path.strip_prefix(base).unwrap_or(&path).to_path_buf(),
+                file: path,
+            });
+        }
+    }
+
+    return Ok(());
+}
+
+/// Expands directories and glob patterns into the files to convert, in order and without
+/// duplicates. Directories are searched recursively, skipping hidden entries. Whatever
+/// directories and patterns find below `exclude` (usually the output directory, which may be
+/// inside an input directory) is left out too.
+///
+/// Fails if a pattern is invalid or a path or pattern matches nothing.
+pub fn find_inputs(patterns: &[String], exclude: &Path) -> Result<Vec<BatchInput>, PosterError> {
+    let exclude = exclude.canonicalize().ok();
+    let exclude = exclude.as_deref();
+    let mut inputs: Vec<BatchInput> = Vec::new();
+
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        if path.is_dir() {
+            walk(path, path, exclude, &mut inputs)?;
+        } else if path.is_file() {
+            inputs.push(BatchInput {
+                file: path.to_path_buf(),
+                relative: PathBuf::from(path.file_name().unwrap_or(path.as_os_str())),
+            });
+        } else if is_glob(pattern) {
+            let base = glob_base(pattern);
+            let matches =
+                glob::glob(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let found = inputs.len();
+            for file in matches {
+                let file = file.map_err(io::Error::from)?;
+                if is_hidden(&file) || is_excluded(&file, exclude) {
+                    continue;
+                }
+                if file.is_dir() {
+                    walk(&base, &file, exclude, &mut inputs)?;
+                } else {
+                    inputs.push(BatchInput {
+                        relative: file.strip_prefix(&base).unwrap_or(&file).to_path_buf(),
+                        file,
+                    });
+                }
+            }
+            if inputs.len() == found {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} matches no files", pattern),
+                )
+                .into());
+            }
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} doesn't exist", pattern),
+            )
+            .into());
+        }
+    }
+
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    inputs.retain(|input| seen.insert(input.file.clone()));
+
+    return Ok(inputs);
+}
+
+/// The directories to watch for changes to the files `patterns` find, see [`find_inputs`].
+pub fn watch_roots(patterns: &[String]) -> Vec<WatchRoot> {
+    return patterns
+        .iter()
+        .map(|pattern| {
+            let path = Path::new(pattern);
+            if path.is_dir() {
+                return WatchRoot {
+                    path: path.to_path_buf(),
+                    recursive: true,
+                };
+            } else if path.is_file() {
+                return WatchRoot::parent_of(path);
+            }
+
+            let base = glob_base(pattern);
+            return WatchRoot {
+                path: if base.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    base
+                },
+                recursive: true,
+            };
+        })
+        .collect();
+}
+
+/// Where `relative` is written below `output`, without an extension. Only normal components
+/// are kept so a relative path can't lead out of the output directory.
+fn output_base(output: &Path, relative: &Path) -> PathBuf {
+    let relative: PathBuf = relative
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+
+    return output.join(relative.with_extension(""));
+}
+
+/// `base` with `extension` appended, which unlike [`Path::with_extension`] keeps any dots
+/// already in its name.
+fn with_extension(base: &Path, extension: &str) -> PathBuf {
+    let mut file = OsString::from(base.as_os_str());
+    file.push(".");
+    file.push(extension);
+
+    return PathBuf::from(file);
+}
+
+fn write_image(
+    image: &Img2d,
+    base: &Path,
+    options: &BatchOptions,
+) -> Result<Vec<PathBuf>, PosterError> {
+    let file = match options.format {
+        ImgFormat::JSON => with_extension(base, "2dj"),
+        ImgFormat::Binary => with_extension(base, "2db"),
+        ImgFormat::PNG => with_extension(base, "png"),
+    };
+    match options.format {
+        ImgFormat::JSON => write_2dj(&file, image, &options.encode)?,
+        ImgFormat::Binary => write_2db(&file, image, &options.encode)?,
+        ImgFormat::PNG => write_png(&file, image, &options.export)?,
+    }
+
+    return Ok(vec![file]);
+}
+
+fn write_image_array(
+    image: &Img2dArray,
+    base: &Path,
+    options: &BatchOptions,
+) -> Result<Vec<PathBuf>, PosterError> {
+    let file = match options.format {
+        ImgFormat::JSON => with_extension(base, "2dja"),
+        ImgFormat::Binary => with_extension(base, "2dba"),
+        ImgFormat::PNG => with_extension(base, "png"),
+    };
+    match (&options.format, &options.sheet) {
+        (ImgFormat::JSON, _) => write_2dja(&file, image, &options.encode)?,
+        (ImgFormat::Binary, _) => write_2dba(&file, image, &options.encode)?,
+        (ImgFormat::PNG, Some(sheet)) => write_contact_sheet(&file, image, &options.export, sheet)?,
+        (ImgFormat::PNG, None) => return write_page_pngs(&file, image, &options.export),
+    }
+
+    return Ok(vec![file]);
+}
+
+/// Converts `file` into the files starting with `base`, working out its format from its
+/// magic signature, its extension or its content.
+pub fn convert_file(file: &Path, base: &Path, options: &BatchOptions) -> Outcome {
+    let bytes = match fs::read(file) {
+        Ok(t) => t,
+        Err(e) => return Outcome::Failed(e.into()),
+    };
+
+    let by_extension = file
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(PosterFormat::from_extension);
+    let format = match detect_magic(&bytes).or(by_extension) {
+        Some(format) => Some(format),
+        None if is_image_path(file) => None,
+        None => match detect_format(&bytes) {
+            Some(format) => Some(format),
+            None => return Outcome::Skipped("not a poster or raster image".to_string()),
+        },
+    };
+
+    if let Some(parent) = base.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return Outcome::Failed(e.into());
+        }
+    }
+
+    let written = match format {
+        None => decode_image(Cursor::new(&bytes), &options.import)
+            .and_then(|image| write_image(&image, base, options)),
+        Some(PosterFormat::Json) => {
+            decode_2dj(&bytes[..]).and_then(|image| write_image(&image, base, options))
+        }
+        Some(PosterFormat::Binary) => decode_2db(&bytes[..], &options.decode)
+            .and_then(|image| write_image(&image, base, options)),
+        Some(PosterFormat::JsonArray) => {
+            decode_2dja(&bytes[..]).and_then(|image| write_image_array(&image, base, options))
+        }
+        Some(PosterFormat::BinaryArray) => decode_2dba(&bytes[..], &options.decode)
+            .and_then(|image| write_image_array(&image, base, options)),
+    };
+
+    return match written {
+        Ok(files) => Outcome::Converted(files),
+        Err(e) => Outcome::Failed(e),
+    };
+}
+
+/// Whether `file` is converted into a PNG per page, written next to its base as `base_1.png`,
+/// `base_2.png`, ... Image arrays are told apart by their magic signature or extension.
+fn writes_pages(file: &Path, options: &BatchOptions) -> bool {
+    if options.format != ImgFormat::PNG || options.sheet.is_some() {
+        return false;
+    }
+
+    let mut magic = [0; 4];
+    let by_magic = File::open(file)
+        .and_then(|mut reader| reader.read_exact(&mut magic))
+        .ok()
+        .and_then(|_| detect_magic(&magic));
+    let by_extension = file
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(PosterFormat::from_extension);
+
+    return by_magic
+        .or(by_extension)
+        .is_some_and(|format| format.is_array());
+}
+
+/// The base of the image array whose page `base` could be, `castle` for `castle_2`.
+fn page_owner(base: &Path) -> Option<PathBuf> {
+    let name = base.file_name()?.to_str()?;
+    let (owner, page) = name.rsplit_once('_')?;
+    if owner.is_empty() || page.is_empty() || !page.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    return Some(base.with_file_name(owner));
+}
+
+/// Converts every input into `options.output` in parallel, carrying on past failures.
+///
+/// Results are in the order of `inputs`. Inputs that would be written to the same place as
+/// an earlier one are skipped, like `castle.png` next to `castle.2dj`, or `book_1.png` next
+/// to `book.2dba` when the pages of arrays are exported as PNGs.
+pub fn convert_all(inputs: &[BatchInput], options: &BatchOptions) -> Vec<BatchResult> {
+    return convert_matching(inputs, |_| true, options);
+}
+
+/// Like [`convert_all`] but only converts the inputs `only` accepts, for converting the
+/// files that changed while still skipping those an unchanged input takes the place of.
+pub fn convert_matching<F>(
+    inputs: &[BatchInput],
+    only: F,
+    options: &BatchOptions,
+) -> Vec<BatchResult>
+where
+    F: Fn(&BatchInput) -> bool + Sync,
+{
+    let bases: Vec<PathBuf> = inputs
+        .iter()
+        .map(|input| output_base(&options.output, &input.relative))
+        .collect();
+    let pages: Vec<bool> = inputs
+        .par_iter()
+        .map(|input| writes_pages(&input.file, options))
+        .collect();
+
+    // The first input with each base, the first writing pages next to each base and the
+    // first whose base looks like a page of each base
+    let mut first: HashMap<&Path, usize> = HashMap::new();
+    let mut first_pages: HashMap<&Path, usize> = HashMap::new();
+    let mut first_page_like: HashMap<PathBuf, usize> = HashMap::new();
+    for (index, base) in bases.iter().enumerate() {
+        first.entry(base).or_insert(index);
+        if pages[index] {
+            first_pages.entry(base).or_insert(index);
+        }
+        if let Some(owner) = page_owner(base) {
+            first_page_like.entry(owner).or_insert(index);
+        }
+    }
+    let earlier = |index: usize| -> Option<usize> {
+        let base = bases[index].as_path();
+        let owner = page_owner(base);
+        return [
+            first.get(base),
+            owner.as_deref().and_then(|owner| first_pages.get(owner)),
+            first_page_like.get(base).filter(|_| pages[index]),
+        ]
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|&other| other < index)
+        .min();
+    };
+
+    return inputs
+        .par_iter()
+        .zip(bases.par_iter())
+        .enumerate()
+        .filter(|(_, (input, _))| only(input))
+        .map(|(index, (input, base))| {
+            let outcome = match earlier(index) {
+                Some(earlier) => Outcome::Skipped(format!(
+                    "would overwrite the output of {}",
+                    inputs[earlier].file.display()
+                )),
+                None => convert_file(&input.file, base, options),
+            };
+            BatchResult {
+                input: input.file.clone(),
+                outcome,
+            }
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{encode_png, render_image};
+    use crate::poster::{encode_2dba, encode_2dj};
+    use crate::testing::{book, page, TempDir};
+
+    fn image_json() -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2dj(&page("castle", 1), &mut bytes, &EncodeOptions::default()).unwrap();
+
+        return bytes;
+    }
+
+    fn array_binary() -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2dba(&book(), &mut bytes, &EncodeOptions::default()).unwrap();
+
+        return bytes;
+    }
+
+    fn raster() -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let rendered = render_image(&page("castle", 1), &ExportOptions::default()).unwrap();
+        encode_png(&rendered, &mut bytes).unwrap();
+
+        return bytes;
+    }
+
+    fn options(output: PathBuf, format: ImgFormat) -> BatchOptions {
+        return BatchOptions {
+            output,
+            format,
+            decode: DecodeOptions::default(),
+            import: ImportOptions::default(),
+            encode: EncodeOptions::default(),
+            export: ExportOptions::default(),
+            sheet: None,
+        };
+    }
+
+    fn pattern(path: &Path) -> String {
+        return path.to_string_lossy().into_owned();
+    }
+
+    fn relatives(inputs: &[BatchInput]) -> Vec<String> {
+        return inputs
+            .iter()
+            .map(|input| input.relative.to_string_lossy().replace('\\', "/"))
+            .collect();
+    }
+
+    fn outcomes(results: &[BatchResult]) -> Vec<String> {
+        return results
+            .iter()
+            .map(|result| match &result.outcome {
+                Outcome::Converted(files) => format!("converted {}", files.len()),
+                Outcome::Skipped(reason) => format!("skipped: {}", reason),
+                Outcome::Failed(e) => format!("failed: {}", e),
+            })
+            .collect();
+    }
+
+    #[test]
+    fn finds_files_below_directories() {
+        let dir = TempDir::new();
+        dir.write("art/castle.2dj", &image_json());
+        dir.write("art/sub/tower.2dj", &image_json());
+        dir.write("art/.hidden.2dj", &image_json());
+        dir.write("art/out/castle.2db", b"");
+
+        let inputs = find_inputs(&[pattern(&dir.join("art"))], &dir.join("art/out")).unwrap();
+        assert_eq!(relatives(&inputs), vec!["castle.2dj", "sub/tower.2dj"]);
+
+        // Files are relative to their own directory, and only found once
+        let patterns = [
+            pattern(&dir.join("art/castle.2dj")),
+            pattern(&dir.join("art")),
+        ];
+        let inputs = find_inputs(&patterns, &dir.join("art/out")).unwrap();
+        assert_eq!(relatives(&inputs), vec!["castle.2dj", "sub/tower.2dj"]);
+    }
+
+    #[test]
+    fn finds_files_through_patterns() {
+        let dir = TempDir::new();
+        dir.write("art/castle.2dj", &image_json());
+        dir.write("art/sub/tower.2dj", &image_json());
+        dir.write("art/notes.txt", b"");
+        dir.write("art/out/castle.2db", b"");
+
+        let inputs = find_inputs(&[pattern(&dir.join("art/**/*.2dj"))], &dir.join("art/out"));
+        assert_eq!(
+            relatives(&inputs.unwrap()),
+            vec!["castle.2dj", "sub/tower.2dj"]
+        );
+
+        // Directories matched are walked, but the output isn't picked up either way
+        let inputs = find_inputs(&[pattern(&dir.join("art/**/*"))], &dir.join("art/out"));
+        assert_eq!(
+            relatives(&inputs.unwrap()),
+            vec!["castle.2dj", "notes.txt", "sub/tower.2dj"]
+        );
+        let inputs = find_inputs(&[pattern(&dir.join("art/*"))], &dir.join("art/out"));
+        assert_eq!(
+            relatives(&inputs.unwrap()),
+            vec!["castle.2dj", "notes.txt", "sub/tower.2dj"]
+        );
+    }
+
+    #[test]
+    fn fails_for_inputs_that_find_nothing() {
+        let dir = TempDir::new();
+        dir.write("art/castle.2dj", &image_json());
+
+        for input in ["missing", "art/*.png", "art/out/*"] {
+            assert!(
+                find_inputs(&[pattern(&dir.join(input))], &dir.join("art/out")).is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn mirrors_the_input_tree() {
+        let dir = TempDir::new();
+        dir.write("art/castle.2dj", &image_json());
+        dir.write("art/sub/lore.v2.2dba", &array_binary());
+        dir.write("art/sub/photo.png", &raster());
+        dir.write("art/notes.txt", b"not a poster");
+        let output = dir.join("out");
+
+        let inputs = find_inputs(&[pattern(&dir.join("art"))], &output).unwrap();
+        let results = convert_all(&inputs, &options(output.clone(), ImgFormat::Binary));
+        assert_eq!(
+            outcomes(&results),
+            vec![
+                "converted 1",
+                "skipped: not a poster or raster image",
+                "converted 1",
+                "converted 1",
+            ]
+        );
+
+        let castle = fs::read(output.join("castle.2db")).unwrap();
+        let castle = decode_2db(castle.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(castle.label.as_deref(), Some("castle"));
+        let lore = fs::read(output.join("sub/lore.v2.2dba")).unwrap();
+        let lore = decode_2dba(lore.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(lore.pages.len(), 3);
+        assert!(output.join("sub/photo.2db").is_file());
+    }
+
+    #[test]
+    fn skips_inputs_writing_the_same_files() {
+        let dir = TempDir::new();
+        dir.write("art/castle.2dj", &image_json());
+        dir.write("art/castle.png", &raster());
+        let output = dir.join("out");
+
+        let inputs = find_inputs(&[pattern(&dir.join("art"))], &output).unwrap();
+        let results = convert_all(&inputs, &options(output, ImgFormat::Binary));
+        assert_eq!(
+            outcomes(&results),
+            vec![
+                "converted 1".to_string(),
+                format!(
+                    "skipped: would overwrite the output of {}",
+                    dir.join("art/castle.2dj").display()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_inputs_writing_over_pages() {
+        let dir = TempDir::new();
+        dir.write("art/book.2dba", &array_binary());
+        dir.write("art/book_2.png", &raster());
+        dir.write("art/book_x.png", &raster());
+        dir.write("art/castle_1.png", &raster());
+        dir.write("art/castle.2dj", &image_json());
+        let output = dir.join("out");
+        let inputs = find_inputs(&[pattern(&dir.join("art"))], &output).unwrap();
+        assert_eq!(
+            relatives(&inputs),
+            vec![
+                "book.2dba",
+                "book_2.png",
+                "book_x.png",
+                "castle.2dj",
+                "castle_1.png"
+            ]
+        );
+
+        let results = convert_all(&inputs, &options(output.clone(), ImgFormat::PNG));
+        assert_eq!(
+            outcomes(&results),
+            vec![
+                "converted 3".to_string(),
+                format!(
+                    "skipped: would overwrite the output of {}",
+                    dir.join("art/book.2dba").display()
+                ),
+                "converted 1".to_string(),
+                "converted 1".to_string(),
+                "converted 1".to_string(),
+            ]
+        );
+
+        // A contact sheet is a single file
+        let sheet = BatchOptions {
+            sheet: Some(ContactSheetOptions::default()),
+            ..options(output, ImgFormat::PNG)
+        };
+        let results = convert_all(&inputs, &sheet);
+        assert!(outcomes(&results)
+            .iter()
+            .all(|outcome| outcome.starts_with("converted")));
+    }
+}
@@ -0,0 +1,156 @@
+//! The 8-bit ComputerCraft charset used by labels, tooltips and titles in the binary format.
+//!
+//! - `0x00`-`0x7F` are ASCII, control characters included.
+//! - `0x80`-`0x9F` are the 2x3 "teletext" drawing characters. The low five bits of the byte
+//!   light up the top left, top right, middle left, middle right and bottom left cells, the
+//!   bottom right cell is always off. They map to the Unicode block sextants (U+1FB00 onwards)
+//!   and U+258C for the left half block. `0x80` has every cell off and, lacking an empty
+//!   sextant, maps to U+0080.
+//! - `0xA0`-`0xFF` are ISO-8859-1.
+
+use crate::error::PosterError;
+
+const SEXTANT_BASE: u32 = 0x1FB00;
+const LEFT_HALF_BLOCK: char = '\u{258C}';
+/// Drawing character bits of the left half block, which Unicode doesn't have a sextant for.
+const LEFT_HALF_BITS: u8 = 0b10101;
+
+/// What to do with characters the charset has no byte for when encoding.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Unrepresentable {
+    /// Fail with [`PosterError::Charset`].
+    Error,
+    /// Write this byte instead.
+    Replace(u8),
+}
+
+pub fn decode_byte(byte: u8) -> char {
+    if !(0x81..=0x9F).contains(&byte) {
+        // ASCII, ISO-8859-1 and the empty drawing character all map to the same code point.
+        return byte as char;
+    }
+
+    let bits = byte & 0x1F;
+    let code_point = match bits {
+        LEFT_HALF_BITS => return LEFT_HALF_BLOCK,
+        1..=20 => SEXTANT_BASE + bits as u32 - 1,
+        _ => SEXTANT_BASE + bits as u32 - 2,
+    };
+
+    return char::from_u32(code_point).expect("sextants are valid code points");
+}
+
+/// The byte for `character`, if the charset has one.
+///
+/// The C1 control characters U+0081 to U+009F are accepted as their own byte too, which is
+/// how older versions of this tool decoded the drawing characters.
+pub fn encode_char(character: char) -> Option<u8> {
+    let code_point = character as u32;
+    if code_point <= 0xFF {
+        return Some(code_point as u8);
+    }
+    if character == LEFT_HALF_BLOCK {
+        return Some(0x80 | LEFT_HALF_BITS);
+    }
+
+    let bits = match code_point.checked_sub(SEXTANT_BASE)? {
+        offset @ 0..=19 => offset + 1,
+        offset @ 20..=29 => offset + 2,
+        _ => return None,
+    };
+
+    return Some(0x80 | bits as u8);
+}
+
+pub fn decode(bytes: &[u8]) -> String {
+    return bytes.iter().map(|&byte| decode_byte(byte)).collect();
+}
+
+/// Encodes `string`, `offset` is where it starts in the output and only used for errors.
+pub fn encode(
+    string: &str,
+    field: &'static str,
+    offset: usize,
+    unrepresentable: Unrepresentable,
+) -> Result<Vec<u8>, PosterError> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(string.len());
+    for character in string.chars() {
+        match (encode_char(character), unrepresentable) {
+            (Some(byte), _) => bytes.push(byte),
+            (None, Unrepresentable::Replace(byte)) => bytes.push(byte),
+            (None, Unrepresentable::Error) => {
+                return Err(PosterError::Charset {
+                    field,
+                    offset: offset + bytes.len(),
+                    character,
+                });
+            }
+        }
+    }
+
+    return Ok(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_byte_round_trips() {
+        for byte in 0..=u8::MAX {
+            assert_eq!(encode_char(decode_byte(byte)), Some(byte), "{:#04x}", byte);
+        }
+    }
+
+    #[test]
+    fn maps_drawing_characters() {
+        assert_eq!(decode_byte(0x80), '\u{80}');
+        assert_eq!(decode_byte(0x81), '\u{1FB00}');
+        assert_eq!(decode_byte(0x94), '\u{1FB13}');
+        assert_eq!(decode_byte(0x95), LEFT_HALF_BLOCK);
+        assert_eq!(decode_byte(0x96), '\u{1FB14}');
+        assert_eq!(decode_byte(0x9F), '\u{1FB1D}');
+
+        assert_eq!(encode_char('\u{1FB00}'), Some(0x81));
+        assert_eq!(encode_char('\u{258C}'), Some(0x95));
+        assert_eq!(encode_char('\u{1FB1D}'), Some(0x9F));
+    }
+
+    #[test]
+    fn maps_latin_1() {
+        assert_eq!(decode_byte(b'A'), 'A');
+        assert_eq!(decode_byte(0xE9), 'é');
+        assert_eq!(encode_char('ÿ'), Some(0xFF));
+    }
+
+    #[test]
+    fn has_no_byte_for_other_characters() {
+        for character in ['€', '\u{1FB1E}', '\u{2590}', '🙂'] {
+            assert_eq!(encode_char(character), None, "{:?}", character);
+        }
+    }
+
+    #[test]
+    fn encodes_strings() {
+        let string = "café \u{1FB00}\u{258C}";
+        let bytes = encode(string, "label", 0, Unrepresentable::Error).unwrap();
+        assert_eq!(bytes, vec![b'c', b'a', b'f', 0xE9, b' ', 0x81, 0x95]);
+        assert_eq!(decode(&bytes), string);
+    }
+
+    #[test]
+    fn reports_unrepresentable_characters() {
+        let result = encode("ab€", "tooltip", 10, Unrepresentable::Error);
+        assert!(matches!(
+            result,
+            Err(PosterError::Charset {
+                field: "tooltip",
+                offset: 12,
+                character: '€',
+            })
+        ));
+
+        let bytes = encode("ab€", "tooltip", 10, Unrepresentable::Replace(b'?')).unwrap();
+        assert_eq!(bytes, b"ab?".to_vec());
+    }
+}
@@ -0,0 +1,234 @@
+//! Pixel compression for v2 binary files.
+//!
+//! A v2 file with the compressed flag set stores every pixels section as the scheme byte, the
+//! number of pixels (`u32`), the length of the compressed payload (`u32`) and the payload.
+//!
+//! - `0`, none: the pixels as is.
+//! - `1`, run-length: `count, value` byte pairs, `count` being 1 to 255 repetitions of `value`.
+//! - `2`, deflate: a raw DEFLATE stream (RFC 1951) without zlib or gzip framing.
+
+use crate::error::PosterError;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{Read, Write};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compression {
+    None,
+    RunLength,
+    Deflate,
+}
+
+impl Compression {
+    /// Parses the name of a scheme as given on the command line.
+    pub fn from_name(name: &str) -> Option<Compression> {
+        return match name.to_ascii_lowercase().as_str() {
+            "none" => Some(Compression::None),
+            "rle" | "run-length" => Some(Compression::RunLength),
+            "deflate" => Some(Compression::Deflate),
+            _ => None,
+        };
+    }
+
+    pub fn from_id(id: u8) -> Option<Compression> {
+        return match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::RunLength),
+            2 => Some(Compression::Deflate),
+            _ => None,
+        };
+    }
+
+    pub fn id(&self) -> u8 {
+        return match self {
+            Compression::None => 0,
+            Compression::RunLength => 1,
+            Compression::Deflate => 2,
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Compression::None => "none",
+            Compression::RunLength => "rle",
+            Compression::Deflate => "deflate",
+        };
+    }
+}
+
+pub fn compress(pixels: &[u8], compression: Compression) -> Vec<u8> {
+    return match compression {
+        Compression::None => pixels.to_vec(),
+        Compression::RunLength => {
+            let mut payload: Vec<u8> = Vec::new();
+            for run in pixels.chunk_by(|a, b| a == b) {
+                for part in run.chunks(u8::MAX as usize) {
+                    payload.push(part.len() as u8);
+                    payload.push(part[0]);
+                }
+            }
+
+            payload
+        }
+        Compression::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder
+                .write_all(pixels)
+                .expect("writing to a Vec can't fail");
+
+            encoder.finish().expect("writing to a Vec can't fail")
+        }
+    };
+}
+
+/// Decompresses `payload` into exactly `count` pixels. `offset` is where the payload starts
+/// in the file and is only used for errors.
+pub fn decompress(
+    payload: &[u8],
+    count: usize,
+    compression: Compression,
+    offset: usize,
+) -> Result<Vec<u8>, PosterError> {
+    let corrupt = || PosterError::CorruptPixels {
+        offset,
+        compression,
+    };
+
+    let pixels = match compression {
+        Compression::None => payload.to_vec(),
+        Compression::RunLength => {
+            if !payload.len().is_multiple_of(2) {
+                return Err(corrupt());
+            }
+
+            let mut pixels: Vec<u8> = Vec::new();
+            for pair in payload.chunks_exact(2) {
+                let (run, value) = (pair[0] as usize, pair[1]);
+                if run == 0 || pixels.len() + run > count {
+                    return Err(corrupt());
+                }
+                pixels.resize(pixels.len() + run, value);
+            }
+
+            pixels
+        }
+        Compression::Deflate => {
+            // Read one pixel too many so a stream that decodes to more than `count` is caught
+            let mut pixels: Vec<u8> = Vec::new();
+            DeflateDecoder::new(payload)
+                .take(count as u64 + 1)
+                .read_to_end(&mut pixels)
+                .map_err(|_| corrupt())?;
+
+            pixels
+        }
+    };
+
+    if pixels.len() != count {
+        return Err(corrupt());
+    }
+
+    return Ok(pixels);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs of every length around the 255 pixel run limit, plus noise.
+    fn sample_pixels() -> Vec<u8> {
+        let mut pixels: Vec<u8> = Vec::new();
+        for (value, run) in [(0, 1), (1, 254), (2, 255), (3, 256), (4, 600), (0, 2)] {
+            pixels.extend(std::iter::repeat_n(value, run));
+        }
+        pixels.extend((0..200).map(|i| (i * 7 % 13) as u8));
+
+        return pixels;
+    }
+
+    #[test]
+    fn round_trips() {
+        let pixels = sample_pixels();
+        for compression in [
+            Compression::None,
+            Compression::RunLength,
+            Compression::Deflate,
+        ] {
+            let payload = compress(&pixels, compression);
+            let decompressed = decompress(&payload, pixels.len(), compression, 0).unwrap();
+            assert_eq!(decompressed, pixels, "{}", compression.name());
+        }
+    }
+
+    #[test]
+    fn round_trips_nothing() {
+        for compression in [Compression::RunLength, Compression::Deflate] {
+            let payload = compress(&[], compression);
+            assert!(decompress(&payload, 0, compression, 0).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn splits_long_runs() {
+        assert_eq!(
+            compress(&[9; 300], Compression::RunLength),
+            vec![255, 9, 45, 9]
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_pixel_counts() {
+        let pixels = sample_pixels();
+        for compression in [
+            Compression::None,
+            Compression::RunLength,
+            Compression::Deflate,
+        ] {
+            let payload = compress(&pixels, compression);
+            for count in [pixels.len() - 1, pixels.len() + 1] {
+                let result = decompress(&payload, count, compression, 42);
+                assert!(
+                    matches!(result, Err(PosterError::CorruptPixels { offset: 42, .. })),
+                    "{} with {} pixels",
+                    compression.name(),
+                    count
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_run_length() {
+        // Odd length, and a run of zero
+        for payload in [&[3, 1, 2][..], &[0, 1, 3, 1][..]] {
+            assert!(matches!(
+                decompress(payload, 3, Compression::RunLength, 0),
+                Err(PosterError::CorruptPixels { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_deflate() {
+        assert!(matches!(
+            decompress(&[0xFF, 0xFF, 0xFF], 3, Compression::Deflate, 0),
+            Err(PosterError::CorruptPixels { .. })
+        ));
+    }
+
+    #[test]
+    fn ids_and_names_round_trip() {
+        for compression in [
+            Compression::None,
+            Compression::RunLength,
+            Compression::Deflate,
+        ] {
+            assert_eq!(Compression::from_id(compression.id()), Some(compression));
+            assert_eq!(
+                Compression::from_name(compression.name()),
+                Some(compression)
+            );
+        }
+        assert_eq!(Compression::from_id(3), None);
+    }
+}
@@ -0,0 +1,212 @@
+//! Bounds checked reading of the little-endian fields of the binary format.
+
+use crate::error::PosterError;
+use std::io;
+use std::io::Read;
+
+/// Reads fields from a byte slice, failing with [`PosterError::Truncated`] when a field runs
+/// past the end of it. Every offset reported is counted from the start of the file.
+pub(crate) struct ByteCursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Where `bytes` starts in the file.
+    base_offset: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+    pub fn new(bytes: &'a [u8], base_offset: usize) -> Self {
+        return ByteCursor {
+            bytes,
+            position: 0,
+            base_offset,
+        };
+    }
+
+    /// Offset of the next field in the file.
+    pub fn offset(&self) -> usize {
+        return self.base_offset + self.position;
+    }
+
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        return self.position;
+    }
+
+    pub fn remaining(&self) -> usize {
+        return self.bytes.len() - self.position;
+    }
+
+    pub fn bytes(&mut self, length: usize, field: &'static str) -> Result<&'a [u8], PosterError> {
+        if length > self.remaining() {
+            return Err(PosterError::Truncated {
+                field,
+                offset: self.offset(),
+                needed: length,
+                available: self.remaining(),
+            });
+        }
+
+        let bytes = &self.bytes[self.position..self.position + length];
+        self.position += length;
+
+        return Ok(bytes);
+    }
+
+    pub fn u8(&mut self, field: &'static str) -> Result<u8, PosterError> {
+        return Ok(self.bytes(1, field)?[0]);
+    }
+
+    pub fn u16(&mut self, field: &'static str) -> Result<u16, PosterError> {
+        let bytes = self.bytes(2, field)?;
+
+        return Ok(u16::from_le_bytes([bytes[0], bytes[1]]));
+    }
+
+    pub fn u32(&mut self, field: &'static str) -> Result<u32, PosterError> {
+        let bytes = self.bytes(4, field)?;
+
+        return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+}
+
+/// Reads fields from a stream, the streaming counterpart of [`ByteCursor`].
+pub(crate) struct ReadCursor<R: Read> {
+    reader: R,
+    offset: usize,
+}
+
+impl<R: Read> ReadCursor<R> {
+    /// `offset` is where the next byte of `reader` is in the file.
+    pub fn new(reader: R, offset: usize) -> Self {
+        return ReadCursor { reader, offset };
+    }
+
+    /// Offset of the next field in the file.
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+
+    pub fn get_ref(&self) -> &R {
+        return &self.reader;
+    }
+
+    /// Reads up to `length` bytes, fewer only at the end of the stream.
+    pub fn bytes_up_to(&mut self, length: usize) -> Result<Vec<u8>, PosterError> {
+        let mut buffer: Vec<u8> = Vec::new();
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut buffer)?;
+        self.offset += buffer.len();
+
+        return Ok(buffer);
+    }
+
+    /// Reads and discards the rest of the stream, returning how many bytes it had.
+    pub fn skip_to_end(&mut self) -> Result<usize, PosterError> {
+        let skipped = io::copy(&mut self.reader, &mut io::sink())? as usize;
+        self.offset += skipped;
+
+        return Ok(skipped);
+    }
+
+    pub fn bytes(&mut self, length: usize, field: &'static str) -> Result<Vec<u8>, PosterError> {
+        let offset = self.offset;
+        let buffer = self.bytes_up_to(length)?;
+        if buffer.len() < length {
+            return Err(PosterError::Truncated {
+                field,
+                offset,
+                needed: length,
+                available: buffer.len(),
+            });
+        }
+
+        return Ok(buffer);
+    }
+
+    pub fn u16(&mut self, field: &'static str) -> Result<u16, PosterError> {
+        let bytes = self.bytes(2, field)?;
+
+        return Ok(u16::from_le_bytes([bytes[0], bytes[1]]));
+    }
+
+    pub fn u32(&mut self, field: &'static str) -> Result<u32, PosterError> {
+        let bytes = self.bytes(4, field)?;
+
+        return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_up_to_the_exact_end() {
+        let mut cursor = ByteCursor::new(&[1, 0, 2, 0, 0, 0, 3], 100);
+        assert_eq!(cursor.u16("a").unwrap(), 1);
+        assert_eq!(cursor.u32("b").unwrap(), 2);
+        assert_eq!(cursor.u8("c").unwrap(), 3);
+        assert_eq!(cursor.remaining(), 0);
+        assert_eq!(cursor.offset(), 107);
+        assert!(cursor.bytes(0, "d").unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_truncation_at_the_field_offset() {
+        let mut cursor = ByteCursor::new(&[1, 0, 2, 0, 0], 100);
+        assert_eq!(cursor.u16("a").unwrap(), 1);
+        let result = cursor.u32("b");
+        assert!(matches!(
+            result,
+            Err(PosterError::Truncated {
+                field: "b",
+                offset: 102,
+                needed: 4,
+                available: 3,
+            })
+        ));
+        // A failed read doesn't consume anything
+        assert_eq!(cursor.offset(), 102);
+    }
+
+    #[test]
+    fn reports_truncation_of_empty_input() {
+        let result = ByteCursor::new(&[], 7).u8("a");
+        assert!(matches!(
+            result,
+            Err(PosterError::Truncated {
+                offset: 7,
+                needed: 1,
+                available: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn stream_reports_truncation_at_the_field_offset() {
+        let mut cursor = ReadCursor::new(&[1, 0, 2, 0, 0][..], 100);
+        assert_eq!(cursor.u16("a").unwrap(), 1);
+        let result = cursor.u32("b");
+        assert!(matches!(
+            result,
+            Err(PosterError::Truncated {
+                field: "b",
+                offset: 102,
+                needed: 4,
+                available: 3,
+            })
+        ));
+    }
+
+    #[test]
+    fn stream_reads_up_to_the_exact_end() {
+        let mut cursor = ReadCursor::new(&[1, 0, 2, 0, 0, 0][..], 0);
+        assert_eq!(cursor.u16("a").unwrap(), 1);
+        assert_eq!(cursor.u32("b").unwrap(), 2);
+        assert_eq!(cursor.offset(), 6);
+        assert!(cursor.bytes_up_to(4).unwrap().is_empty());
+        assert_eq!(cursor.skip_to_end().unwrap(), 0);
+    }
+}
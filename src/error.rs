@@ -0,0 +1,306 @@
+use crate::compression::Compression;
+use crate::poster::PosterFormat;
+use crate::validate::Violation;
+use std::fmt::Formatter;
+use std::{error, fmt, io};
+
+/// Every way reading or writing a poster can fail.
+///
+/// Binary variants carry the name of the field being processed and the byte offset
+/// (from the start of the file or buffer) at which the problem was found.
+#[derive(Debug)]
+pub enum PosterError {
+    /// The buffer ended before `field` could be read completely.
+    Truncated {
+        field: &'static str,
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// `character` in `field` can't be represented in the binary charset.
+    ///
+    /// Only writing can fail this way. Every byte read back maps to a character of the
+    /// charset, so binary strings can't hold bad UTF-8, and bad UTF-8 in JSON is a
+    /// [`PosterError::Json`].
+    Charset {
+        field: &'static str,
+        offset: usize,
+        character: char,
+    },
+    /// An encoded string is longer than its length prefix can describe.
+    StringTooLong {
+        field: &'static str,
+        length: usize,
+        max: usize,
+    },
+    /// The palette has more entries than the binary format can store.
+    PaletteOverflow {
+        length: usize,
+        max: usize,
+    },
+    /// A pixel references a palette entry that doesn't exist.
+    PixelOutOfRange {
+        index: usize,
+        value: u8,
+        palette_length: usize,
+    },
+    /// An image array has more pages than its width and height describe as a grid.
+    GridMismatch {
+        columns: u32,
+        rows: u32,
+        pages: usize,
+    },
+    /// The image failed validation, every problem found is listed.
+    Invalid(Vec<Violation>),
+    /// A v2 binary file has a version this reader doesn't know.
+    UnsupportedVersion {
+        offset: usize,
+        version: u8,
+    },
+    /// A v2 binary file uses feature flags this reader doesn't know.
+    UnsupportedFlags {
+        offset: usize,
+        flags: u8,
+    },
+    /// The magic signature is that of a different binary format, e.g. a 2db read as a 2dba.
+    FormatMismatch {
+        expected: PosterFormat,
+        found: PosterFormat,
+    },
+    /// A pixels section uses a compression scheme this reader doesn't know.
+    UnsupportedCompression {
+        offset: usize,
+        id: u8,
+    },
+    /// A compressed pixels section doesn't decompress to the pixel count it declares.
+    CorruptPixels {
+        offset: usize,
+        compression: Compression,
+    },
+    /// A compressed pixels section at `offset` declares more pixels than the image has.
+    PixelCountMismatch {
+        offset: usize,
+        declared: usize,
+        expected: u64,
+    },
+    /// The CRC32 stored at `offset` doesn't match the bytes it covers.
+    ChecksumMismatch {
+        field: &'static str,
+        offset: usize,
+        stored: u32,
+        computed: u32,
+    },
+    /// `feature` was asked for in a binary format version that can't store it.
+    VersionTooOld {
+        feature: &'static str,
+    },
+    /// A 2dba page at `offset` declares more bytes than its image takes up.
+    PageLengthMismatch {
+        offset: usize,
+        declared: usize,
+        consumed: usize,
+    },
+    /// An image to render would be larger than an image can be.
+    ImageTooLarge {
+        width: u64,
+        height: u64,
+    },
+    /// Bytes were left over after the last field was read.
+    TrailingBytes {
+        offset: usize,
+        count: usize,
+    },
+    Json(serde_json::Error),
+    /// A raster image could not be decoded or encoded.
+    Image(image::ImageError),
+    Io(io::Error),
+}
+
+impl PosterError {
+    /// Stable name of the variant, for reporting errors to programs rather than people.
+    pub fn kind(&self) -> &'static str {
+        return match self {
+            PosterError::Truncated { .. } => "truncated",
+            PosterError::Charset { .. } => "charset",
+            PosterError::StringTooLong { .. } => "string_too_long",
+            PosterError::PaletteOverflow { .. } => "palette_overflow",
+            PosterError::PixelOutOfRange { .. } => "pixel_out_of_range",
+            PosterError::GridMismatch { .. } => "grid_mismatch",
+            PosterError::Invalid(_) => "invalid",
+            PosterError::UnsupportedVersion { .. } => "unsupported_version",
+            PosterError::UnsupportedFlags { .. } => "unsupported_flags",
+            PosterError::FormatMismatch { .. } => "format_mismatch",
+            PosterError::UnsupportedCompression { .. } => "unsupported_compression",
+            PosterError::CorruptPixels { .. } => "corrupt_pixels",
+            PosterError::PixelCountMismatch { .. } => "pixel_count_mismatch",
+            PosterError::ChecksumMismatch { .. } => "checksum_mismatch",
+            PosterError::VersionTooOld { .. } => "version_too_old",
+            PosterError::PageLengthMismatch { .. } => "page_length_mismatch",
+            PosterError::ImageTooLarge { .. } => "image_too_large",
+            PosterError::TrailingBytes { .. } => "trailing_bytes",
+            PosterError::Json(_) => "json",
+            PosterError::Image(_) => "image",
+            PosterError::Io(_) => "io",
+        };
+    }
+}
+
+impl fmt::Display for PosterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PosterError::Truncated {
+                field,
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "{} at byte {} is truncated (needed {} bytes, {} available)",
+                field, offset, needed, available
+            ),
+            PosterError::Charset {
+                field,
+                offset,
+                character,
+            } => write!(
+                f,
+                "{} contains unrepresentable character {:?} at byte {}",
+                field, character, offset
+            ),
+            PosterError::StringTooLong { field, length, max } => write!(
+                f,
+                "{} is {} bytes long, at most {} are allowed",
+                field, length, max
+            ),
+            PosterError::PaletteOverflow { length, max } => write!(
+                f,
+                "palette has {} entries, at most {} are allowed",
+                length, max
+            ),
+            PosterError::PixelOutOfRange {
+                index,
+                value,
+                palette_length,
+            } => write!(
+                f,
+                "pixel {} has value {} but the palette only has {} entries",
+                index, value, palette_length
+            ),
+            PosterError::GridMismatch {
+                columns,
+                rows,
+                pages,
+            } => write!(
+                f,
+                "{} pages don't fit a grid of {} columns and {} rows",
+                pages, columns, rows
+            ),
+            PosterError::Invalid(violations) => {
+                write!(f, "image is invalid")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { ":" } else { ";" }, violation)?;
+                }
+                Ok(())
+            }
+            PosterError::UnsupportedVersion { offset, version } => write!(
+                f,
+                "version at byte {} is {}, which is not supported",
+                offset, version
+            ),
+            PosterError::UnsupportedFlags { offset, flags } => write!(
+                f,
+                "flags at byte {} use unsupported feature flags {:#010b}",
+                offset, flags
+            ),
+            PosterError::FormatMismatch { expected, found } => write!(
+                f,
+                "expected a {} file but found a {} signature",
+                expected.extension(),
+                found.extension()
+            ),
+            PosterError::UnsupportedCompression { offset, id } => write!(
+                f,
+                "pixels at byte {} use unknown compression scheme {}",
+                offset, id
+            ),
+            PosterError::CorruptPixels {
+                offset,
+                compression,
+            } => write!(
+                f,
+                "pixels at byte {} are not valid {} data",
+                offset,
+                compression.name()
+            ),
+            PosterError::PixelCountMismatch {
+                offset,
+                declared,
+                expected,
+            } => write!(
+                f,
+                "pixels at byte {} declare {} pixels but width * height is {}",
+                offset, declared, expected
+            ),
+            PosterError::ChecksumMismatch {
+                field,
+                offset,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "{} at byte {} doesn't match (stored {:#010x}, computed {:#010x})",
+                field, offset, stored, computed
+            ),
+            PosterError::VersionTooOld { feature } => {
+                write!(f, "{} needs version 2 of the binary format", feature)
+            }
+            PosterError::PageLengthMismatch {
+                offset,
+                declared,
+                consumed,
+            } => write!(
+                f,
+                "page at byte {} is declared as {} bytes long but its image only takes up {}",
+                offset, declared, consumed
+            ),
+            PosterError::ImageTooLarge { width, height } => {
+                write!(f, "{}x{} pixels is too large for an image", width, height)
+            }
+            PosterError::TrailingBytes { offset, count } => {
+                write!(f, "{} trailing bytes at byte {}", count, offset)
+            }
+            PosterError::Json(e) => write!(f, "JSON error: {}", e),
+            PosterError::Image(e) => write!(f, "image error: {}", e),
+            PosterError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl error::Error for PosterError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PosterError::Json(e) => Some(e),
+            PosterError::Image(e) => Some(e),
+            PosterError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for PosterError {
+    fn from(e: serde_json::Error) -> Self {
+        PosterError::Json(e)
+    }
+}
+
+impl From<image::ImageError> for PosterError {
+    fn from(e: image::ImageError) -> Self {
+        PosterError::Image(e)
+    }
+}
+
+impl From<io::Error> for PosterError {
+    fn from(e: io::Error) -> Self {
+        PosterError::Io(e)
+    }
+}
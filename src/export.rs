@@ -0,0 +1,372 @@
+use crate::error::PosterError;
+use crate::poster::{unpack_rgb, Img2d, Img2dArray};
+use crate::validate::{check_canvas_size, check_pixel_count};
+use image::codecs::png::PngEncoder;
+use image::{Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// 3x5 bitmaps of the digits 0-9 used for page numbers, one bit per pixel, row by row.
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+pub struct ExportOptions {
+    /// Packed RGB colour drawn for pixels that index past the end of the palette.
+    pub error_color: u32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        return ExportOptions {
+            error_color: 0xFF00FF,
+        };
+    }
+}
+
+pub struct ContactSheetOptions {
+    /// Pages per row, a roughly square grid is used if not set.
+    pub columns: Option<u32>,
+    /// Gap in pixels between pages and around the edge of the sheet.
+    pub spacing: u32,
+    /// Packed RGB colour behind and between the pages.
+    pub background: u32,
+    /// Draw 1-based page numbers in the corner of every page.
+    pub page_numbers: bool,
+}
+
+impl Default for ContactSheetOptions {
+    fn default() -> Self {
+        return ContactSheetOptions {
+            columns: None,
+            spacing: 4,
+            background: 0x202020,
+            page_numbers: false,
+        };
+    }
+}
+
+fn rgba(color: u32) -> Rgba<u8> {
+    let [r, g, b] = unpack_rgb(color);
+
+    return Rgba([r, g, b, 255]);
+}
+
+/// Renders an image [`check_pixel_count`] accepted.
+fn render_checked(image: &Img2d, options: &ExportOptions) -> RgbaImage {
+    let error = rgba(options.error_color);
+
+    return RgbaImage::from_fn(image.width, image.height, |x, y| {
+        let index = y as usize * image.width as usize + x as usize;
+        return image
+            .palette
+            .get(image.pixels[index] as usize)
+            .map_or(error, |&color| rgba(color));
+    });
+}
+
+/// Expands the pixels of `image` through its palette into an RGBA image.
+///
+/// Pixels without a palette entry are drawn in the error colour. Fails if the image doesn't
+/// have exactly `width * height` pixels.
+pub fn render_image(image: &Img2d, options: &ExportOptions) -> Result<RgbaImage, PosterError> {
+    check_pixel_count(image, None)?;
+
+    return Ok(render_checked(image, options));
+}
+
+/// Renders every page of `image` on its own.
+pub fn render_pages(
+    image: &Img2dArray,
+    options: &ExportOptions,
+) -> Result<Vec<RgbaImage>, PosterError> {
+    return image
+        .pages
+        .iter()
+        .enumerate()
+        .map(|(index, page)| {
+            check_pixel_count(page, Some(index))?;
+            return Ok(render_checked(page, options));
+        })
+        .collect();
+}
+
+/// Lays every page of `image` out on a single sheet, row by row.
+///
+/// Every cell is as large as the largest page, so fails if pages of very different sizes
+/// would make the sheet too large, see [`check_canvas_size`].
+pub fn render_contact_sheet(
+    image: &Img2dArray,
+    options: &ExportOptions,
+    sheet: &ContactSheetOptions,
+) -> Result<RgbaImage, PosterError> {
+    let count = image.pages.len() as u32;
+    let columns = sheet
+        .columns
+        .unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+        .clamp(1, count.max(1));
+    let rows = count.div_ceil(columns).max(1);
+
+    let cell_width = image.pages.iter().map(|page| page.width).max().unwrap_or(0);
+    let cell_height = image
+        .pages
+        .iter()
+        .map(|page| page.height)
+        .max()
+        .unwrap_or(0);
+    let scale = (cell_width.min(cell_height) / 64).max(1);
+
+    for (index, page) in image.pages.iter().enumerate() {
+        check_pixel_count(page, Some(index))?;
+    }
+
+    let side = |cells: u32, cell: u32| -> u64 {
+        return (cells as u64)
+            .saturating_mul(cell as u64 + sheet.spacing as u64)
+            .saturating_add(sheet.spacing as u64);
+    };
+    let (width, height) = (side(columns, cell_width), side(rows, cell_height));
+    check_canvas_size(width, height, &image.pages)?;
+    let mut canvas = RgbaImage::from_pixel(width as u32, height as u32, rgba(sheet.background));
+
+    for (index, page) in image.pages.iter().enumerate() {
+        let x = sheet.spacing + (index as u32 % columns) * (cell_width + sheet.spacing);
+        let y = sheet.spacing + (index as u32 / columns) * (cell_height + sheet.spacing);
+
+        image::imageops::replace(
+            &mut canvas,
+            &render_checked(page, options),
+            x as i64,
+            y as i64,
+        );
+        if sheet.page_numbers {
+            draw_number(&mut canvas, x, y, index + 1, scale);
+        }
+    }
+
+    return Ok(canvas);
+}
+
+/// Draws `number` in white on a black box with its top left corner at `x`, `y`.
+fn draw_number(canvas: &mut RgbaImage, x: u32, y: u32, number: usize, scale: u32) {
+    let digits: Vec<usize> = number
+        .to_string()
+        .bytes()
+        .map(|digit| (digit - b'0') as usize)
+        .collect();
+    let box_width = (digits.len() as u32 * 4 + 1) * scale;
+    let box_height = 7 * scale;
+
+    let mut put = |px: u32, py: u32, color: Rgba<u8>| {
+        if x + px < canvas.width() && y + py < canvas.height() {
+            canvas.put_pixel(x + px, y + py, color);
+        }
+    };
+
+    for py in 0..box_height {
+        for px in 0..box_width {
+            put(px, py, Rgba([0, 0, 0, 255]));
+        }
+    }
+
+    for (position, &digit) in digits.iter().enumerate() {
+        for bit in 0..15 {
+            if DIGITS[digit] & (1 << (14 - bit)) == 0 {
+                continue;
+            }
+
+            let px = (position as u32 * 4 + 1 + bit % 3) * scale;
+            let py = (1 + bit / 3) * scale;
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    put(px + dx, py + dy, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+    }
+}
+
+/// Encodes a rendered image as PNG.
+pub fn encode_png<W: Write>(image: &RgbaImage, writer: W) -> Result<(), PosterError> {
+    image.write_with_encoder(PngEncoder::new(writer))?;
+
+    return Ok(());
+}
+
+fn write_rendered_png(file: &Path, image: &RgbaImage) -> Result<(), PosterError> {
+    let mut writer = BufWriter::new(File::create(file)?);
+    encode_png(image, &mut writer)?;
+    writer.flush()?;
+
+    return Ok(());
+}
+
+pub fn write_png(file: &Path, image: &Img2d, options: &ExportOptions) -> Result<(), PosterError> {
+    return write_rendered_png(file, &render_image(image, options)?);
+}
+
+/// Writes one PNG per page next to `file`, numbered from 1 (`book.png` becomes
+/// `book_1.png`, `book_2.png`, ...). Returns the paths that were written.
+pub fn write_page_pngs(
+    file: &Path,
+    image: &Img2dArray,
+    options: &ExportOptions,
+) -> Result<Vec<PathBuf>, PosterError> {
+    let stem = file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut written: Vec<PathBuf> = Vec::new();
+    for (index, page) in render_pages(image, options)?.iter().enumerate() {
+        let page_file = file.with_file_name(format!("{}_{}.png", stem, index + 1));
+        write_rendered_png(&page_file, page)?;
+        written.push(page_file);
+    }
+
+    return Ok(written);
+}
+
+pub fn write_contact_sheet(
+    file: &Path,
+    image: &Img2dArray,
+    options: &ExportOptions,
+    sheet: &ContactSheetOptions,
+) -> Result<(), PosterError> {
+    return write_rendered_png(file, &render_contact_sheet(image, options, sheet)?);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{book, page, TempDir};
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const BACKGROUND: Rgba<u8> = Rgba([0x20, 0x20, 0x20, 255]);
+
+    #[test]
+    fn renders_pixels_through_the_palette() {
+        let mut image = page("p1", 1);
+        image.pixels[4] = 0;
+        image.pixels[5] = 7;
+
+        let rendered = render_image(&image, &ExportOptions::default()).unwrap();
+        assert_eq!(rendered.dimensions(), (3, 2));
+        assert_eq!(*rendered.get_pixel(0, 0), WHITE);
+        assert_eq!(*rendered.get_pixel(1, 1), BLACK);
+        assert_eq!(*rendered.get_pixel(2, 1), Rgba([0xFF, 0x00, 0xFF, 255]));
+    }
+
+    #[test]
+    fn rejects_images_with_the_wrong_pixel_count() {
+        let mut image = page("p1", 1);
+        image.pixels.pop();
+        assert!(matches!(
+            render_image(&image, &ExportOptions::default()),
+            Err(PosterError::Invalid(violations)) if violations[0].page.is_none()
+        ));
+
+        let mut array = book();
+        array.pages[2].height = 3;
+        assert!(matches!(
+            render_pages(&array, &ExportOptions::default()),
+            Err(PosterError::Invalid(violations)) if violations[0].page == Some(2)
+        ));
+    }
+
+    #[test]
+    fn lays_pages_out_row_by_row() {
+        let sheet = render_contact_sheet(
+            &book(),
+            &ExportOptions::default(),
+            &ContactSheetOptions::default(),
+        )
+        .unwrap();
+
+        // Two columns of 3x2 pages, 4 pixels apart and from the edge
+        assert_eq!(sheet.dimensions(), (18, 16));
+        assert_eq!(*sheet.get_pixel(0, 0), BACKGROUND);
+        assert_eq!(*sheet.get_pixel(4, 4), BLACK);
+        assert_eq!(*sheet.get_pixel(11, 5), WHITE);
+        assert_eq!(*sheet.get_pixel(6, 11), BLACK);
+        assert_eq!(*sheet.get_pixel(11, 10), BACKGROUND);
+
+        let options = ContactSheetOptions {
+            columns: Some(5),
+            spacing: 0,
+            ..Default::default()
+        };
+        let sheet = render_contact_sheet(&book(), &ExportOptions::default(), &options).unwrap();
+        assert_eq!(sheet.dimensions(), (9, 2));
+        assert_eq!(*sheet.get_pixel(3, 0), WHITE);
+        assert_eq!(*sheet.get_pixel(6, 0), BLACK);
+    }
+
+    #[test]
+    fn draws_page_numbers() {
+        let options = ContactSheetOptions {
+            page_numbers: true,
+            ..Default::default()
+        };
+        let sheet = render_contact_sheet(&book(), &ExportOptions::default(), &options).unwrap();
+
+        // The number box covers the corner of the white page, with the 2 drawn on it
+        assert_eq!(*sheet.get_pixel(11, 4), BLACK);
+        assert_eq!(*sheet.get_pixel(12, 5), WHITE);
+    }
+
+    #[test]
+    fn rejects_sheets_of_very_different_pages() {
+        let mut array = book();
+        array.pages = vec![page("wide", 0), page("tall", 0)];
+        (array.pages[0].width, array.pages[0].height) = (20000, 1);
+        (array.pages[1].width, array.pages[1].height) = (1, 20000);
+        for page in &mut array.pages {
+            page.pixels = vec![0; 20000];
+        }
+
+        // Every cell would be 20000 by 20000
+        let options = ContactSheetOptions {
+            columns: Some(2),
+            ..Default::default()
+        };
+        assert!(matches!(
+            render_contact_sheet(&array, &ExportOptions::default(), &options),
+            Err(PosterError::ImageTooLarge {
+                width: 40012,
+                height: 20008
+            })
+        ));
+    }
+
+    #[test]
+    fn writes_numbered_page_pngs() {
+        let dir = TempDir::new();
+        let written =
+            write_page_pngs(&dir.join("book.png"), &book(), &ExportOptions::default()).unwrap();
+        assert_eq!(
+            written,
+            vec![
+                dir.join("book_1.png"),
+                dir.join("book_2.png"),
+                dir.join("book_3.png")
+            ]
+        );
+
+        let second = image::open(&written[1]).unwrap().to_rgba8();
+        assert_eq!(second.dimensions(), (3, 2));
+        assert_eq!(*second.get_pixel(0, 0), WHITE);
+        assert!(!dir.join("book.png").exists());
+    }
+}
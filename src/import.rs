@@ -0,0 +1,799 @@
+use crate::error::PosterError;
+use crate::poster::{pack_rgb, parse_hex_color, unpack_rgb, Img2d, MAX_PALETTE_LENGTH};
+use image::{DynamicImage, ImageReader};
+use std::collections::HashMap;
+use std::io::{BufRead, Seek};
+use std::path::Path;
+
+/// How many refinement passes [`Quantizer::KMeans`] runs at most.
+const K_MEANS_ITERATIONS: usize = 16;
+
+/// Bits per channel the octree quantizer descends through.
+const OCTREE_DEPTH: usize = 8;
+
+/// The 16 default ComputerCraft terminal colours, `colors.white` through `colors.black`.
+pub const COMPUTERCRAFT_PALETTE: [u32; 16] = [
+    0xF0F0F0, 0xF2B233, 0xE57FD8, 0x99B2F2, 0xDEDE6C, 0x7FCC19, 0xF2B2CC, 0x4C4C4C, 0x999999,
+    0x4C99B2, 0xB266E5, 0x3366CC, 0x7F664C, 0x57A64E, 0xCC4C4C, 0x111111,
+];
+
+/// Error diffusion kernels as `(dx, dy, weight)`, with the divisor of the weights.
+const FLOYD_STEINBERG: (&[(i32, i32, f32)], f32) =
+    (&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0);
+const ATKINSON: (&[(i32, i32, f32)], f32) = (
+    &[
+        (1, 0, 1.0),
+        (2, 0, 1.0),
+        (-1, 1, 1.0),
+        (0, 1, 1.0),
+        (1, 1, 1.0),
+        (0, 2, 1.0),
+    ],
+    8.0,
+);
+const SIERRA: (&[(i32, i32, f32)], f32) = (
+    &[
+        (1, 0, 5.0),
+        (2, 0, 3.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 5.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+        (-1, 2, 2.0),
+        (0, 2, 3.0),
+        (1, 2, 2.0),
+    ],
+    32.0,
+);
+
+/// Algorithm used to reduce an image to a palette.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Quantizer {
+    /// Repeatedly splits the colour box with the widest channel at its median.
+    MedianCut,
+    /// Refines the median cut palette with k-means clustering, slower but closer.
+    KMeans,
+    /// Merges the least used branches of an RGB octree.
+    Octree,
+}
+
+impl Quantizer {
+    pub fn from_name(name: &str) -> Option<Quantizer> {
+        return match name.to_ascii_lowercase().as_str() {
+            "median-cut" | "mediancut" | "median" => Some(Quantizer::MedianCut),
+            "k-means" | "kmeans" => Some(Quantizer::KMeans),
+            "octree" => Some(Quantizer::Octree),
+            _ => None,
+        };
+    }
+}
+
+/// How pixels are mapped onto the palette.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Dither {
+    /// Plain nearest colour.
+    None,
+    FloydSteinberg,
+    Atkinson,
+    Sierra,
+    /// Ordered dithering with a 4x4 Bayer matrix.
+    Bayer4,
+    /// Ordered dithering with an 8x8 Bayer matrix.
+    Bayer8,
+}
+
+impl Dither {
+    pub fn from_name(name: &str) -> Option<Dither> {
+        return match name.to_ascii_lowercase().as_str() {
+            "none" => Some(Dither::None),
+            "floyd-steinberg" | "floydsteinberg" | "fs" => Some(Dither::FloydSteinberg),
+            "atkinson" => Some(Dither::Atkinson),
+            "sierra" => Some(Dither::Sierra),
+            "bayer4" | "bayer-4" => Some(Dither::Bayer4),
+            "bayer8" | "bayer-8" => Some(Dither::Bayer8),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Clone)]
+pub struct ImportOptions {
+    pub quantizer: Quantizer,
+    /// Palette size to aim for, at most [`MAX_PALETTE_LENGTH`].
+    pub max_colors: usize,
+    /// Use these packed RGB colours instead of generating a palette.
+    pub palette: Option<Vec<u32>>,
+    pub dither: Dither,
+    /// Scales the diffused error (or the ordered dither offsets), `1.0` is the full amount.
+    pub dither_strength: f32,
+    pub label: Option<String>,
+    pub tooltip: Option<String>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        return ImportOptions {
+            quantizer: Quantizer::MedianCut,
+            max_colors: MAX_PALETTE_LENGTH,
+            palette: None,
+            dither: Dither::None,
+            dither_strength: 1.0,
+            label: None,
+            tooltip: None,
+        };
+    }
+}
+
+/// Parses a palette given as `cc` (the default ComputerCraft colours) or as `RRGGBB` hex
+/// colours separated by commas or whitespace.
+pub fn parse_palette(palette: &str) -> Option<Vec<u32>> {
+    if palette.trim().eq_ignore_ascii_case("cc") {
+        return Some(COMPUTERCRAFT_PALETTE.to_vec());
+    }
+
+    let colors = palette
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|color| !color.is_empty())
+        .map(parse_hex_color)
+        .collect::<Option<Vec<u32>>>()?;
+    if colors.is_empty() {
+        return None;
+    }
+
+    return Some(colors);
+}
+
+/// Whether `file` has the extension of a raster format [`read_image`] understands.
+pub fn is_image_path(file: &Path) -> bool {
+    return image::ImageFormat::from_path(file).is_ok_and(|format| format.reading_enabled());
+}
+
+/// Reads a PNG (or other raster image) from `file` and converts it to an [`Img2d`].
+pub fn read_image(file: &Path, options: &ImportOptions) -> Result<Img2d, PosterError> {
+    return import_image(&image::open(file)?, options);
+}
+
+/// Decodes a raster image of any supported format from `reader` and converts it.
+pub fn decode_image<R: BufRead + Seek>(
+    reader: R,
+    options: &ImportOptions,
+) -> Result<Img2d, PosterError> {
+    let image = ImageReader::new(reader).with_guessed_format()?.decode()?;
+
+    return import_image(&image, options);
+}
+
+/// Converts `image` to an [`Img2d`], quantizing it to `options.max_colors` colours unless
+/// a fixed palette is given. Transparency is dropped, posters have no alpha channel.
+pub fn import_image(image: &DynamicImage, options: &ImportOptions) -> Result<Img2d, PosterError> {
+    let palette_length = match &options.palette {
+        Some(palette) => palette.len(),
+        None => options.max_colors,
+    };
+    if palette_length > MAX_PALETTE_LENGTH {
+        return Err(PosterError::PaletteOverflow {
+            length: palette_length,
+            max: MAX_PALETTE_LENGTH,
+        });
+    }
+
+    let rgb = image.to_rgb8();
+    let colors: Vec<[u8; 3]> = rgb.pixels().map(|pixel| pixel.0).collect();
+
+    let palette = match &options.palette {
+        Some(palette) if !palette.is_empty() => palette.clone(),
+        _ => quantize(&colors, options.max_colors.max(1), options.quantizer),
+    };
+    let pixels = dither_to_palette(
+        &colors,
+        rgb.width() as usize,
+        &palette,
+        options.dither,
+        options.dither_strength,
+    );
+
+    return Ok(Img2d {
+        label: options.label.clone(),
+        tooltip: options.tooltip.clone(),
+        palette,
+        pixels,
+        width: rgb.width(),
+        height: rgb.height(),
+    });
+}
+
+/// Picks at most `max_colors` packed RGB colours representing `colors`.
+///
+/// If `colors` already has few enough distinct values they are used as is.
+pub fn quantize(colors: &[[u8; 3]], max_colors: usize, quantizer: Quantizer) -> Vec<u32> {
+    let histogram = histogram(colors);
+
+    let palette: Vec<[u8; 3]> = if histogram.len() <= max_colors {
+        histogram.iter().map(|(color, _)| *color).collect()
+    } else {
+        match quantizer {
+            Quantizer::MedianCut => median_cut(&histogram, max_colors),
+            Quantizer::KMeans => k_means(&histogram, max_colors),
+            Quantizer::Octree => octree(&histogram, max_colors),
+        }
+    };
+
+    let mut packed: Vec<u32> = Vec::with_capacity(palette.len());
+    for color in palette.into_iter().map(pack_rgb) {
+        if !packed.contains(&color) {
+            packed.push(color);
+        }
+    }
+
+    return packed;
+}
+
+/// Maps every colour to the index of the closest palette entry.
+pub fn map_to_palette(colors: &[[u8; 3]], palette: &[u32]) -> Vec<u8> {
+    let palette: Vec<[u8; 3]> = palette.iter().map(|&color| unpack_rgb(color)).collect();
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+
+    return colors
+        .iter()
+        .map(|color| {
+            *cache
+                .entry(*color)
+                .or_insert_with(|| nearest_color(&palette, *color) as u8)
+        })
+        .collect();
+}
+
+/// Maps a `width` pixels wide image onto the palette, dithering with `dither`.
+pub fn dither_to_palette(
+    colors: &[[u8; 3]],
+    width: usize,
+    palette: &[u32],
+    dither: Dither,
+    strength: f32,
+) -> Vec<u8> {
+    return match dither {
+        Dither::None => map_to_palette(colors, palette),
+        Dither::FloydSteinberg => diffuse_error(colors, width, palette, FLOYD_STEINBERG, strength),
+        Dither::Atkinson => diffuse_error(colors, width, palette, ATKINSON, strength),
+        Dither::Sierra => diffuse_error(colors, width, palette, SIERRA, strength),
+        Dither::Bayer4 => ordered_dither(colors, width, palette, 2, strength),
+        Dither::Bayer8 => ordered_dither(colors, width, palette, 3, strength),
+    };
+}
+
+fn clamp_color(color: [f32; 3]) -> [u8; 3] {
+    return color.map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+}
+
+fn diffuse_error(
+    colors: &[[u8; 3]],
+    width: usize,
+    palette: &[u32],
+    (kernel, divisor): (&[(i32, i32, f32)], f32),
+    strength: f32,
+) -> Vec<u8> {
+    let palette: Vec<[u8; 3]> = palette.iter().map(|&color| unpack_rgb(color)).collect();
+    let height = colors.len() / width.max(1);
+    let mut buffer: Vec<[f32; 3]> = colors
+        .iter()
+        .map(|color| color.map(|channel| channel as f32))
+        .collect();
+
+    let mut pixels: Vec<u8> = Vec::with_capacity(colors.len());
+    for (i, color) in colors.iter().enumerate() {
+        if i >= width * height {
+            pixels.push(nearest_color(&palette, *color) as u8);
+            continue;
+        }
+
+        let wanted = buffer[i];
+        let index = nearest_color(&palette, clamp_color(wanted));
+        pixels.push(index as u8);
+
+        let error: [f32; 3] =
+            std::array::from_fn(|channel| wanted[channel] - palette[index][channel] as f32);
+        let (x, y) = ((i % width) as i32, (i / width) as i32);
+        for &(dx, dy, weight) in kernel {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || nx >= width as i32 || ny >= height as i32 {
+                continue;
+            }
+
+            let target = &mut buffer[ny as usize * width + nx as usize];
+            for (channel, value) in target.iter_mut().enumerate() {
+                *value += error[channel] * weight / divisor * strength;
+            }
+        }
+    }
+
+    return pixels;
+}
+
+/// Bayer threshold for `x`, `y` in a `2^order` sized matrix, in `0..4^order`.
+///
+/// Interleaves the bits of `x ^ y` and `y`, lowest coordinate bits first, which builds the
+/// same matrix as the usual recursive definition.
+fn bayer_threshold(x: usize, y: usize, order: u32) -> u32 {
+    let mut threshold = 0;
+    for bit in 0..order {
+        let (xb, yb) = ((x >> bit) as u32 & 1, (y >> bit) as u32 & 1);
+        threshold = threshold << 2 | (xb ^ yb) << 1 | yb;
+    }
+
+    return threshold;
+}
+
+fn ordered_dither(
+    colors: &[[u8; 3]],
+    width: usize,
+    palette: &[u32],
+    order: u32,
+    strength: f32,
+) -> Vec<u8> {
+    let palette_rgb: Vec<[u8; 3]> = palette.iter().map(|&color| unpack_rgb(color)).collect();
+    let levels = (1u32 << (2 * order)) as f32;
+    // Roughly the distance between neighbouring palette colours along one channel.
+    let spread = 255.0 / (palette.len().max(2) as f32).cbrt() * strength;
+
+    return colors
+        .iter()
+        .enumerate()
+        .map(|(i, color)| {
+            let threshold = bayer_threshold(i % width.max(1), i / width.max(1), order);
+            let offset = ((threshold as f32 + 0.5) / levels - 0.5) * spread;
+            let shifted = clamp_color(color.map(|channel| channel as f32 + offset));
+            nearest_color(&palette_rgb, shifted) as u8
+        })
+        .collect();
+}
+
+/// Index of the palette entry with the smallest squared RGB distance to `color`.
+pub fn nearest_color(palette: &[[u8; 3]], color: [u8; 3]) -> usize {
+    let mut best = 0;
+    let mut best_distance = u32::MAX;
+
+    for (index, entry) in palette.iter().enumerate() {
+        let distance = color_distance(*entry, color);
+        if distance < best_distance {
+            best = index;
+            best_distance = distance;
+        }
+    }
+
+    return best;
+}
+
+fn color_distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    return (0..3)
+        .map(|channel| {
+            let difference = a[channel] as i32 - b[channel] as i32;
+            (difference * difference) as u32
+        })
+        .sum();
+}
+
+/// Distinct colours with how often they occur, sorted so results are deterministic.
+fn histogram(colors: &[[u8; 3]]) -> Vec<([u8; 3], u64)> {
+    let mut counts: HashMap<[u8; 3], u64> = HashMap::new();
+    for color in colors {
+        *counts.entry(*color).or_insert(0) += 1;
+    }
+
+    let mut histogram: Vec<([u8; 3], u64)> = counts.into_iter().collect();
+    histogram.sort_unstable();
+
+    return histogram;
+}
+
+fn weighted_average(colors: &[([u8; 3], u64)]) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+    let mut count = 0u64;
+    for (color, n) in colors {
+        for (total, value) in sum.iter_mut().zip(color) {
+            *total += *value as u64 * n;
+        }
+        count += n;
+    }
+
+    return sum.map(|total| ((total + count / 2) / count.max(1)) as u8);
+}
+
+/// Returns the channel with the widest spread in `colors` and that spread.
+fn widest_channel(colors: &[([u8; 3], u64)]) -> (usize, u8) {
+    let mut widest = (0, 0);
+    for channel in 0..3 {
+        let min = colors
+            .iter()
+            .map(|(color, _)| color[channel])
+            .min()
+            .unwrap_or(0);
+        let max = colors
+            .iter()
+            .map(|(color, _)| color[channel])
+            .max()
+            .unwrap_or(0);
+        if max - min > widest.1 {
+            widest = (channel, max - min);
+        }
+    }
+
+    return widest;
+}
+
+fn median_cut(histogram: &[([u8; 3], u64)], max_colors: usize) -> Vec<[u8; 3]> {
+    let mut boxes: Vec<Vec<([u8; 3], u64)>> = vec![histogram.to_vec()];
+
+    while boxes.len() < max_colors {
+        let candidate = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .max_by_key(|(_, colors)| {
+                let population: u64 = colors.iter().map(|(_, n)| n).sum();
+                (widest_channel(colors).1, population)
+            })
+            .map(|(index, _)| index);
+        let Some(index) = candidate else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(index);
+        let (channel, _) = widest_channel(&colors);
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+
+        // Split at the weighted median, keeping at least one colour on each side.
+        let population: u64 = colors.iter().map(|(_, n)| n).sum();
+        let mut seen = 0;
+        let mut split = colors.len() / 2;
+        for (i, (_, n)) in colors.iter().enumerate() {
+            seen += n;
+            if seen * 2 >= population {
+                split = (i + 1).clamp(1, colors.len() - 1);
+                break;
+            }
+        }
+
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    return boxes
+        .iter()
+        .map(|colors| weighted_average(colors))
+        .collect();
+}
+
+fn k_means(histogram: &[([u8; 3], u64)], max_colors: usize) -> Vec<[u8; 3]> {
+    let mut centroids: Vec<[u8; 3]> = median_cut(histogram, max_colors);
+
+    for _ in 0..K_MEANS_ITERATIONS {
+        let mut clusters: Vec<Vec<([u8; 3], u64)>> = vec![Vec::new(); centroids.len()];
+        for &(color, n) in histogram {
+            clusters[nearest_color(&centroids, color)].push((color, n));
+        }
+
+        let mut moved = false;
+        for (centroid, cluster) in centroids.iter_mut().zip(clusters.iter()) {
+            if cluster.is_empty() {
+                continue;
+            }
+
+            let average = weighted_average(cluster);
+            if average != *centroid {
+                *centroid = average;
+                moved = true;
+            }
+        }
+
+        if !moved {
+            break;
+        }
+    }
+
+    return centroids;
+}
+
+#[derive(Default)]
+struct OctreeNode {
+    /// Indices into the node list, `0` (the root) means no child.
+    children: [usize; 8],
+    sum: [u64; 3],
+    count: u64,
+    is_leaf: bool,
+}
+
+fn octree(histogram: &[([u8; 3], u64)], max_colors: usize) -> Vec<[u8; 3]> {
+    let mut nodes: Vec<OctreeNode> = vec![OctreeNode::default()];
+    // Internal nodes by depth, the candidates for being folded into a leaf.
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); OCTREE_DEPTH];
+    levels[0].push(0);
+    let mut leaves = 0;
+
+    for &(color, n) in histogram {
+        let mut node = 0;
+        for depth in 0..OCTREE_DEPTH {
+            let shift = 7 - depth;
+            let branch = ((color[0] >> shift) & 1) << 2
+                | ((color[1] >> shift) & 1) << 1
+                | ((color[2] >> shift) & 1);
+
+            if nodes[node].children[branch as usize] == 0 {
+                nodes.push(OctreeNode::default());
+                let child = nodes.len() - 1;
+                nodes[node].children[branch as usize] = child;
+
+                if depth + 1 == OCTREE_DEPTH {
+                    nodes[child].is_leaf = true;
+                    leaves += 1;
+                } else {
+                    levels[depth + 1].push(child);
+                }
+            }
+            node = nodes[node].children[branch as usize];
+        }
+
+        for (total, value) in nodes[node].sum.iter_mut().zip(color) {
+            *total += value as u64 * n;
+        }
+        nodes[node].count += n;
+    }
+
+    // Fold the least used nodes of the deepest level first, their children are all leaves
+    // by then so a level only needs sorting once, most used first so the least pop off.
+    let mut sorted_depth = OCTREE_DEPTH;
+    while leaves > max_colors {
+        let Some(depth) = (0..OCTREE_DEPTH)
+            .rev()
+            .find(|&depth| !levels[depth].is_empty())
+        else {
+            break;
+        };
+
+        if depth != sorted_depth {
+            let subtree_count = |node: usize| -> u64 {
+                nodes[node]
+                    .children
+                    .iter()
+                    .filter(|&&child| child != 0)
+                    .map(|&child| nodes[child].count)
+                    .sum()
+            };
+            levels[depth].sort_by_cached_key(|&node| std::cmp::Reverse(subtree_count(node)));
+            sorted_depth = depth;
+        }
+        let Some(node) = levels[depth].pop() else {
+            break;
+        };
+
+        let mut merged = 0;
+        for branch in 0..8 {
+            let child = nodes[node].children[branch];
+            if child == 0 {
+                continue;
+            }
+
+            let (sum, count) = (nodes[child].sum, nodes[child].count);
+            for (total, value) in nodes[node].sum.iter_mut().zip(sum) {
+                *total += value;
+            }
+            nodes[node].count += count;
+            nodes[node].children[branch] = 0;
+            nodes[child].is_leaf = false;
+            merged += 1;
+        }
+        nodes[node].is_leaf = true;
+        leaves = leaves + 1 - merged;
+    }
+
+    return nodes
+        .iter()
+        .filter(|node| node.is_leaf && node.count > 0)
+        .map(|node| {
+            node.sum
+                .map(|total| ((total + node.count / 2) / node.count) as u8)
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    const QUANTIZERS: [Quantizer; 3] = [Quantizer::MedianCut, Quantizer::KMeans, Quantizer::Octree];
+
+    /// A 64x64 gradient with 4096 distinct colours.
+    fn gradient() -> DynamicImage {
+        return DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+        }));
+    }
+
+    fn assert_within_palette(image: &Img2d, max_colors: usize) {
+        assert!(!image.palette.is_empty());
+        assert!(image.palette.len() <= max_colors);
+        assert_eq!(image.pixels.len(), 64 * 64);
+        assert!(image
+            .pixels
+            .iter()
+            .all(|&pixel| (pixel as usize) < image.palette.len()));
+        image.validate().unwrap();
+    }
+
+    #[test]
+    fn quantizers_stay_within_the_palette_length() {
+        for quantizer in QUANTIZERS {
+            for max_colors in [1, 2, 16, MAX_PALETTE_LENGTH] {
+                let options = ImportOptions {
+                    quantizer,
+                    max_colors,
+                    ..Default::default()
+                };
+                let image = import_image(&gradient(), &options).unwrap();
+                assert_within_palette(&image, max_colors);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_few_colours_as_they_are() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(4, 1, |x, _| {
+            image::Rgb([if x % 2 == 0 { 255 } else { 0 }, 0, 0])
+        }));
+        for quantizer in QUANTIZERS {
+            let options = ImportOptions {
+                quantizer,
+                ..Default::default()
+            };
+            let image = import_image(&image, &options).unwrap();
+            let colors: Vec<u32> = image
+                .pixels
+                .iter()
+                .map(|&pixel| image.palette[pixel as usize])
+                .collect();
+            assert_eq!(image.palette.len(), 2);
+            assert_eq!(colors, vec![0xFF0000, 0x000000, 0xFF0000, 0x000000]);
+        }
+    }
+
+    #[test]
+    fn maps_onto_a_fixed_palette() {
+        let options = ImportOptions {
+            palette: parse_palette("cc"),
+            ..Default::default()
+        };
+        let image = import_image(&gradient(), &options).unwrap();
+        assert_eq!(image.palette, COMPUTERCRAFT_PALETTE.to_vec());
+        assert_within_palette(&image, 16);
+    }
+
+    #[test]
+    fn rejects_palettes_too_long_to_store() {
+        let options = ImportOptions {
+            max_colors: MAX_PALETTE_LENGTH + 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            import_image(&gradient(), &options),
+            Err(PosterError::PaletteOverflow { length: 256, .. })
+        ));
+
+        let options = ImportOptions {
+            palette: Some((0..256).collect()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            import_image(&gradient(), &options),
+            Err(PosterError::PaletteOverflow { length: 256, .. })
+        ));
+    }
+
+    #[test]
+    fn parses_palettes() {
+        assert_eq!(
+            parse_palette("ff0000, 00ff00 0000FF"),
+            Some(vec![0xFF0000, 0x00FF00, 0x0000FF])
+        );
+        assert_eq!(parse_palette(" CC ").map(|palette| palette.len()), Some(16));
+        assert_eq!(parse_palette(""), None);
+        assert_eq!(parse_palette("ff0000,nope"), None);
+    }
+
+    const DITHERS: [Dither; 6] = [
+        Dither::None,
+        Dither::FloydSteinberg,
+        Dither::Atkinson,
+        Dither::Sierra,
+        Dither::Bayer4,
+        Dither::Bayer8,
+    ];
+
+    /// A 16x16 image in mid grey.
+    fn grey() -> Vec<[u8; 3]> {
+        return vec![[128, 128, 128]; 16 * 16];
+    }
+
+    /// Checks that `dither` only picks palette entries, always picks the same ones for the
+    /// same input and mixes black and white for grey unless it's [`Dither::None`].
+    fn check_dither(dither: Dither) {
+        let colors: Vec<[u8; 3]> = gradient().to_rgb8().pixels().map(|pixel| pixel.0).collect();
+        let palette = parse_palette("cc").unwrap();
+        for strength in [0.5, 1.0, 4.0] {
+            let pixels = dither_to_palette(&colors, 64, &palette, dither, strength);
+            assert_eq!(pixels.len(), colors.len());
+            assert!(pixels.iter().all(|&pixel| (pixel as usize) < palette.len()));
+            assert_eq!(
+                pixels,
+                dither_to_palette(&colors, 64, &palette, dither, strength)
+            );
+        }
+
+        let palette = vec![0x000000, 0xFFFFFF];
+        let pixels = dither_to_palette(&grey(), 16, &palette, dither, 1.0);
+        let white = pixels.iter().filter(|&&pixel| pixel == 1).count();
+        if dither == Dither::None {
+            assert_eq!(pixels, map_to_palette(&grey(), &palette));
+        } else {
+            // Roughly half of the pixels
+            assert!((96..=160).contains(&white), "{}", white);
+        }
+    }
+
+    #[test]
+    fn maps_without_dithering() {
+        check_dither(Dither::None);
+    }
+
+    #[test]
+    fn dithers_floyd_steinberg() {
+        check_dither(Dither::FloydSteinberg);
+    }
+
+    #[test]
+    fn dithers_atkinson() {
+        check_dither(Dither::Atkinson);
+    }
+
+    #[test]
+    fn dithers_sierra() {
+        check_dither(Dither::Sierra);
+    }
+
+    #[test]
+    fn dithers_bayer4() {
+        check_dither(Dither::Bayer4);
+    }
+
+    #[test]
+    fn dithers_bayer8() {
+        check_dither(Dither::Bayer8);
+    }
+
+    #[test]
+    fn only_dithers_with_some_strength() {
+        let palette = vec![0x000000, 0xFFFFFF];
+        for dither in DITHERS {
+            assert_eq!(
+                dither_to_palette(&grey(), 16, &palette, dither, 0.0),
+                map_to_palette(&grey(), &palette),
+                "{:?}",
+                dither
+            );
+        }
+    }
+
+    #[test]
+    fn builds_bayer_matrices() {
+        let matrix: Vec<u32> = (0..16).map(|i| bayer_threshold(i % 4, i / 4, 2)).collect();
+        assert_eq!(
+            matrix,
+            vec![0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5]
+        );
+
+        let mut matrix: Vec<u32> = (0..64).map(|i| bayer_threshold(i % 8, i / 8, 3)).collect();
+        matrix.sort();
+        assert_eq!(matrix, (0..64).collect::<Vec<u32>>());
+    }
+}
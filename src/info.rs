@@ -0,0 +1,232 @@
+//! Summaries of what a poster contains, without converting it.
+
+use crate::charset::Unrepresentable;
+use crate::error::PosterError;
+use crate::poster::{
+    detect_magic, encode_2db, encode_2dba, encode_2dj, encode_2dja, parse_image_header,
+    DecodeOptions, EncodeOptions, FormatVersion, Img2d, Img2dArray, Img2dArrayDecoder,
+    PosterFormat, FLAG_CHECKSUMS, FLAG_COMPRESSED,
+};
+use serde::Serialize;
+
+/// The v2 header of a 2db or 2dba file, or what stands in for it in a v1 file.
+#[derive(Serialize)]
+pub struct HeaderInfo {
+    pub version: u8,
+    pub compressed: bool,
+    pub checksums: bool,
+}
+
+#[derive(Serialize)]
+pub struct ImageInfo {
+    pub label: Option<String>,
+    pub tooltip: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub palette: Vec<u32>,
+    pub pixel_count: usize,
+    /// `width * height`, what `pixel_count` should be.
+    pub expected_pixel_count: u64,
+    /// Number of distinct palette entries used by the pixels.
+    pub colors_used: usize,
+    /// Number of pixels without a palette entry.
+    pub out_of_palette: usize,
+    /// Size of the image encoded as 2dj, `None` if it can't be encoded.
+    pub size_2dj: Option<usize>,
+    /// Size of the image encoded as an uncompressed v2 2db, `None` if it can't be encoded.
+    pub size_2db: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct ArrayInfo {
+    pub title: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub pages: Vec<ImageInfo>,
+    pub size_2dja: Option<usize>,
+    pub size_2dba: Option<usize>,
+}
+
+/// Options the encoded sizes are measured with, encoding whatever can be encoded at all.
+fn sizing_options() -> EncodeOptions {
+    return EncodeOptions {
+        validate: false,
+        unrepresentable: Unrepresentable::Replace(b'?'),
+        version: FormatVersion::V2,
+        ..Default::default()
+    };
+}
+
+/// Number of bytes `encode` writes, `None` if it fails.
+fn encoded_size<F>(encode: F) -> Option<usize>
+where
+    F: FnOnce(&mut Vec<u8>) -> Result<(), PosterError>,
+{
+    let mut bytes: Vec<u8> = Vec::new();
+
+    return encode(&mut bytes).ok().map(|_| bytes.len());
+}
+
+pub fn image_info(image: &Img2d) -> ImageInfo {
+    let mut used = [false; 256];
+    for &pixel in image.pixels.iter() {
+        used[pixel as usize] = true;
+    }
+
+    let options = sizing_options();
+    return ImageInfo {
+        label: image.label.clone(),
+        tooltip: image.tooltip.clone(),
+        width: image.width,
+        height: image.height,
+        palette: image.palette.clone(),
+        pixel_count: image.pixels.len(),
+        expected_pixel_count: image.width as u64 * image.height as u64,
+        colors_used: used
+            .iter()
+            .take(image.palette.len())
+            .filter(|&&used| used)
+            .count(),
+        out_of_palette: image
+            .pixels
+            .iter()
+            .filter(|&&pixel| pixel as usize >= image.palette.len())
+            .count(),
+        size_2dj: encoded_size(|bytes| encode_2dj(image, bytes, &options)),
+        size_2db: encoded_size(|bytes| encode_2db(image, bytes, &options)),
+    };
+}
+
+pub fn array_info(image: &Img2dArray) -> ArrayInfo {
+    let options = sizing_options();
+    return ArrayInfo {
+        title: image.title.clone(),
+        width: image.width,
+        height: image.height,
+        pages: image.pages.iter().map(image_info).collect(),
+        size_2dja: encoded_size(|bytes| encode_2dja(image, bytes, &options)),
+        size_2dba: encoded_size(|bytes| encode_2dba(image, bytes, &options)),
+    };
+}
+
+/// Reads the header of a 2db or 2dba file, a file without the v2 magic signature is v1.
+pub fn header_info(bytes: &[u8]) -> Result<HeaderInfo, PosterError> {
+    let (version, flags) = match detect_magic(bytes) {
+        Some(PosterFormat::BinaryArray) => {
+            let decoder = Img2dArrayDecoder::new(bytes, &DecodeOptions::default())?;
+            (decoder.version, decoder.flags)
+        }
+        _ => {
+            let (version, flags, _) = parse_image_header(bytes)?;
+            (version, flags)
+        }
+    };
+
+    return Ok(HeaderInfo {
+        version: version.number(),
+        compressed: flags & FLAG_COMPRESSED != 0,
+        checksums: flags & FLAG_CHECKSUMS != 0,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+    use crate::testing::{book, encode_book, page};
+
+    #[test]
+    fn counts_used_colours() {
+        let mut image = page("p1", 0);
+        image.palette = vec![0x000000, 0xFFFFFF, 0xFF0000, 0x00FF00];
+        image.pixels = vec![0, 2, 2, 9, 9, 4];
+
+        let info = image_info(&image);
+        assert_eq!(info.colors_used, 2);
+        assert_eq!(info.out_of_palette, 3);
+        assert_eq!((info.pixel_count, info.expected_pixel_count), (6, 6));
+
+        image.pixels.pop();
+        image.height = 100_000;
+        image.width = 100_000;
+        let info = image_info(&image);
+        assert_eq!(info.pixel_count, 5);
+        assert_eq!(info.expected_pixel_count, 10_000_000_000);
+    }
+
+    #[test]
+    fn measures_encoded_sizes() {
+        let image = page("p1", 1);
+        let info = image_info(&image);
+        assert_eq!(info.label.as_deref(), Some("p1"));
+        assert_eq!(info.palette, image.palette);
+
+        let options = sizing_options();
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(&image, &mut bytes, &options).unwrap();
+        assert_eq!(info.size_2db, Some(bytes.len()));
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2dj(&image, &mut bytes, &options).unwrap();
+        assert_eq!(info.size_2dj, Some(bytes.len()));
+
+        // Invalid images and characters outside the charset are still measured
+        let mut broken = page("snow ☃", 1);
+        broken.pixels.push(7);
+        assert!(image_info(&broken).size_2db.is_some());
+    }
+
+    #[test]
+    fn summarises_every_page() {
+        let info = array_info(&book());
+        assert_eq!(info.title.as_deref(), Some("book"));
+        assert_eq!((info.width, info.height), (3, 1));
+        let labels: Vec<&str> = info
+            .pages
+            .iter()
+            .map(|page| page.label.as_deref().unwrap())
+            .collect();
+        assert_eq!(labels, vec!["p1", "p2", "p3"]);
+        assert!(info.pages.iter().all(|page| page.colors_used == 1));
+        assert_eq!(info.size_2dba, Some(encode_book(&sizing_options()).len()));
+        assert!(info.size_2dja.is_some());
+    }
+
+    #[test]
+    fn reads_headers() {
+        let header = |bytes: &[u8]| {
+            let info = header_info(bytes).unwrap();
+            return (info.version, info.compressed, info.checksums);
+        };
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(&page("p1", 1), &mut bytes, &EncodeOptions::default()).unwrap();
+        assert_eq!(header(&bytes), (1, false, false));
+        assert_eq!(
+            header(&encode_book(&EncodeOptions::default())),
+            (1, false, false)
+        );
+
+        let options = EncodeOptions {
+            version: FormatVersion::V2,
+            compression: Compression::RunLength,
+            ..Default::default()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(&page("p1", 1), &mut bytes, &options).unwrap();
+        assert_eq!(header(&bytes), (2, true, false));
+
+        let options = EncodeOptions {
+            version: FormatVersion::V2,
+            checksums: true,
+            ..Default::default()
+        };
+        let mut bytes = encode_book(&options);
+        assert_eq!(header(&bytes), (2, false, true));
+
+        bytes[4] = 3;
+        assert!(matches!(
+            header_info(&bytes),
+            Err(PosterError::UnsupportedVersion { .. })
+        ));
+    }
+}
@@ -0,0 +1,43 @@
+//! Reading and writing ComputerCraft posters in their JSON (2dj/2dja) and binary (2db/2dba)
+//! encodings.
+
+#![allow(clippy::needless_return)]
+
+#[cfg(feature = "api")]
+pub mod api;
+pub mod batch;
+pub mod charset;
+pub mod compression;
+mod cursor;
+pub mod error;
+pub mod export;
+pub mod import;
+pub mod info;
+pub mod lua;
+pub mod poster;
+pub mod preview;
+pub mod recover;
+pub mod server;
+#[cfg(test)]
+mod testing;
+pub mod tile;
+pub mod validate;
+pub mod verify;
+pub mod watch;
+
+pub use charset::Unrepresentable;
+pub use compression::Compression;
+pub use error::PosterError;
+pub use import::{read_image, Dither, ImportOptions, Quantizer};
+pub use poster::{
+    decode_2db, decode_2dba, decode_2dj, decode_2dja, detect_format, detect_magic, encode_2db,
+    encode_2dba, encode_2dj, encode_2dja, img_2d_array_to_bytes, img_2d_array_to_string,
+    img_2d_to_bytes, img_2d_to_string, pack_rgb, read_2db, read_2dba, read_2dj, read_2dja,
+    unpack_rgb, write_2db, write_2dba, write_2dj, write_2dja, DecodeOptions, EncodeOptions,
+    FormatVersion, Img2d, Img2dArray, Img2dArrayDecoder, Img2dArrayEncoder, ImgFormat,
+    PosterFormat,
+};
+pub use preview::{render_preview, ColorMode, PreviewOptions};
+pub use recover::{recover_2dba, Recovery};
+pub use validate::{Violation, ViolationKind};
+pub use verify::{verify_2db, verify_2dba, Verification};
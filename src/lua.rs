@@ -0,0 +1,318 @@
+//! Generation of a standalone Lua library for reading and writing the binary formats in
+//! ComputerCraft programs.
+//!
+//! The library is assembled from the sources in `src/lua`, only including the compression
+//! scheme and checksums the chosen [`EncodeOptions`] use. It needs `string.pack` and `bit32`,
+//! which CC: Tweaked provides.
+
+use crate::compression::Compression;
+use crate::error::PosterError;
+use crate::poster::EncodeOptions;
+
+const POSTER: &str = include_str!("lua/poster.lua");
+const CRC32: &str = include_str!("lua/crc32.lua");
+const RUN_LENGTH: &str = include_str!("lua/rle.lua");
+const DEFLATE: &str = include_str!("lua/deflate.lua");
+
+/// Describes what the generated library writes, for its header comment.
+fn settings(options: &EncodeOptions) -> String {
+    let mut features: Vec<String> = Vec::new();
+    if options.compression != Compression::None {
+        features.push(format!("{} compression", options.compression.name()));
+    }
+    if options.checksums {
+        features.push("checksums".to_string());
+    }
+
+    let version = format!("version {} files", options.version.number());
+    if features.is_empty() {
+        return version;
+    }
+
+    return format!("{} with {}", version, features.join(" and "));
+}
+
+/// Generates a Lua library that writes 2db/2dba files the way [`crate::poster::encode_2db`]
+/// and [`crate::poster::encode_2dba`] do with `options`, and reads any file that only uses the
+/// features `options` enable.
+///
+/// Labels, tooltips and titles are passed through as bytes since Lua strings in ComputerCraft
+/// already use its charset, so `validate` and `unrepresentable` are ignored.
+pub fn generate_lua(options: &EncodeOptions) -> Result<String, PosterError> {
+    options.check_version()?;
+
+    let mut codecs = String::new();
+    if options.checksums {
+        codecs.push_str(CRC32);
+    }
+    match options.compression {
+        Compression::None => {}
+        Compression::RunLength => codecs.push_str(RUN_LENGTH),
+        Compression::Deflate => codecs.push_str(DEFLATE),
+    }
+
+    return Ok(POSTER
+        .replace(
+            "{{GENERATOR}}",
+            concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")),
+        )
+        .replace("{{SETTINGS}}", &settings(options))
+        .replace("{{VERSION}}", &options.version.number().to_string())
+        .replace("{{COMPRESSION}}", &options.compression.id().to_string())
+        .replace(
+            "{{CHECKSUMS}}",
+            if options.checksums { "true" } else { "false" },
+        )
+        .replace("{{CODECS}}", &codecs));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poster::{
+        decode_2db, decode_2dba, encode_2db, encode_2dba, DecodeOptions, FormatVersion, Img2d,
+    };
+    use crate::testing::{book, TempDir};
+    use std::fs;
+    use std::process::Command;
+
+    const COMPRESSIONS: [Compression; 3] = [
+        Compression::None,
+        Compression::RunLength,
+        Compression::Deflate,
+    ];
+
+    /// Every combination of options the library can be generated with.
+    fn all_options() -> Vec<EncodeOptions> {
+        let mut all: Vec<EncodeOptions> = vec![EncodeOptions::default()];
+        for compression in COMPRESSIONS {
+            for checksums in [false, true] {
+                all.push(EncodeOptions {
+                    version: FormatVersion::V2,
+                    compression,
+                    checksums,
+                    ..Default::default()
+                });
+            }
+        }
+
+        return all;
+    }
+
+    #[test]
+    fn fills_in_every_placeholder() {
+        for options in all_options() {
+            let lua = generate_lua(&options).unwrap();
+            assert!(!lua.contains("{{"), "{}", settings(&options));
+            assert!(lua.contains(&format!("local VERSION = {}\n", options.version.number())));
+            assert!(lua.contains(&format!(
+                "local COMPRESSION = {}\n",
+                options.compression.id()
+            )));
+            assert!(lua.contains(&format!("local CHECKSUMS = {}\n", options.checksums)));
+        }
+    }
+
+    #[test]
+    fn only_includes_the_codecs_needed() {
+        for options in all_options() {
+            let lua = generate_lua(&options).unwrap();
+            assert_eq!(lua.contains(CRC32), options.checksums);
+            assert_eq!(
+                lua.contains(RUN_LENGTH),
+                options.compression == Compression::RunLength
+            );
+            assert_eq!(
+                lua.contains(DEFLATE),
+                options.compression == Compression::Deflate
+            );
+        }
+    }
+
+    #[test]
+    fn describes_the_settings() {
+        let options = EncodeOptions {
+            version: FormatVersion::V2,
+            compression: Compression::Deflate,
+            checksums: true,
+            ..Default::default()
+        };
+        let lua = generate_lua(&options).unwrap();
+        assert!(lua.contains("writes version 2 files with deflate compression and checksums.\n"));
+        let lua = generate_lua(&EncodeOptions {
+            version: FormatVersion::V1,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(lua.contains("writes version 1 files.\n"));
+    }
+
+    #[test]
+    fn rejects_v2_features_in_v1() {
+        for compression in [Compression::RunLength, Compression::Deflate] {
+            let options = EncodeOptions {
+                compression,
+                version: FormatVersion::V1,
+                ..Default::default()
+            };
+            assert!(matches!(
+                generate_lua(&options),
+                Err(PosterError::VersionTooOld {
+                    feature: "pixel compression"
+                })
+            ));
+        }
+        let options = EncodeOptions {
+            checksums: true,
+            version: FormatVersion::V1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            generate_lua(&options),
+            Err(PosterError::VersionTooOld { .. })
+        ));
+    }
+
+    /// Decodes the 2db and 2dba given as its arguments with the library and writes them back
+    /// encoded again. Outside ComputerCraft `bit32` is emulated, exits with 77 without
+    /// `string.pack`.
+    const ROUND_TRIP: &str = r#"
+if string.pack == nil then os.exit(77) end
+if bit32 == nil then
+  bit32 = load([[
+    local mask = 0xFFFFFFFF
+    local function fold(f, first, ...)
+      local result = first & mask
+      for _, value in ipairs({ ... }) do result = f(result, value & mask) end
+      return result
+    end
+    return {
+      band = function(...) return fold(function(a, b) return a & b end, ...) end,
+      bor = function(...) return fold(function(a, b) return a | b end, ...) end,
+      bxor = function(...) return fold(function(a, b) return a ~ b end, ...) end,
+      bnot = function(a) return ~a & mask end,
+      lshift = function(a, n) return (a << n) & mask end,
+      rshift = function(a, n) return (a & mask) >> n end,
+    }
+  ]])()
+end
+local poster = dofile(arg[1])
+local function read(path)
+  local file = assert(io.open(path, "rb"))
+  local bytes = file:read("a")
+  file:close()
+  return bytes
+end
+local function write(path, bytes)
+  local file = assert(io.open(path, "wb"))
+  file:write(bytes)
+  file:close()
+end
+write(arg[3], poster.encode(assert(poster.decode(read(arg[2])))))
+write(arg[5], poster.encode_array(assert(poster.decode_array(read(arg[4])))))
+"#;
+
+    /// A Lua interpreter from `$LUA` or the path, `None` if there isn't one.
+    fn lua_interpreter() -> Option<String> {
+        let candidates = std::env::var("LUA")
+            .into_iter()
+            .chain(["lua5.4", "lua5.3", "lua"].map(String::from));
+        for candidate in candidates {
+            let found = Command::new(&candidate)
+                .args(["-e", "os.exit(0)"])
+                .output()
+                .is_ok_and(|output| output.status.success());
+            if found {
+                return Some(candidate);
+            }
+        }
+
+        return None;
+    }
+
+    fn assert_same_image(expected: &Img2d, actual: &Img2d) {
+        assert_eq!(expected.label, actual.label);
+        assert_eq!(expected.tooltip, actual.tooltip);
+        assert_eq!(expected.palette, actual.palette);
+        assert_eq!(expected.pixels, actual.pixels);
+        assert_eq!(
+            (expected.width, expected.height),
+            (actual.width, actual.height)
+        );
+    }
+
+    #[test]
+    fn round_trips_through_lua() {
+        let lua = match lua_interpreter() {
+            Some(t) => t,
+            None => {
+                println!("No Lua interpreter found, set LUA to run the round trip.");
+                return;
+            }
+        };
+
+        let dir = TempDir::new();
+        dir.write("round_trip.lua", ROUND_TRIP.as_bytes());
+        let mut array = book();
+        array.title = None;
+        array.pages[0].pixels = vec![0, 0, 0, 1, 1, 0];
+        array.pages[1].tooltip = Some("tip".to_string());
+        let image = &array.pages[0];
+        let decode = DecodeOptions { strict: true };
+
+        for options in all_options() {
+            let name = settings(&options);
+            dir.write("poster.lua", generate_lua(&options).unwrap().as_bytes());
+            let mut image_bytes: Vec<u8> = Vec::new();
+            encode_2db(image, &mut image_bytes, &options).unwrap();
+            dir.write("image.2db", &image_bytes);
+            let mut array_bytes: Vec<u8> = Vec::new();
+            encode_2dba(&array, &mut array_bytes, &options).unwrap();
+            dir.write("array.2dba", &array_bytes);
+
+            let output = Command::new(&lua)
+                .arg(dir.join("round_trip.lua"))
+                .args(
+                    [
+                        "poster.lua",
+                        "image.2db",
+                        "image.lua.2db",
+                        "array.2dba",
+                        "array.lua.2dba",
+                    ]
+                    .map(|file| dir.join(file)),
+                )
+                .output()
+                .unwrap();
+            if output.status.code() == Some(77) {
+                println!("{} has no string.pack, skipping the round trip.", lua);
+                return;
+            }
+            assert!(
+                output.status.success(),
+                "{}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            );
+
+            // The Lua deflate compresses differently, so the files are compared decoded
+            let lua_image = fs::read(dir.join("image.lua.2db")).unwrap();
+            assert_same_image(
+                &decode_2db(image_bytes.as_slice(), &decode).unwrap(),
+                &decode_2db(lua_image.as_slice(), &decode).unwrap(),
+            );
+            let lua_array = fs::read(dir.join("array.lua.2dba")).unwrap();
+            let expected = decode_2dba(array_bytes.as_slice(), &decode).unwrap();
+            let actual = decode_2dba(lua_array.as_slice(), &decode).unwrap();
+            assert_eq!(expected.title, actual.title, "{}", name);
+            assert_eq!(expected.pages.len(), actual.pages.len(), "{}", name);
+            for (expected, actual) in expected.pages.iter().zip(&actual.pages) {
+                assert_same_image(expected, actual);
+            }
+            if options.compression == Compression::None {
+                assert_eq!(lua_image, image_bytes, "{}", name);
+                assert_eq!(lua_array, array_bytes, "{}", name);
+            }
+        }
+    }
+}
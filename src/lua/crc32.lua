@@ -0,0 +1,23 @@
+
+-- CRC32 (IEEE) of the checksums
+
+local crc_table = {}
+for i = 0, 255 do
+  local crc = i
+  for _ = 1, 8 do
+    if bit32.band(crc, 1) ~= 0 then
+      crc = bit32.bxor(0xEDB88320, bit32.rshift(crc, 1))
+    else
+      crc = bit32.rshift(crc, 1)
+    end
+  end
+  crc_table[i] = crc
+end
+
+crc32 = function(bytes)
+  local crc = 0xFFFFFFFF
+  for i = 1, #bytes do
+    crc = bit32.bxor(crc_table[bit32.band(bit32.bxor(crc, bytes:byte(i)), 0xFF)], bit32.rshift(crc, 8))
+  end
+  return bit32.bnot(crc)
+end
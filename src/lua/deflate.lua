@@ -0,0 +1,299 @@
+
+-- Raw DEFLATE (RFC 1951). Inflating handles every block type, deflating writes a single
+-- block with the fixed Huffman codes and greedy LZ77 matching.
+
+local unpack = table.unpack or unpack
+
+local LENGTH_BASE = { 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258 }
+local LENGTH_EXTRA = { 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0 }
+local DISTANCE_BASE = { 1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577 }
+local DISTANCE_EXTRA = { 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13 }
+local CODE_LENGTH_ORDER = { 16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15 }
+
+-- Raised inside inflate, which turns it into a nil result
+local CORRUPT = {}
+
+local function bits(state, need)
+  local value = state.buffer
+  while state.count < need do
+    local byte = state.data:byte(state.position)
+    if byte == nil then
+      error(CORRUPT)
+    end
+    value = bit32.bor(value, bit32.lshift(byte, state.count))
+    state.position = state.position + 1
+    state.count = state.count + 8
+  end
+  state.buffer = bit32.rshift(value, need)
+  state.count = state.count - need
+  return bit32.band(value, bit32.lshift(1, need) - 1)
+end
+
+-- Canonical Huffman code from the code lengths of symbols 0 to n - 1
+local function huffman(lengths, n)
+  local code = { counts = {}, symbols = {} }
+  for length = 0, 15 do
+    code.counts[length] = 0
+  end
+  for symbol = 0, n - 1 do
+    code.counts[lengths[symbol]] = code.counts[lengths[symbol]] + 1
+  end
+
+  local offsets = { [1] = 0 }
+  for length = 1, 14 do
+    offsets[length + 1] = offsets[length] + code.counts[length]
+  end
+  for symbol = 0, n - 1 do
+    local length = lengths[symbol]
+    if length ~= 0 then
+      code.symbols[offsets[length]] = symbol
+      offsets[length] = offsets[length] + 1
+    end
+  end
+  return code
+end
+
+local function decode_symbol(state, code)
+  local value, first, index = 0, 0, 0
+  for length = 1, 15 do
+    value = value + bits(state, 1)
+    local count = code.counts[length]
+    if value - count < first then
+      local symbol = code.symbols[index + value - first]
+      if symbol == nil then
+        error(CORRUPT)
+      end
+      return symbol
+    end
+    index = index + count
+    first = (first + count) * 2
+    value = value * 2
+  end
+  error(CORRUPT)
+end
+
+local fixed_lengths, fixed_distances = {}, {}
+for symbol = 0, 287 do
+  fixed_lengths[symbol] = symbol < 144 and 8 or symbol < 256 and 9 or symbol < 280 and 7 or 8
+end
+for symbol = 0, 29 do
+  fixed_distances[symbol] = 5
+end
+local FIXED_LENGTH_CODE = huffman(fixed_lengths, 288)
+local FIXED_DISTANCE_CODE = huffman(fixed_distances, 30)
+
+local function dynamic_codes(state)
+  local length_count = bits(state, 5) + 257
+  local distance_count = bits(state, 5) + 1
+  local code_length_count = bits(state, 4) + 4
+
+  local lengths = {}
+  for i = 0, 18 do
+    lengths[i] = 0
+  end
+  for i = 1, code_length_count do
+    lengths[CODE_LENGTH_ORDER[i]] = bits(state, 3)
+  end
+  local code_length_code = huffman(lengths, 19)
+
+  local index = 0
+  while index < length_count + distance_count do
+    local symbol = decode_symbol(state, code_length_code)
+    local length, repeats = 0, 1
+    if symbol < 16 then
+      length = symbol
+    elseif symbol == 16 then
+      if index == 0 then
+        error(CORRUPT)
+      end
+      length, repeats = lengths[index - 1], 3 + bits(state, 2)
+    elseif symbol == 17 then
+      repeats = 3 + bits(state, 3)
+    else
+      repeats = 11 + bits(state, 7)
+    end
+    if index + repeats > length_count + distance_count then
+      error(CORRUPT)
+    end
+    for _ = 1, repeats do
+      lengths[index] = length
+      index = index + 1
+    end
+  end
+
+  local distance_lengths = {}
+  for i = 0, distance_count - 1 do
+    distance_lengths[i] = lengths[length_count + i]
+  end
+  return huffman(lengths, length_count), huffman(distance_lengths, distance_count)
+end
+
+local function inflate(payload, count)
+  local state = { data = payload, position = 1, buffer = 0, count = 0 }
+  local output = {}
+
+  local function block(length_code, distance_code)
+    while true do
+      local symbol = decode_symbol(state, length_code)
+      if symbol > 285 then
+        error(CORRUPT)
+      elseif symbol < 256 then
+        output[#output + 1] = symbol
+      elseif symbol == 256 then
+        return
+      else
+        symbol = symbol - 257
+        local length = LENGTH_BASE[symbol + 1] + bits(state, LENGTH_EXTRA[symbol + 1])
+        local distance_symbol = decode_symbol(state, distance_code)
+        if distance_symbol > 29 then
+          error(CORRUPT)
+        end
+        local distance = DISTANCE_BASE[distance_symbol + 1] + bits(state, DISTANCE_EXTRA[distance_symbol + 1])
+        if distance > #output then
+          error(CORRUPT)
+        end
+        for _ = 1, length do
+          output[#output + 1] = output[#output + 1 - distance]
+        end
+      end
+      if #output > count then
+        error(CORRUPT)
+      end
+    end
+  end
+
+  local last
+  repeat
+    last = bits(state, 1)
+    local kind = bits(state, 2)
+    if kind == 0 then
+      -- Stored blocks start on a byte boundary
+      state.buffer, state.count = 0, 0
+      local position = state.position
+      if position + 3 > #payload then
+        error(CORRUPT)
+      end
+      local length, inverse = string.unpack("<I2I2", payload, position)
+      if bit32.bxor(length, inverse) ~= 0xFFFF or position + 3 + length > #payload then
+        error(CORRUPT)
+      end
+      for i = position + 4, position + 3 + length do
+        output[#output + 1] = payload:byte(i)
+      end
+      state.position = position + 4 + length
+      if #output > count then
+        error(CORRUPT)
+      end
+    elseif kind == 1 then
+      block(FIXED_LENGTH_CODE, FIXED_DISTANCE_CODE)
+    elseif kind == 2 then
+      block(dynamic_codes(state))
+    else
+      error(CORRUPT)
+    end
+  until last == 1
+
+  local parts = {}
+  for i = 1, #output, 4096 do
+    parts[#parts + 1] = string.char(unpack(output, i, math.min(i + 4095, #output)))
+  end
+  return table.concat(parts)
+end
+
+decompressors[2] = function(payload, count)
+  local ok, pixels = pcall(inflate, payload, count)
+  if not ok then
+    if pixels ~= CORRUPT then
+      error(pixels, 0)
+    end
+    return nil
+  end
+  return pixels
+end
+
+-- Huffman codes are sent most significant bit first, so they're reversed into the LSB
+-- first bit stream
+local function reverse(code, length)
+  local reversed = 0
+  for _ = 1, length do
+    reversed = reversed * 2 + bit32.band(code, 1)
+    code = bit32.rshift(code, 1)
+  end
+  return reversed
+end
+
+local function deflate(pixels)
+  local parts, buffer, count = {}, 0, 0
+  local function put(value, length)
+    buffer = bit32.bor(buffer, bit32.lshift(value, count))
+    count = count + length
+    while count >= 8 do
+      parts[#parts + 1] = string.char(bit32.band(buffer, 0xFF))
+      buffer = bit32.rshift(buffer, 8)
+      count = count - 8
+    end
+  end
+  local function put_symbol(symbol)
+    if symbol < 144 then
+      put(reverse(0x30 + symbol, 8), 8)
+    elseif symbol < 256 then
+      put(reverse(0x190 + symbol - 144, 9), 9)
+    elseif symbol < 280 then
+      put(reverse(symbol - 256, 7), 7)
+    else
+      put(reverse(0xC0 + symbol - 280, 8), 8)
+    end
+  end
+  local function find(bases, value)
+    local index = #bases
+    while bases[index] > value do
+      index = index - 1
+    end
+    return index
+  end
+
+  -- A single final block with the fixed codes
+  put(1, 1)
+  put(1, 2)
+
+  local last_seen = {}
+  local i = 1
+  while i <= #pixels do
+    local key = pixels:sub(i, i + 2)
+    local candidate = #key == 3 and last_seen[key] or nil
+    local length = 0
+    if candidate ~= nil and i - candidate <= 32768 then
+      while length < 258 and i + length <= #pixels and pixels:byte(candidate + length) == pixels:byte(i + length) do
+        length = length + 1
+      end
+    end
+
+    if length >= 3 then
+      local length_index = find(LENGTH_BASE, length)
+      put_symbol(256 + length_index)
+      put(length - LENGTH_BASE[length_index], LENGTH_EXTRA[length_index])
+      local distance = i - candidate
+      local distance_index = find(DISTANCE_BASE, distance)
+      put(reverse(distance_index - 1, 5), 5)
+      put(distance - DISTANCE_BASE[distance_index], DISTANCE_EXTRA[distance_index])
+      for j = i, i + length - 1 do
+        last_seen[pixels:sub(j, j + 2)] = j
+      end
+      i = i + length
+    else
+      put_symbol(pixels:byte(i))
+      if #key == 3 then
+        last_seen[key] = i
+      end
+      i = i + 1
+    end
+  end
+
+  put_symbol(256)
+  if count > 0 then
+    put(0, 8 - count)
+  end
+  return table.concat(parts)
+end
+
+compressors[2] = deflate
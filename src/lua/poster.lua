@@ -0,0 +1,315 @@
+-- Reads and writes ComputerCraft posters in the 2db/2dba binary formats.
+-- Generated by {{GENERATOR}}, writes {{SETTINGS}}.
+--
+-- An image is a table with `label` and `tooltip` (strings or nil), `width`, `height`,
+-- `palette` (a list of 0xRRGGBB colours) and `pixels` (a string with one byte per pixel, the
+-- 0-based index of its palette colour). An image array has `title` (a string or nil),
+-- `width` and `height` (in pages) and `pages` (a list of images).
+--
+-- decode and decode_array return nil and an error message for damaged or unsupported files,
+-- encode and encode_array raise an error for images that can't be written.
+
+local poster = {}
+
+local VERSION = {{VERSION}}
+local COMPRESSION = {{COMPRESSION}}
+local CHECKSUMS = {{CHECKSUMS}}
+
+local IMAGE_MAGIC = "\1372DB"
+local ARRAY_MAGIC = "\1372DA"
+local FLAG_COMPRESSED = 1
+local FLAG_CHECKSUMS = 2
+local ABSENT_STRING_LENGTH = 0xFFFF
+local MAX_PALETTE_LENGTH = 255
+local END_OF_PAGES = 0xFFFFFFFF
+
+-- Pixel compression schemes by id, the library only has the ones it was generated with
+local compressors = { [0] = function(pixels) return pixels end }
+local decompressors = { [0] = function(payload) return payload end }
+local crc32 = nil
+{{CODECS}}
+local function fail(message, ...)
+  error(message:format(...), 0)
+end
+
+-- Reading
+
+-- Calls `f`, returning nil and the error message instead of raising it
+local function protect(f)
+  local ok, result = pcall(f)
+  if not ok then
+    return nil, result
+  end
+  return result
+end
+
+-- Reads a field of `size` bytes at `position`, failing if it runs past `limit`
+local function read(bytes, position, limit, format, size, field)
+  if position + size - 1 > limit then
+    fail("truncated %s at byte %d", field, position - 1)
+  end
+  return string.unpack(format, bytes, position)
+end
+
+local function read_bytes(bytes, position, limit, length, field)
+  if position + length - 1 > limit then
+    fail("truncated %s at byte %d", field, position - 1)
+  end
+  return bytes:sub(position, position + length - 1), position + length
+end
+
+local function read_string(bytes, position, limit, field)
+  local length
+  length, position = read(bytes, position, limit, "<I2", 2, field .. " length")
+  if length == ABSENT_STRING_LENGTH then
+    return nil, position
+  end
+  return read_bytes(bytes, position, limit, length, field)
+end
+
+local function check_checksum(bytes, stored, position, field)
+  if crc32 == nil then
+    fail("the file has checksums, which this library was generated without")
+  end
+  local computed = crc32(bytes)
+  if computed ~= stored then
+    fail("%s mismatch at byte %d, stored %08X but computed %08X", field, position - 1, stored, computed)
+  end
+end
+
+-- Reads the version and flags after `magic`, a file without it is version 1
+local function read_header(bytes, magic)
+  if bytes:sub(1, #magic) ~= magic then
+    return 1, 0, 1
+  end
+  local version, flags, position = read(bytes, #magic + 1, #bytes, "<BB", 2, "version and flags")
+  if version ~= 2 then
+    fail("unsupported version %d", version)
+  end
+  if bit32.band(flags, bit32.bnot(FLAG_COMPRESSED + FLAG_CHECKSUMS)) ~= 0 then
+    fail("unsupported flags %02X", flags)
+  end
+  return version, flags, position
+end
+
+-- Reads an image from `position` up to `limit`, returning it with the position after it
+local function read_image(bytes, position, limit, flags)
+  local function field(format, size, name)
+    local value
+    value, position = read(bytes, position, limit, format, size, name)
+    return value
+  end
+
+  local image = { palette = {} }
+  image.label, position = read_string(bytes, position, limit, "label")
+  image.tooltip, position = read_string(bytes, position, limit, "tooltip")
+  image.width = field("<I4", 4, "width")
+  image.height = field("<I4", 4, "height")
+
+  local palette_length = field("B", 1, "palette length")
+  for i = 1, palette_length do
+    image.palette[i] = field("<I4", 4, "palette")
+  end
+
+  local compressed = bit32.band(flags, FLAG_COMPRESSED) ~= 0
+  local compression = 0
+  if compressed then
+    local compression_position = position
+    compression = field("B", 1, "compression")
+    if decompressors[compression] == nil then
+      fail("unsupported compression %d at byte %d", compression, compression_position - 1)
+    end
+  end
+  local count = field("<I4", 4, "pixels length")
+  local payload_length = compressed and field("<I4", 4, "payload length") or count
+
+  local payload_position = position
+  local payload
+  payload, position = read_bytes(bytes, position, limit, payload_length, "pixels")
+  image.pixels = decompressors[compression](payload, count)
+  if image.pixels == nil or #image.pixels ~= count then
+    fail("corrupt compressed pixels at byte %d", payload_position - 1)
+  end
+
+  return image, position
+end
+
+--- Decodes a 2db image, returning nil and the reason if it can't.
+function poster.decode(bytes)
+  return protect(function()
+    local _, flags, position = read_header(bytes, IMAGE_MAGIC)
+    local image
+    image, position = read_image(bytes, position, #bytes, flags)
+    if bit32.band(flags, FLAG_CHECKSUMS) ~= 0 then
+      local stored = read(bytes, position, #bytes, "<I4", 4, "file checksum")
+      check_checksum(bytes:sub(1, position - 1), stored, position, "file checksum")
+    end
+    return image
+  end)
+end
+
+--- Decodes a 2dba image array, returning nil and the reason if it can't.
+function poster.decode_array(bytes)
+  return protect(function()
+    local _, flags, position = read_header(bytes, ARRAY_MAGIC)
+    local checksums = bit32.band(flags, FLAG_CHECKSUMS) ~= 0
+    local array = { pages = {} }
+    array.title, position = read_string(bytes, position, #bytes, "array title")
+    array.width, position = read(bytes, position, #bytes, "<I4", 4, "array width")
+    array.height, position = read(bytes, position, #bytes, "<I4", 4, "array height")
+
+    -- Files with checksums have to end with END_OF_PAGES, so running out is truncation
+    while checksums or position + 3 <= #bytes do
+      local page_length
+      page_length, position = read(bytes, position, #bytes, "<I4", 4, "page length")
+      if checksums and page_length == END_OF_PAGES then
+        local stored = read(bytes, position, #bytes, "<I4", 4, "file checksum")
+        check_checksum(bytes:sub(1, position - 1), stored, position, "file checksum")
+        break
+      end
+
+      local page_position = position
+      local page
+      page, position = read_bytes(bytes, position, #bytes, page_length, "page")
+      if checksums then
+        local stored
+        stored, position = read(bytes, position, #bytes, "<I4", 4, "page checksum")
+        check_checksum(page, stored, position - 4, "page checksum")
+      end
+      array.pages[#array.pages + 1] = read_image(bytes, page_position, page_position + page_length - 1, flags)
+    end
+
+    return array
+  end)
+end
+
+-- Writing
+
+local function write_string(parts, value, field)
+  if value == nil then
+    parts[#parts + 1] = string.pack("<I2", VERSION == 1 and 0 or ABSENT_STRING_LENGTH)
+    return
+  end
+  if #value >= ABSENT_STRING_LENGTH then
+    fail("%s is %d bytes long, the most is %d", field, #value, ABSENT_STRING_LENGTH - 1)
+  end
+  parts[#parts + 1] = string.pack("<I2", #value) .. value
+end
+
+local function write_header(parts, magic)
+  if VERSION == 2 then
+    local flags = 0
+    if COMPRESSION ~= 0 then flags = flags + FLAG_COMPRESSED end
+    if CHECKSUMS then flags = flags + FLAG_CHECKSUMS end
+    parts[#parts + 1] = magic .. string.pack("<BB", VERSION, flags)
+  end
+end
+
+local function write_image(image)
+  local parts = {}
+  write_string(parts, image.label, "label")
+  write_string(parts, image.tooltip, "tooltip")
+  parts[#parts + 1] = string.pack("<I4I4", image.width, image.height)
+
+  if #image.palette > MAX_PALETTE_LENGTH then
+    fail("palette has %d colours, the most is %d", #image.palette, MAX_PALETTE_LENGTH)
+  end
+  parts[#parts + 1] = string.pack("B", #image.palette)
+  for i = 1, #image.palette do
+    parts[#parts + 1] = string.pack("<I4", image.palette[i])
+  end
+
+  if COMPRESSION == 0 then
+    parts[#parts + 1] = string.pack("<I4", #image.pixels) .. image.pixels
+  else
+    local payload = compressors[COMPRESSION](image.pixels)
+    parts[#parts + 1] = string.pack("<BI4I4", COMPRESSION, #image.pixels, #payload) .. payload
+  end
+
+  return table.concat(parts)
+end
+
+--- Encodes an image as a 2db.
+function poster.encode(image)
+  local parts = {}
+  write_header(parts, IMAGE_MAGIC)
+  parts[#parts + 1] = write_image(image)
+
+  local bytes = table.concat(parts)
+  if CHECKSUMS then
+    bytes = bytes .. string.pack("<I4", crc32(bytes))
+  end
+  return bytes
+end
+
+--- Encodes an image array as a 2dba.
+function poster.encode_array(array)
+  local parts = {}
+  write_header(parts, ARRAY_MAGIC)
+  write_string(parts, array.title, "array title")
+  parts[#parts + 1] = string.pack("<I4I4", array.width, array.height)
+
+  for i = 1, #array.pages do
+    local page = write_image(array.pages[i])
+    parts[#parts + 1] = string.pack("<I4", #page) .. page
+    if CHECKSUMS then
+      parts[#parts + 1] = string.pack("<I4", crc32(page))
+    end
+  end
+
+  local bytes = table.concat(parts)
+  if CHECKSUMS then
+    bytes = bytes .. string.pack("<I4", END_OF_PAGES)
+    bytes = bytes .. string.pack("<I4", crc32(bytes))
+  end
+  return bytes
+end
+
+-- Files
+
+local function read_file(path)
+  local file, message = fs.open(path, "rb")
+  if file == nil then
+    return nil, message
+  end
+  local bytes = file.readAll() or ""
+  file.close()
+  return bytes
+end
+
+local function write_file(path, bytes)
+  local file, message = fs.open(path, "wb")
+  if file == nil then
+    error(message, 0)
+  end
+  file.write(bytes)
+  file.close()
+end
+
+--- Reads a 2db file, returning nil and the reason if it can't.
+function poster.read(path)
+  local bytes, message = read_file(path)
+  if bytes == nil then
+    return nil, message
+  end
+  return poster.decode(bytes)
+end
+
+--- Reads a 2dba file, returning nil and the reason if it can't.
+function poster.read_array(path)
+  local bytes, message = read_file(path)
+  if bytes == nil then
+    return nil, message
+  end
+  return poster.decode_array(bytes)
+end
+
+function poster.write(path, image)
+  write_file(path, poster.encode(image))
+end
+
+function poster.write_array(path, array)
+  write_file(path, poster.encode_array(array))
+end
+
+return poster
@@ -0,0 +1,33 @@
+
+-- Run-length compression, `count, value` byte pairs of 1 to 255 repetitions
+
+compressors[1] = function(pixels)
+  local parts = {}
+  local i = 1
+  while i <= #pixels do
+    local value = pixels:byte(i)
+    local run = 1
+    while run < 255 and pixels:byte(i + run) == value do
+      run = run + 1
+    end
+    parts[#parts + 1] = string.char(run, value)
+    i = i + run
+  end
+  return table.concat(parts)
+end
+
+decompressors[1] = function(payload, count)
+  if #payload % 2 ~= 0 then
+    return nil
+  end
+  local parts, length = {}, 0
+  for i = 1, #payload, 2 do
+    local run, value = payload:byte(i, i + 1)
+    if run == 0 or length + run > count then
+      return nil
+    end
+    parts[#parts + 1] = string.rep(string.char(value), run)
+    length = length + run
+  end
+  return table.concat(parts)
+end
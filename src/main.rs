@@ -1,176 +1,1621 @@
-mod poster;
+#![allow(clippy::needless_return)]
 
-use clap::{arg, command, value_parser, ArgMatches};
+use _2db::{
+    batch, charset, compression, export, import, info, lua, poster, preview, recover, server, tile,
+    verify, watch, PosterError,
+};
+use clap::parser::ValueSource;
+use clap::{arg, command, value_parser, Arg, ArgMatches, Command};
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
 
 fn main() {
     let matches = make_matches();
 
+    let code = match matches.subcommand() {
+        Some(("convert", sub_matches)) => convert(sub_matches),
+        Some(("batch", sub_matches)) => batch(sub_matches),
+        Some(("info", sub_matches)) => info(sub_matches),
+        Some(("validate", sub_matches)) => validate(sub_matches),
+        Some(("verify", sub_matches)) => verify(sub_matches),
+        Some(("preview", sub_matches)) => preview(sub_matches),
+        Some(("pages", sub_matches)) => pages(sub_matches),
+        Some(("lua", sub_matches)) => lua(sub_matches),
+        Some(("serve", sub_matches)) => serve(sub_matches),
+        _ => unreachable!("A subcommand is required, this shouldn't have happened"),
+    };
+
+    process::exit(code);
+}
+
+/// The command line arguments, with `convert` inserted when they start with a flag so the
+/// `-i <INPUT> -o <OUTPUT> -F <FORMAT>` form from before subcommands keeps working.
+fn args() -> Vec<OsString> {
+    return with_default_subcommand(env::args_os().collect());
+}
+
+/// Inserts `convert` after the program name when `args` start with a flag other than help or
+/// version.
+fn with_default_subcommand(mut args: Vec<OsString>) -> Vec<OsString> {
+    let starts_with_flag = args.get(1).is_some_and(|arg| {
+        let arg = arg.to_string_lossy();
+        arg.starts_with('-') && !matches!(arg.as_ref(), "-h" | "--help" | "-V" | "--version")
+    });
+    if starts_with_flag {
+        args.insert(1, OsString::from("convert"));
+    }
+
+    return args;
+}
+
+/// A poster loaded from the input of a subcommand.
+enum Poster {
+    Image(poster::Img2d),
+    Array(poster::Img2dArray),
+}
+
+/// Reads the input of a subcommand, importing raster images and recovering damaged 2dba
+/// files if asked to. Returns the format it was read in (`None` for raster images) and the
+/// poster, or prints why it couldn't be read.
+fn load_input(matches: &ArgMatches) -> Option<(Option<poster::PosterFormat>, Poster)> {
     let input = matches
         .get_one::<PathBuf>("input")
         .expect("Input argument doesn't exist, this shouldn't have happened");
-    let output = matches
-        .get_one::<PathBuf>("output")
-        .expect("Output argument doesn't exist, this shouldn't have happened");
-    let output_format = matches
-        .get_one::<String>("outformat")
-        .expect("Output format doesn't exist, this shouldn't have happened");
 
     if !input.exists() {
         println!("Input file doesn't exist.");
-        return;
+        return None;
     }
     if input.is_dir() {
         println!("Input can't be a directory.");
-        return;
+        return None;
     }
 
+    let input_format = match input_format(input, matches.get_one::<String>("informat")) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        }
+    };
+
+    let decode_options = poster::DecodeOptions {
+        strict: matches.get_flag("strict"),
+    };
+
+    let poster = match input_format {
+        None => {
+            let options = import_options(matches)?;
+            match import::read_image(input, &options) {
+                Ok(t) => Poster::Image(t),
+                Err(e) => {
+                    println!("Failed to import input image: {}", e);
+                    return None;
+                }
+            }
+        }
+        Some(poster::PosterFormat::Json) => match poster::read_2dj(input) {
+            Ok(t) => Poster::Image(t),
+            Err(e) => {
+                println!("Failed to read input image (2dj): {}", e);
+                return None;
+            }
+        },
+        Some(poster::PosterFormat::Binary) => match poster::read_2db(input, &decode_options) {
+            Ok(t) => Poster::Image(t),
+            Err(e) => {
+                println!("Failed to read input image (2db): {}", e);
+                return None;
+            }
+        },
+        Some(poster::PosterFormat::JsonArray) => match poster::read_2dja(input) {
+            Ok(t) => Poster::Array(t),
+            Err(e) => {
+                println!("Failed to read input image array (2dja): {}", e);
+                return None;
+            }
+        },
+        Some(poster::PosterFormat::BinaryArray) if matches.get_flag("recover") => {
+            match recover::recover_2dba_file(input) {
+                Ok(recovery) => {
+                    print_recovery(input, &recovery);
+                    Poster::Array(recovery.image)
+                }
+                Err(e) => {
+                    println!("Failed to recover input image array (2dba): {}", e);
+                    return None;
+                }
+            }
+        }
+        Some(poster::PosterFormat::BinaryArray) => {
+            match poster::read_2dba(input, &decode_options) {
+                Ok(t) => Poster::Array(t),
+                Err(e) => {
+                    println!("Failed to read input image array (2dba): {}", e);
+                    return None;
+                }
+            }
+        }
+    };
+
+    return Some((input_format, poster));
+}
+
+/// Checks `output` can be written and parses the requested output format, printing why if
+/// either is invalid.
+fn output_format(matches: &ArgMatches) -> Option<(&PathBuf, poster::ImgFormat)> {
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .expect("Output argument doesn't exist, this shouldn't have happened");
+    let output_format = matches
+        .get_one::<String>("outformat")
+        .expect("Output format doesn't exist, this shouldn't have happened");
+
     if output.is_dir() {
         println!("Output can't be a directory.");
-        return;
+        return None;
     }
 
-    if !output.parent().unwrap().exists() {
+    // A bare file name has an empty parent, which is the current directory
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.exists() {
         println!("Output file parent directory doesn't exist.");
-        return;
+        return None;
     }
 
-    let output_format_type: poster::ImgFormat = match output_format.to_lowercase().as_str() {
-        "json" => poster::ImgFormat::JSON,
-        "j" => poster::ImgFormat::JSON,
-        "binary" => poster::ImgFormat::Binary,
-        "bin" => poster::ImgFormat::Binary,
-        "b" => poster::ImgFormat::Binary,
+    return Some((output, parse_output_format(output_format)?));
+}
+
+/// Parses the `-F` output format, printing why if it's invalid.
+fn parse_output_format(output_format: &str) -> Option<poster::ImgFormat> {
+    return match output_format.to_lowercase().as_str() {
+        "json" => Some(poster::ImgFormat::JSON),
+        "j" => Some(poster::ImgFormat::JSON),
+        "binary" => Some(poster::ImgFormat::Binary),
+        "bin" => Some(poster::ImgFormat::Binary),
+        "b" => Some(poster::ImgFormat::Binary),
+        "png" => Some(poster::ImgFormat::PNG),
+        "p" => Some(poster::ImgFormat::PNG),
         _ => {
-            println!("Invalid output format supplied, valid formats are (json,binary).");
-            return;
+            println!("Invalid output format supplied, valid formats are (json,binary,png).");
+            None
         }
     };
+}
 
-    let input_file_extension = match input.extension() {
-        Some(ext) => ext.to_str().unwrap(),
-        None => {
-            println!("Input file has no extension, has to be 2dj/2dja/2db/2dba.");
-            return;
-        }
+/// Runs the `convert` subcommand, returning the exit code. With `--watch` the input is
+/// converted again whenever it changes, until the process is stopped.
+fn convert(matches: &ArgMatches) -> i32 {
+    if !matches.get_flag("watch") {
+        return convert_once(matches);
+    }
+
+    let input = matches
+        .get_one::<PathBuf>("input")
+        .expect("Input argument doesn't exist, this shouldn't have happened");
+    let (output, output_format_type) = match output_format(matches) {
+        Some(t) => t,
+        None => return 2,
     };
+    // Every conversion would change the input again
+    if writes_to(output, &output_format_type, input) {
+        println!("Output can't be the input when watching it.");
+        return 2;
+    }
+
+    let code = convert_once(matches);
+    if code == 2 {
+        return code;
+    }
 
-    let (input_format_type, is_image_array) = match input
-        .extension()
-        .expect("Failed to get extension of input file.")
-        .to_ascii_lowercase()
-        .to_str()
-        .expect("Failed to convert input file extension to str.")
+    return watch_changes(
+        matches,
+        &[watch::WatchRoot::parent_of(input)],
+        |path| {
+            path.file_name() == input.file_name() && !writes_to(output, &output_format_type, path)
+        },
+        |_| {
+            println!("{} changed, converting it again.", input.display());
+            convert_once(matches);
+        },
+    );
+}
+
+/// `file` with its directory resolved, so differently spelled paths to the same place compare
+/// equal even if the file doesn't exist yet.
+fn resolve_path(file: &Path) -> PathBuf {
+    let parent = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    return match (parent.canonicalize(), file.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => file.to_path_buf(),
+    };
+}
+
+/// Whether converting to `output` in `output_format_type` can write `file`.
+fn writes_to(output: &Path, output_format_type: &poster::ImgFormat, file: &Path) -> bool {
+    let extensions: &[&str] = match output_format_type {
+        poster::ImgFormat::JSON => &["2dj", "2dja"],
+        poster::ImgFormat::Binary => &["2db", "2dba"],
+        poster::ImgFormat::PNG => &["png"],
+    };
+    let file = resolve_path(file);
+    if extensions
+        .iter()
+        .any(|extension| resolve_path(&output.with_extension(extension)) == file)
     {
-        "2dj" => (poster::ImgFormat::JSON, false),
-        "2db" => (poster::ImgFormat::Binary, false),
-        "2dja" => (poster::ImgFormat::JSON, true),
-        "2dba" => (poster::ImgFormat::Binary, true),
-        _ => {
-            println!("Invalid input file file extension, has to be 2dj/2dja/2db/2dba.");
-            return;
-        }
+        return true;
+    }
+
+    // The pages of an image array exported as PNG are written as `stem_N.png`
+    if *output_format_type != poster::ImgFormat::PNG {
+        return false;
+    }
+    let output = resolve_path(output);
+    let stem = output.file_stem().map(|stem| stem.to_string_lossy());
+    let name = file.file_name().map(|name| name.to_string_lossy());
+    let page = match (stem, name) {
+        (Some(stem), Some(name)) => name
+            .strip_prefix(&format!("{}_", stem))
+            .and_then(|name| name.strip_suffix(".png"))
+            .map(|page| page.to_string()),
+        _ => None,
     };
 
-    if is_image_array {
-        let image_array: poster::Img2dArray;
+    return file.parent() == output.parent()
+        && page.is_some_and(|page| !page.is_empty() && page.bytes().all(|b| b.is_ascii_digit()));
+}
 
-        if input_format_type == poster::ImgFormat::JSON {
-            image_array = match poster::read_2dja(input) {
-                Ok(t) => t,
-                Err(e) => {
-                    println!("Failed to read input image array (2dja): {}", e);
-                    return;
-                }
+/// Converts the input once, returning the exit code.
+fn convert_once(matches: &ArgMatches) -> i32 {
+    let (output, output_format_type) = match output_format(matches) {
+        Some(t) => t,
+        None => return 2,
+    };
+
+    let image = match load_input(matches) {
+        Some((_, t)) => t,
+        None => return 1,
+    };
+
+    return match image {
+        Poster::Array(image_array) if matches.get_flag("stitch") => {
+            let options = match stitch_options(matches) {
+                Some(t) => t,
+                None => return 2,
             };
-        } else if input_format_type == poster::ImgFormat::Binary {
-            image_array = match poster::read_2dba(input) {
-                Ok(t) => t,
+            match tile::stitch_pages(&image_array, &options) {
+                Ok(image) => write_image(matches, output, &output_format_type, &image),
                 Err(e) => {
-                    println!("Failed to read input image array (2dba): {}", e);
-                    return;
+                    println!("Failed to stitch input image array: {}", e);
+                    1
                 }
-            };
-        } else {
-            panic!("Invalid input format type");
-        }
-
-        let mut out_path = output.clone();
-        if output_format_type == poster::ImgFormat::JSON {
-            out_path.set_extension("2dja");
-            fs::write(
-                out_path,
-                serde_json::to_string(&image_array).expect("Failed to parse image data to JSON"),
-            )
-            .expect("Failed to write to output file.");
-        } else if output_format_type == poster::ImgFormat::Binary {
-            out_path.set_extension("2dba");
-            fs::write(
-                out_path,
-                poster::img_2d_array_to_bytes(&image_array)
-                    .expect("Failed to parse image data to bytes"),
-            )
-            .expect("Failed to write to output file.");
-        }
-    } else {
-        let image: poster::Img2d;
-
-        if input_format_type == poster::ImgFormat::JSON {
-            image = match poster::read_2dj(input) {
-                Ok(t) => t,
-                Err(e) => {
-                    println!("Failed to read input image (2dj): {}", e);
-                    return;
+            }
+        }
+        Poster::Array(image_array) => {
+            write_image_array(matches, output, &output_format_type, &image_array)
+        }
+        Poster::Image(image) => match matches.get_one::<String>("tile") {
+            Some(tile) => {
+                let options = match tile_options(matches, tile) {
+                    Some(t) => t,
+                    None => return 2,
+                };
+                match tile::tile_image(&image, &options) {
+                    Ok(image_array) => {
+                        write_image_array(matches, output, &output_format_type, &image_array)
+                    }
+                    Err(e) => {
+                        println!("Failed to tile input image: {}", e);
+                        1
+                    }
                 }
-            };
-        } else if input_format_type == poster::ImgFormat::Binary {
-            image = match poster::read_2db(input) {
+            }
+            None => write_image(matches, output, &output_format_type, &image),
+        },
+    };
+}
+
+/// Runs the `batch` subcommand, converting every input into the output directory and
+/// printing what happened to each. Returns 1 if any file failed.
+fn batch(matches: &ArgMatches) -> i32 {
+    let patterns: Vec<String> = matches
+        .get_many::<String>("inputs")
+        .expect("Inputs argument doesn't exist, this shouldn't have happened")
+        .cloned()
+        .collect();
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .expect("Output argument doesn't exist, this shouldn't have happened");
+    let output_format = matches
+        .get_one::<String>("outformat")
+        .expect("Output format doesn't exist, this shouldn't have happened");
+
+    if output.exists() && !output.is_dir() {
+        println!("Output has to be a directory.");
+        return 2;
+    }
+
+    let format = match parse_output_format(output_format) {
+        Some(t) => t,
+        None => return 2,
+    };
+    let (import, encode, export) = match (
+        import_options(matches),
+        encode_options(matches),
+        export_options(matches),
+    ) {
+        (Some(import), Some(encode), Some(export)) => (import, encode, export),
+        _ => return 2,
+    };
+    let options = batch::BatchOptions {
+        output: output.clone(),
+        format,
+        decode: poster::DecodeOptions {
+            strict: matches.get_flag("strict"),
+        },
+        import,
+        encode,
+        export,
+        sheet: sheet_options(matches),
+    };
+
+    let inputs = match batch::find_inputs(&patterns, output) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Failed to find inputs: {}", e);
+            return 2;
+        }
+    };
+    if let Err(e) = fs::create_dir_all(output) {
+        println!("Failed to create output directory: {}", e);
+        return 1;
+    }
+
+    let threads = matches
+        .get_one::<u32>("jobs")
+        .map_or(0, |jobs| *jobs as usize);
+    let pool = match rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Failed to start worker threads: {}", e);
+            return 1;
+        }
+    };
+    let results = pool.install(|| batch::convert_all(&inputs, &options));
+    let failed = print_batch_results(&results);
+    if !matches.get_flag("watch") {
+        return if failed > 0 { 1 } else { 0 };
+    }
+
+    // Changes inside the output directory are our own writes
+    let output_dir = output.canonicalize().ok();
+    let not_output = |path: &Path| {
+        return !output_dir.as_ref().is_some_and(|output_dir| {
+            path.canonicalize()
+                .is_ok_and(|path| path.starts_with(output_dir))
+        });
+    };
+    return watch_changes(
+        matches,
+        &batch::watch_roots(&patterns),
+        not_output,
+        |changed| {
+            let changed: HashSet<PathBuf> = changed
+                .iter()
+                .filter_map(|file| file.canonicalize().ok())
+                .collect();
+            // Found again so that new files matching the patterns are picked up too
+            let inputs = match batch::find_inputs(&patterns, output) {
                 Ok(t) => t,
                 Err(e) => {
-                    println!("Failed to read input image (2db): {}", e);
+                    println!("Failed to find inputs: {}", e);
                     return;
                 }
             };
+            let is_changed = |input: &batch::BatchInput| {
+                return input
+                    .file
+                    .canonicalize()
+                    .is_ok_and(|file| changed.contains(&file));
+            };
+            let results = pool.install(|| batch::convert_matching(&inputs, is_changed, &options));
+            if !results.is_empty() {
+                print_batch_results(&results);
+            }
+        },
+    );
+}
+
+/// Prints what happened to every file of a batch and a summary, returning how many failed.
+fn print_batch_results(results: &[batch::BatchResult]) -> usize {
+    let (mut converted, mut skipped, mut failed) = (0, 0, 0);
+    for result in results.iter() {
+        match &result.outcome {
+            batch::Outcome::Converted(files) => {
+                converted += 1;
+                let files: Vec<String> = files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect();
+                println!(
+                    "Converted {} -> {}",
+                    result.input.display(),
+                    files.join(", ")
+                );
+            }
+            batch::Outcome::Skipped(reason) => {
+                skipped += 1;
+                println!("Skipped {}: {}", result.input.display(), reason);
+            }
+            batch::Outcome::Failed(e) => {
+                failed += 1;
+                println!("Failed {}: {}", result.input.display(), e);
+            }
+        }
+    }
+    println!(
+        "{} converted, {} skipped, {} failed.",
+        converted, skipped, failed
+    );
+
+    return failed;
+}
+
+/// Watches `roots` for changes to files `filter` accepts, calling `rebuild` with them after
+/// `--debounce` milliseconds without further changes. Errors are printed and watching
+/// carries on, returns the exit code if it can't start.
+fn watch_changes<P, C>(
+    matches: &ArgMatches,
+    roots: &[watch::WatchRoot],
+    filter: P,
+    rebuild: C,
+) -> i32
+where
+    P: Fn(&Path) -> bool,
+    C: FnMut(Vec<PathBuf>),
+{
+    let debounce = matches
+        .get_one::<u64>("debounce")
+        .expect("Debounce argument doesn't exist, this shouldn't have happened");
+
+    println!("Watching for changes, press Ctrl+C to stop.");
+    let result = watch::watch(
+        roots,
+        Duration::from_millis(*debounce),
+        filter,
+        rebuild,
+        |e| println!("Error while watching: {}", e),
+    );
+    if let Err(e) = result {
+        println!("Failed to watch inputs: {}", e);
+        return 1;
+    }
+
+    return 0;
+}
+
+/// Runs the `pages` subcommand, writing every page of an image array (or only `--page`) to
+/// its own file, returning the exit code.
+fn pages(matches: &ArgMatches) -> i32 {
+    let (output, output_format_type) = match output_format(matches) {
+        Some(t) => t,
+        None => return 2,
+    };
+
+    let image_array = match load_input(matches) {
+        Some((_, Poster::Array(t))) => t,
+        Some((_, Poster::Image(_))) => {
+            println!("Only image arrays (2dja/2dba) have pages.");
+            return 2;
+        }
+        None => return 1,
+    };
+
+    let count = image_array.pages.len();
+    let selected = matches.get_one::<u32>("page").map(|page| *page as usize);
+    if let Some(page) = selected.filter(|&page| page > count) {
+        println!(
+            "Page {} doesn't exist, the image array has {} pages.",
+            page, count
+        );
+        return 2;
+    }
+
+    // Pages are numbered from 1, `book` becomes `book_1`, `book_2`, ...
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    for (index, page) in image_array.pages.iter().enumerate() {
+        if selected.is_some_and(|selected| selected != index + 1) {
+            continue;
+        }
+
+        let page_output = output.with_file_name(format!("{}_{}", stem, index + 1));
+        let code = write_image(matches, &page_output, &output_format_type, page);
+        if code != 0 {
+            return code;
+        }
+    }
+
+    return 0;
+}
+
+/// Runs the `info` subcommand, returning the exit code.
+fn info(matches: &ArgMatches) -> i32 {
+    let input = matches
+        .get_one::<PathBuf>("input")
+        .expect("Input argument doesn't exist, this shouldn't have happened");
+
+    let (format, image) = match load_input(matches) {
+        Some(t) => t,
+        None => return 1,
+    };
+
+    let header = match format.filter(|format| format.encoding() == poster::ImgFormat::Binary) {
+        Some(_) => match fs::read(input)
+            .map_err(PosterError::from)
+            .and_then(|bytes| info::header_info(&bytes))
+        {
+            Ok(t) => Some(t),
+            Err(e) => {
+                println!("Failed to read the header of {}: {}", input.display(), e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let format = format.map_or("image", |format| format.extension());
+
+    if matches.get_flag("json") {
+        let json = match image {
+            Poster::Image(image) => serde_json::json!({
+                "format": format,
+                "header": header,
+                "image": info::image_info(&image),
+            }),
+            Poster::Array(image_array) => serde_json::json!({
+                "format": format,
+                "header": header,
+                "array": info::array_info(&image_array),
+            }),
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json).expect("serializing the info can't fail")
+        );
+        return 0;
+    }
+
+    match header {
+        Some(header) => println!(
+            "Format: {}, version {}{}{}",
+            format,
+            header.version,
+            if header.compressed {
+                ", compressed"
+            } else {
+                ""
+            },
+            if header.checksums { ", checksums" } else { "" }
+        ),
+        None => println!("Format: {}", format),
+    }
+    match image {
+        Poster::Image(image) => print_image_info(&info::image_info(&image)),
+        Poster::Array(image_array) => print_array_info(&info::array_info(&image_array)),
+    }
+
+    return 0;
+}
+
+/// Formats an encoded size for `info`.
+fn size_text(size: Option<usize>) -> String {
+    return size.map_or("unencodable".to_string(), |size| format!("{} bytes", size));
+}
+
+fn print_image_info(image: &info::ImageInfo) {
+    println!("Label: {}", image.label.as_deref().unwrap_or("none"));
+    println!("Tooltip: {}", image.tooltip.as_deref().unwrap_or("none"));
+    println!("Size: {}x{}", image.width, image.height);
+    println!(
+        "Pixels: {} of {}",
+        image.pixel_count, image.expected_pixel_count
+    );
+    if image.out_of_palette > 0 {
+        println!("Pixels without a palette entry: {}", image.out_of_palette);
+    }
+
+    // Swatches are only drawn for terminals, escapes would clutter files and pipes
+    let color_mode = io::stdout().is_terminal().then(preview::ColorMode::detect);
+    println!(
+        "Palette: {} colors, {} used",
+        image.palette.len(),
+        image.colors_used
+    );
+    for (index, &color) in image.palette.iter().enumerate() {
+        let swatch = color_mode.map_or(String::new(), |mode| preview::swatch(color, mode) + " ");
+        println!("  {}{:>3} #{:06X}", swatch, index, color);
+    }
+
+    println!(
+        "Encoded size: {} as 2dj, {} as 2db",
+        size_text(image.size_2dj),
+        size_text(image.size_2db)
+    );
+}
+
+fn print_array_info(image_array: &info::ArrayInfo) {
+    println!("Title: {}", image_array.title.as_deref().unwrap_or("none"));
+    println!("Size: {}x{} pages", image_array.width, image_array.height);
+    println!("Pages: {}", image_array.pages.len());
+    println!(
+        "Encoded size: {} as 2dja, {} as 2dba",
+        size_text(image_array.size_2dja),
+        size_text(image_array.size_2dba)
+    );
+
+    println!(
+        "  {:>4}  {:>11}  {:>13}  {:>7}  {:>4}  Label",
+        "Page", "Size", "Pixels", "Palette", "Used"
+    );
+    for (index, page) in image_array.pages.iter().enumerate() {
+        println!(
+            "  {:>4}  {:>11}  {:>13}  {:>7}  {:>4}  {}",
+            index + 1,
+            format!("{}x{}", page.width, page.height),
+            format!("{}/{}", page.pixel_count, page.expected_pixel_count),
+            page.palette.len(),
+            page.colors_used,
+            page.label.as_deref().unwrap_or("")
+        );
+    }
+}
+
+/// Runs the `lua` subcommand, returning the exit code.
+fn lua(matches: &ArgMatches) -> i32 {
+    let options = match binary_options(matches) {
+        Some(t) => t,
+        None => return 2,
+    };
+    let library = match lua::generate_lua(&options) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Failed to generate the Lua library: {}", e);
+            return 2;
+        }
+    };
+
+    match matches.get_one::<PathBuf>("output") {
+        Some(output) => {
+            if let Err(e) = fs::write(output, library) {
+                println!("Failed to write output file: {}", e);
+                return 1;
+            }
+        }
+        None => print!("{}", library),
+    }
+
+    return 0;
+}
+
+/// Runs the `serve` subcommand, returning the exit code if the server fails to start.
+fn serve(matches: &ArgMatches) -> i32 {
+    let root = matches
+        .get_one::<PathBuf>("root")
+        .expect("Root argument doesn't exist, this shouldn't have happened");
+    let address = matches
+        .get_one::<String>("address")
+        .expect("Address argument doesn't exist, this shouldn't have happened");
+
+    if !root.is_dir() {
+        println!("Directory to serve doesn't exist.");
+        return 2;
+    }
+
+    let encode = match binary_options(matches) {
+        Some(t) => t,
+        None => return 2,
+    };
+    let import = match import_options(matches) {
+        Some(t) => t,
+        None => return 2,
+    };
+    let options = server::ServeOptions {
+        root: root.clone(),
+        address: address.clone(),
+        encode,
+        import,
+    };
+
+    println!("Serving {} on http://{}", root.display(), address);
+    let result = server::serve(&options, |method, url, status| {
+        println!("{} {} {}", method, url, status);
+    });
+    if let Err(e) = result {
+        println!("Failed to start the server: {}", e);
+        return 1;
+    }
+
+    return 0;
+}
+
+/// Prints what recovering `input` had to skip.
+fn print_recovery(input: &Path, recovery: &recover::Recovery) {
+    if recovery.is_complete() {
+        println!(
+            "{} is undamaged, every page was recovered.",
+            input.display()
+        );
+        return;
+    }
+
+    println!(
+        "Recovered {} pages from {}, skipped:",
+        recovery.image.pages.len(),
+        input.display()
+    );
+    for skipped in recovery.skipped.iter() {
+        println!(
+            "  {} bytes at byte {} after page {}: {}",
+            skipped.length, skipped.offset, skipped.after_page, skipped.error
+        );
+    }
+    if let Some(e) = &recovery.file_error {
+        println!("  {}", e);
+    }
+}
+
+/// Works out the format of `input` from `informat`, its magic signature, its extension or its
+/// content, in that order. `None` means the input is a raster image that has to be imported.
+fn input_format(
+    input: &Path,
+    informat: Option<&String>,
+) -> Result<Option<poster::PosterFormat>, String> {
+    return match informat {
+        Some(informat) if informat.eq_ignore_ascii_case("image") => Ok(None),
+        Some(informat) => match poster::PosterFormat::from_extension(informat) {
+            Some(format) => Ok(Some(format)),
+            None => Err(
+                "Invalid input format supplied, valid formats are (2dj,2dja,2db,2dba,image)."
+                    .to_string(),
+            ),
+        },
+        None if import::is_image_path(input) && read_magic(input).is_none() => Ok(None),
+        None => match read_magic(input).or_else(|| {
+            input
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(poster::PosterFormat::from_extension)
+        }) {
+            Some(format) => Ok(Some(format)),
+            None => {
+                let bytes =
+                    fs::read(input).map_err(|e| format!("Failed to read input file: {}", e))?;
+                match poster::detect_format(&bytes) {
+                    Some(format) => Ok(Some(format)),
+                    None => {
+                        Err("Could not detect input format, set it with --informat.".to_string())
+                    }
+                }
+            }
+        },
+    };
+}
+
+/// The binary format `input` has the magic signature of, if any.
+fn read_magic(input: &Path) -> Option<poster::PosterFormat> {
+    let mut signature: Vec<u8> = Vec::new();
+    fs::File::open(input)
+        .ok()?
+        .take(poster::IMAGE_MAGIC.len() as u64)
+        .read_to_end(&mut signature)
+        .ok()?;
+
+    return poster::detect_magic(&signature);
+}
+
+/// Runs the `validate` subcommand, returning the exit code.
+fn validate(matches: &ArgMatches) -> i32 {
+    let input = matches
+        .get_one::<PathBuf>("input")
+        .expect("Input argument doesn't exist, this shouldn't have happened");
+
+    let decode_options = poster::DecodeOptions {
+        strict: !matches.get_flag("lenient"),
+    };
+
+    let result = match input_format(input, matches.get_one::<String>("informat")) {
+        Ok(Some(poster::PosterFormat::Json)) => poster::read_2dj(input).and_then(|t| t.validate()),
+        Ok(Some(poster::PosterFormat::Binary)) => {
+            poster::read_2db(input, &decode_options).and_then(|t| t.validate())
+        }
+        Ok(Some(poster::PosterFormat::JsonArray)) => {
+            poster::read_2dja(input).and_then(|t| t.validate())
+        }
+        Ok(Some(poster::PosterFormat::BinaryArray)) => {
+            poster::read_2dba(input, &decode_options).and_then(|t| t.validate())
+        }
+        Ok(None) => {
+            println!("Only 2dj/2dja/2db/2dba files can be validated.");
+            return 2;
+        }
+        Err(e) => {
+            println!("{}", e);
+            return 2;
+        }
+    };
+
+    return match result {
+        Ok(()) => {
+            println!("{} is valid.", input.display());
+            0
+        }
+        Err(PosterError::Invalid(violations)) => {
+            println!("{} is invalid:", input.display());
+            for violation in violations {
+                println!("  {}", violation);
+            }
+            1
+        }
+        Err(e) => {
+            println!("Failed to read {}: {}", input.display(), e);
+            1
+        }
+    };
+}
+
+/// Runs the `verify` subcommand, returning the exit code.
+fn verify(matches: &ArgMatches) -> i32 {
+    let input = matches
+        .get_one::<PathBuf>("input")
+        .expect("Input argument doesn't exist, this shouldn't have happened");
+
+    let format = input_format(input, matches.get_one::<String>("informat"));
+    let result = match format {
+        Ok(Some(poster::PosterFormat::Binary)) => verify::verify_2db_file(input),
+        Ok(Some(poster::PosterFormat::BinaryArray)) => verify::verify_2dba_file(input),
+        Ok(_) => {
+            println!("Only 2db/2dba files can be verified.");
+            return 2;
+        }
+        Err(e) => {
+            println!("{}", e);
+            return 2;
+        }
+    };
+    let verification = match result {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Failed to read {}: {}", input.display(), e);
+            return 1;
+        }
+    };
+
+    if !verification.checksums {
+        println!(
+            "{} has no checksums, only damage that breaks parsing can be found.",
+            input.display()
+        );
+    }
+    if verification.is_intact() {
+        println!("{} is intact.", input.display());
+        return 0;
+    }
+
+    println!("{} is damaged:", input.display());
+    for (page, e) in verification.damaged_pages.iter() {
+        println!("  page {}: {}", page + 1, e);
+    }
+    if let Some(e) = verification.file_error {
+        println!("  {}", e);
+    }
+    if let Ok(Some(poster::PosterFormat::BinaryArray)) = format {
+        println!(
+            "{} of {} readable pages are damaged.",
+            verification.damaged_pages.len(),
+            verification.pages
+        );
+    }
+
+    return 1;
+}
+
+/// Runs the `preview` subcommand, returning the exit code.
+fn preview(matches: &ArgMatches) -> i32 {
+    let input = matches
+        .get_one::<PathBuf>("input")
+        .expect("Input argument doesn't exist, this shouldn't have happened");
+    let page = *matches
+        .get_one::<u32>("page")
+        .expect("Page argument doesn't exist, this shouldn't have happened");
+
+    let color_mode = match matches.get_one::<String>("color-mode") {
+        Some(name) => match preview::ColorMode::from_name(name) {
+            Some(t) => t,
+            None => {
+                println!("Invalid color mode supplied, valid modes are (truecolor,256).");
+                return 2;
+            }
+        },
+        None => preview::ColorMode::detect(),
+    };
+    let max_width = match matches.get_one::<u32>("width") {
+        Some(width) => *width,
+        None => terminal_size::terminal_size().map_or(80, |(width, _)| width.0 as u32),
+    };
+
+    // Single images are treated as an array of one page
+    let pages = match load_input(matches) {
+        Some((_, Poster::Image(image))) => vec![image],
+        Some((_, Poster::Array(image_array))) => image_array.pages,
+        None => return 1,
+    };
+
+    let count = pages.len();
+    let image = match pages.into_iter().nth(page as usize - 1) {
+        Some(t) => t,
+        None => {
+            println!(
+                "Page {} doesn't exist, {} has {} pages.",
+                page,
+                input.display(),
+                count
+            );
+            return 2;
+        }
+    };
+
+    let options = preview::PreviewOptions {
+        max_width,
+        color_mode,
+        ..Default::default()
+    };
+    match preview::render_preview(&image, &options) {
+        Ok(preview) => print!("{}", preview),
+        Err(e) => {
+            println!("Failed to draw input image: {}", e);
+            return 1;
+        }
+    }
+
+    return 0;
+}
+
+/// Builds the 2dj/2db encoding options from the command line, printing why if they're invalid.
+fn encode_options(matches: &ArgMatches) -> Option<poster::EncodeOptions> {
+    let unrepresentable = match matches.get_one::<String>("replace-unrepresentable") {
+        Some(replacement) => {
+            let mut chars = replacement.chars();
+            match (chars.next().and_then(charset::encode_char), chars.next()) {
+                (Some(byte), None) => charset::Unrepresentable::Replace(byte),
+                _ => {
+                    println!("Invalid replacement character supplied, has to be a single character in the ComputerCraft charset.");
+                    return None;
+                }
+            }
+        }
+        None => charset::Unrepresentable::Error,
+    };
+
+    return Some(poster::EncodeOptions {
+        validate: !matches.get_flag("no-validate"),
+        unrepresentable,
+        ..binary_options(matches)?
+    });
+}
+
+/// Builds the binary format options (version, compression and checksums) from the command
+/// line, printing why if they're invalid.
+fn binary_options(matches: &ArgMatches) -> Option<poster::EncodeOptions> {
+    let version = matches
+        .get_one::<u8>("format-version")
+        .expect("Format version doesn't exist, this shouldn't have happened");
+    let version = poster::FormatVersion::from_number(*version)
+        .expect("Format version is range checked, this shouldn't have happened");
+
+    let compression = matches
+        .get_one::<String>("compression")
+        .expect("Compression doesn't exist, this shouldn't have happened");
+    let compression = match compression::Compression::from_name(compression) {
+        Some(t) => t,
+        None => {
+            println!("Invalid compression supplied, valid compressions are (none,rle,deflate).");
+            return None;
+        }
+    };
+
+    return Some(poster::EncodeOptions {
+        version,
+        compression,
+        checksums: matches.get_flag("checksums"),
+        ..Default::default()
+    });
+}
+
+/// Warns that absent labels, tooltips or titles are about to be written as empty ones, the
+/// only way v1 binary output can hold them, unless the version was asked for.
+fn warn_absent_strings(matches: &ArgMatches, options: &poster::EncodeOptions, absent: bool) {
+    if !absent
+        || options.version != poster::FormatVersion::V1
+        || matches.value_source("format-version") == Some(ValueSource::CommandLine)
+    {
+        return;
+    }
+
+    println!("Warning: absent labels, tooltips and titles are written as empty ones by format version 1, use --format-version 2 to keep them absent or --format-version 1 to silence this.");
+}
+
+/// Writes `image_array` to `output` (replacing its extension) in the requested format,
+/// returning the exit code.
+fn write_image_array(
+    matches: &ArgMatches,
+    output: &Path,
+    output_format_type: &poster::ImgFormat,
+    image_array: &poster::Img2dArray,
+) -> i32 {
+    let mut out_path = output.to_path_buf();
+    if *output_format_type == poster::ImgFormat::JSON {
+        out_path.set_extension("2dja");
+        let options = match encode_options(matches) {
+            Some(t) => t,
+            None => return 2,
+        };
+        if let Err(e) = poster::write_2dja(&out_path, image_array, &options) {
+            println!("Failed to write output file: {}", e);
+            return 1;
+        }
+    } else if *output_format_type == poster::ImgFormat::Binary {
+        out_path.set_extension("2dba");
+        let options = match encode_options(matches) {
+            Some(t) => t,
+            None => return 2,
+        };
+        warn_absent_strings(matches, &options, image_array.has_absent_strings());
+        if let Err(e) = poster::write_2dba(&out_path, image_array, &options) {
+            println!("Failed to write output file: {}", e);
+            return 1;
+        }
+    } else if *output_format_type == poster::ImgFormat::PNG {
+        out_path.set_extension("png");
+        let options = match export_options(matches) {
+            Some(t) => t,
+            None => return 2,
+        };
+        if let Some(sheet) = sheet_options(matches) {
+            if let Err(e) = export::write_contact_sheet(&out_path, image_array, &options, &sheet) {
+                println!("Failed to write output file: {}", e);
+                return 1;
+            }
         } else {
-            panic!("Invalid input format type");
+            if let Err(e) = export::write_page_pngs(&out_path, image_array, &options) {
+                println!("Failed to write output files: {}", e);
+                return 1;
+            }
         }
+    }
+
+    return 0;
+}
 
-        let mut out_path = output.clone();
-        if output_format_type == poster::ImgFormat::JSON {
-            out_path.set_extension("2dj");
-            fs::write(
-                out_path,
-                serde_json::to_string(&image).expect("Failed to parse image data to JSON"),
-            )
-            .expect("Failed to write to output file.");
-        } else if output_format_type == poster::ImgFormat::Binary {
-            out_path.set_extension("2db");
-            fs::write(
-                out_path,
-                poster::img_2d_to_bytes(&image).expect("Failed to parse image data to bytes"),
-            )
-            .expect("Failed to write to output file.");
+/// Writes `image` to `output` (replacing its extension) in the requested format,
+/// returning the exit code.
+fn write_image(
+    matches: &ArgMatches,
+    output: &Path,
+    output_format_type: &poster::ImgFormat,
+    image: &poster::Img2d,
+) -> i32 {
+    let mut out_path = output.to_path_buf();
+    if *output_format_type == poster::ImgFormat::JSON {
+        out_path.set_extension("2dj");
+        let options = match encode_options(matches) {
+            Some(t) => t,
+            None => return 2,
+        };
+        if let Err(e) = poster::write_2dj(&out_path, image, &options) {
+            println!("Failed to write output file: {}", e);
+            return 1;
+        }
+    } else if *output_format_type == poster::ImgFormat::Binary {
+        out_path.set_extension("2db");
+        let options = match encode_options(matches) {
+            Some(t) => t,
+            None => return 2,
+        };
+        warn_absent_strings(matches, &options, image.has_absent_strings());
+        if let Err(e) = poster::write_2db(&out_path, image, &options) {
+            println!("Failed to write output file: {}", e);
+            return 1;
+        }
+    } else if *output_format_type == poster::ImgFormat::PNG {
+        out_path.set_extension("png");
+        let options = match export_options(matches) {
+            Some(t) => t,
+            None => return 2,
+        };
+        if let Err(e) = export::write_png(&out_path, image, &options) {
+            println!("Failed to write output file: {}", e);
+            return 1;
         }
     }
+
+    return 0;
+}
+
+/// Builds the tiling options from the command line, printing why if they're invalid.
+fn tile_options(matches: &ArgMatches, tile: &str) -> Option<tile::TileOptions> {
+    let (page_width, page_height) = match tile::parse_page_size(tile) {
+        Some(t) => t,
+        None => {
+            println!("Invalid tile size supplied, has to be WIDTHxHEIGHT or a single size.");
+            return None;
+        }
+    };
+
+    let pad_color = matches
+        .get_one::<String>("pad-color")
+        .expect("Pad color doesn't exist, this shouldn't have happened");
+    let pad_color = match poster::parse_hex_color(pad_color) {
+        Some(t) => t,
+        None => {
+            println!("Invalid pad color supplied, has to be a RRGGBB hex color.");
+            return None;
+        }
+    };
+
+    return Some(tile::TileOptions {
+        page_width,
+        page_height,
+        label: matches.get_one::<String>("label").cloned(),
+        title: matches.get_one::<String>("title").cloned(),
+        pad_color,
+    });
+}
+
+/// Builds the stitching options from the command line, printing why if they're invalid.
+fn stitch_options(matches: &ArgMatches) -> Option<tile::StitchOptions> {
+    let import = import_options(matches)?;
+
+    let fill_color = matches
+        .get_one::<String>("pad-color")
+        .expect("Pad color doesn't exist, this shouldn't have happened");
+    let fill_color = match poster::parse_hex_color(fill_color) {
+        Some(t) => t,
+        None => {
+            println!("Invalid pad color supplied, has to be a RRGGBB hex color.");
+            return None;
+        }
+    };
+
+    return Some(tile::StitchOptions {
+        quantizer: import.quantizer,
+        dither: import.dither,
+        dither_strength: import.dither_strength,
+        label: import.label,
+        fill_color,
+    });
+}
+
+/// Builds the PNG export options from the command line, printing why if they're invalid.
+fn export_options(matches: &ArgMatches) -> Option<export::ExportOptions> {
+    let error_color = matches
+        .get_one::<String>("error-color")
+        .expect("Error color doesn't exist, this shouldn't have happened");
+    let error_color = match poster::parse_hex_color(error_color) {
+        Some(t) => t,
+        None => {
+            println!("Invalid error color supplied, has to be a RRGGBB hex color.");
+            return None;
+        }
+    };
+
+    return Some(export::ExportOptions { error_color });
+}
+
+/// The contact sheet options if `--sheet` was given.
+fn sheet_options(matches: &ArgMatches) -> Option<export::ContactSheetOptions> {
+    if !matches.get_flag("sheet") {
+        return None;
+    }
+
+    return Some(export::ContactSheetOptions {
+        columns: matches.get_one::<u32>("columns").copied(),
+        page_numbers: matches.get_flag("page-numbers"),
+        ..Default::default()
+    });
+}
+
+/// Builds the raster import options from the command line, printing why if they're invalid.
+fn import_options(matches: &ArgMatches) -> Option<import::ImportOptions> {
+    let quantizer = matches
+        .get_one::<String>("quantizer")
+        .expect("Quantizer doesn't exist, this shouldn't have happened");
+    let quantizer = match import::Quantizer::from_name(quantizer) {
+        Some(t) => t,
+        None => {
+            println!(
+                "Invalid quantizer supplied, valid quantizers are (median-cut,kmeans,octree)."
+            );
+            return None;
+        }
+    };
+
+    let dither = matches
+        .get_one::<String>("dither")
+        .expect("Dither doesn't exist, this shouldn't have happened");
+    let dither = match import::Dither::from_name(dither) {
+        Some(t) => t,
+        None => {
+            println!("Invalid dither supplied, valid dithers are (none,floyd-steinberg,atkinson,sierra,bayer4,bayer8).");
+            return None;
+        }
+    };
+
+    // A palette is either given inline or as the path of a file listing the colours
+    let palette = match matches.get_one::<String>("palette") {
+        Some(palette) => {
+            let palette_file = PathBuf::from(palette);
+            let palette = if palette_file.is_file() {
+                match fs::read_to_string(&palette_file) {
+                    Ok(t) => t,
+                    Err(e) => {
+                        println!("Failed to read palette file: {}", e);
+                        return None;
+                    }
+                }
+            } else {
+                palette.clone()
+            };
+
+            match import::parse_palette(&palette) {
+                Some(t) => Some(t),
+                None => {
+                    println!("Invalid palette supplied, has to be \"cc\" or a list of RRGGBB hex colors.");
+                    return None;
+                }
+            }
+        }
+        None => None,
+    };
+
+    return Some(import::ImportOptions {
+        quantizer,
+        max_colors: *matches
+            .get_one::<u8>("colors")
+            .expect("Colors argument doesn't exist, this shouldn't have happened")
+            as usize,
+        palette,
+        dither,
+        dither_strength: *matches
+            .get_one::<f32>("dither-strength")
+            .expect("Dither strength doesn't exist, this shouldn't have happened"),
+        label: matches.get_one::<String>("label").cloned(),
+        tooltip: matches.get_one::<String>("tooltip").cloned(),
+    });
+}
+
+/// Arguments choosing how the input of a subcommand is read.
+fn load_args() -> Vec<Arg> {
+    return vec![
+        arg!(--informat <FORMAT> "Input format (\"2dj\", \"2dja\", \"2db\", \"2dba\" or \"image\"), overrides the file extension")
+            .required(false)
+            .value_parser(value_parser!(String)),
+        arg!(--recover "Skip damaged pages of 2dba input instead of failing, printing what was lost"),
+        arg!(--strict "Reject 2db/2dba input with trailing bytes, page length mismatches or pixels outside the palette"),
+    ];
+}
+
+/// Arguments controlling how raster images are imported.
+fn import_args() -> Vec<Arg> {
+    return vec![
+        arg!(--quantizer <QUANTIZER> "Quantizer used when importing images (\"median-cut\", \"kmeans\" or \"octree\")")
+            .required(false)
+            .default_value("median-cut")
+            .value_parser(value_parser!(String)),
+        arg!(--colors <COLORS> "Maximum palette size when importing images")
+            .required(false)
+            .default_value("255")
+            .value_parser(value_parser!(u8).range(1..)),
+        arg!(--palette <PALETTE> "Fixed palette for imported images (\"cc\", RRGGBB colors separated by commas, or a file of them)")
+            .required(false)
+            .value_parser(value_parser!(String)),
+        arg!(--dither <DITHER> "Dithering used when importing images (\"none\", \"floyd-steinberg\", \"atkinson\", \"sierra\", \"bayer4\" or \"bayer8\")")
+            .required(false)
+            .default_value("none")
+            .value_parser(value_parser!(String)),
+        arg!(--"dither-strength" <STRENGTH> "Error diffusion strength, 1.0 diffuses the full error")
+            .required(false)
+            .default_value("1.0")
+            .value_parser(value_parser!(f32)),
+        arg!(--label <LABEL> "Label of imported images, or base of the page labels when tiling")
+            .required(false)
+            .value_parser(value_parser!(String)),
+        arg!(--tooltip <TOOLTIP> "Tooltip of imported images")
+            .required(false)
+            .value_parser(value_parser!(String)),
+    ];
+}
+
+/// Arguments choosing the output of a subcommand and how it's written.
+fn output_args() -> Vec<Arg> {
+    return [
+        arg!(-o --output <OUTPUT_FILE> "Sets output file (extension is set automatically, do not set one)")
+            .required(true)
+            .value_parser(value_parser!(PathBuf)),
+    ]
+    .into_iter()
+    .chain(write_args())
+    .collect();
+}
+
+/// Arguments choosing how output is written, apart from where to.
+fn write_args() -> Vec<Arg> {
+    return [
+        arg!(-F --outformat <FORMAT> "Output format (\"binary\", \"json\" or \"png\")")
+            .required(true)
+            .value_parser(value_parser!(String)),
+        arg!(--"error-color" <COLOR> "Color of pixels outside the palette when exporting PNGs (RRGGBB)")
+            .required(false)
+            .default_value("ff00ff")
+            .value_parser(value_parser!(String)),
+        arg!(--"no-validate" "Write the output even if it fails validation"),
+        arg!(--"replace-unrepresentable" <CHAR> "Write this character in place of characters the ComputerCraft charset doesn't have instead of failing")
+            .required(false)
+            .value_parser(value_parser!(String)),
+    ]
+    .into_iter()
+    .chain(binary_args())
+    .collect();
+}
+
+/// Arguments controlling how image arrays are exported as PNG.
+fn sheet_args() -> Vec<Arg> {
+    return vec![
+        arg!(--sheet "Export image arrays as one contact sheet instead of a PNG per page"),
+        arg!(--columns <COLUMNS> "Pages per row of the contact sheet")
+            .required(false)
+            .value_parser(value_parser!(u32).range(1..)),
+        arg!(--"page-numbers" "Draw page numbers on the contact sheet"),
+    ];
+}
+
+/// Arguments for converting inputs again whenever they change.
+fn watch_args() -> Vec<Arg> {
+    return vec![
+        arg!(--watch "Keep running and convert inputs again whenever they change"),
+        arg!(--debounce <MILLISECONDS> "How long to wait for more changes before converting, so a burst of saves converts once")
+            .required(false)
+            .default_value("300")
+            .value_parser(value_parser!(u64)),
+    ];
+}
+
+/// Arguments choosing the layout of binary output.
+fn binary_args() -> Vec<Arg> {
+    return vec![
+        arg!(--"format-version" <VERSION> "Version of the binary format to write, 2 is needed for compression and checksums")
+            .required(false)
+            .default_value("1")
+            .value_parser(value_parser!(u8).range(1..=2)),
+        arg!(--checksums "Write CRC32 checksums in binary output so damage can be found with verify"),
+        arg!(--compression <SCHEME> "Compression of the pixels in binary output (none,rle,deflate)")
+            .required(false)
+            .default_value("none")
+            .value_parser(value_parser!(String)),
+    ];
 }
 
 fn make_matches() -> ArgMatches {
     return command!()
-        .arg(
-            arg!(-i --input <INPUT_FILE> "Sets input image file (use file extension to specify format)")
-                .required(true)
-                .value_parser(value_parser!(PathBuf)),
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new("convert")
+                .about("Converts between posters and raster images, the default when the arguments start with a flag")
+                .arg(
+                    arg!(-i --input <INPUT_FILE> "Sets input image file (format is taken from the extension or detected from the content)")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(load_args())
+                .args(import_args())
+                .args(output_args())
+                .arg(
+                    arg!(--tile <SIZE> "Split a single image into an image array of pages of this size (WIDTHxHEIGHT)")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--stitch "Stitch the pages of a tiled image array back into a single image"),
+                )
+                .arg(
+                    arg!(--"pad-color" <COLOR> "Color used to pad edge pages when tiling, or to fill gaps when stitching (RRGGBB)")
+                        .required(false)
+                        .default_value("000000")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--title <TITLE> "Title of tiled image arrays")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .args(sheet_args())
+                .args(watch_args()),
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Converts directories or glob patterns of files in parallel, mirroring them into an output directory")
+                .arg(
+                    arg!(<INPUTS> ... "Directories, files or glob patterns (like \"art/**/*.png\") to convert")
+                        .id("inputs")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(-o --output <OUTPUT_DIR> "Directory the converted files are written to, keeping their place in the input tree")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(write_args())
+                .args(import_args())
+                .args(sheet_args())
+                .args(watch_args())
+                .arg(arg!(--strict "Reject 2db/2dba input with trailing bytes, page length mismatches or pixels outside the palette"))
+                .arg(
+                    arg!(-j --jobs <JOBS> "Number of files converted at once, the number of cores if not set")
+                        .required(false)
+                        .value_parser(value_parser!(u32).range(1..)),
+                ),
         )
-        .arg(
-            arg!(-o --output <OUTPUT_FILE> "Sets output file (extension is set automatically, do not set one)")
-                .required(true)
-                .value_parser(value_parser!(PathBuf)),
+        .subcommand(
+            Command::new("info")
+                .about("Prints what a poster or raster image contains")
+                .arg(
+                    arg!(<INPUT> "Poster or raster image to describe")
+                        .id("input")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(load_args())
+                .args(import_args())
+                .arg(arg!(--json "Print the summary as JSON")),
         )
-        .arg(
-            arg!(-F --outformat <FORMAT> "Output format (\"binary\" or \"json\")")
-                .required(true)
-                .value_parser(value_parser!(String))
+        .subcommand(
+            Command::new("validate")
+                .about("Checks a poster for structural problems, exits with 1 if it has any")
+                .arg(
+                    arg!(<INPUT> "Poster file to check")
+                        .id("input")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--informat <FORMAT> "Input format (\"2dj\", \"2dja\", \"2db\" or \"2dba\"), overrides the file extension")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--lenient "Ignore trailing bytes and page length mismatches in 2db/2dba files"),
+                ),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("verify")
+                .about("Checks a 2db/2dba against its checksums and lists damaged pages, exits with 1 if any are")
+                .arg(
+                    arg!(<INPUT> "Binary poster file to check")
+                        .id("input")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--informat <FORMAT> "Input format (\"2db\" or \"2dba\"), overrides the file extension")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("preview")
+                .about("Draws a poster in the terminal, downscaled to fit its width")
+                .arg(
+                    arg!(<INPUT> "Poster or raster image to draw")
+                        .id("input")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(load_args())
+                .args(import_args())
+                .arg(
+                    arg!(--page <PAGE> "Page of an image array to draw, starting at 1")
+                        .required(false)
+                        .default_value("1")
+                        .value_parser(value_parser!(u32).range(1..)),
+                )
+                .arg(
+                    arg!(--width <COLUMNS> "Maximum width in columns, the terminal width if not set")
+                        .required(false)
+                        .value_parser(value_parser!(u32).range(1..)),
+                )
+                .arg(
+                    arg!(--"color-mode" <MODE> "Colors to draw with (\"truecolor\" or \"256\"), detected from COLORTERM if not set")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("pages")
+                .about("Writes every page of an image array to its own file, numbered from 1")
+                .arg(
+                    arg!(<INPUT> "Image array (2dja/2dba) to split")
+                        .id("input")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(load_args())
+                .args(import_args())
+                .args(output_args())
+                .arg(
+                    arg!(--page <PAGE> "Only write this page, starting at 1")
+                        .required(false)
+                        .value_parser(value_parser!(u32).range(1..)),
+                ),
+        )
+        .subcommand(
+            Command::new("lua")
+                .about("Generates a Lua library for ComputerCraft that reads and writes 2db/2dba files like the binary output options given")
+                .arg(
+                    arg!(-o --output <OUTPUT_FILE> "File to write the library to, printed if not set")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(binary_args()),
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("Serves a directory of posters over HTTP, converting them to the format each request asks for")
+                .arg(
+                    arg!([ROOT] "Directory to serve")
+                        .id("root")
+                        .default_value(".")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--address <ADDRESS> "Address to listen on")
+                        .required(false)
+                        .default_value("127.0.0.1:8080")
+                        .value_parser(value_parser!(String)),
+                )
+                .args(import_args())
+                .args(binary_args()),
+        )
+        .get_matches_from(args());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(args: &[&str]) -> Vec<OsString> {
+        return with_default_subcommand(args.iter().map(OsString::from).collect());
+    }
+
+    #[test]
+    fn defaults_to_convert_for_flags() {
+        assert_eq!(
+            rewrite(&["2db", "-i", "a.2dj", "-o", "a.2db"]),
+            rewrite(&["2db", "convert", "-i", "a.2dj", "-o", "a.2db"])
+        );
+        assert_eq!(rewrite(&["2db", "info", "a.2dj"]).len(), 3);
+        assert_eq!(rewrite(&["2db", "--help"]).len(), 2);
+        assert_eq!(rewrite(&["2db", "-V"]).len(), 2);
+        assert_eq!(rewrite(&["2db"]).len(), 1);
+    }
 }
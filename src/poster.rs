@@ -1,9 +1,110 @@
+use crate::charset;
+use crate::charset::Unrepresentable;
+use crate::compression;
+use crate::compression::Compression;
+use crate::cursor::{ByteCursor, ReadCursor};
+use crate::error::PosterError;
+use crate::validate::{image_violations, title_violations, MAX_STRING_LENGTH};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fmt::Formatter;
 use std::fs::File;
-use std::io::{BufReader, ErrorKind, Read};
-use std::path::PathBuf;
-use std::{fmt, io, u32};
+use std::io::{BufReader, BufWriter, Chain, Cursor, Read, Write};
+use std::path::Path;
+
+/// Largest palette the binary format can describe, its length is stored as a `u8`.
+pub const MAX_PALETTE_LENGTH: usize = u8::MAX as usize;
+
+/// Length prefix written for an absent (`None`) label, tooltip or title, so that it reads back
+/// as `None` rather than as an empty string.
+pub const ABSENT_STRING_LENGTH: u16 = u16::MAX;
+
+/// Signature at the start of v2 2db files.
+pub const IMAGE_MAGIC: [u8; 4] = [0x89, b'2', b'D', b'B'];
+/// Signature at the start of v2 2dba files.
+pub const ARRAY_MAGIC: [u8; 4] = [0x89, b'2', b'D', b'A'];
+
+/// Feature flag of v2 files whose pixels sections are compressed, see [`crate::compression`].
+pub const FLAG_COMPRESSED: u8 = 0b0000_0001;
+
+/// Feature flag of v2 files carrying CRC32 checksums. A 2db ends with the checksum of every
+/// byte before it. In a 2dba every page is followed by the checksum of its bytes, and the
+/// pages end with [`END_OF_PAGES`] in place of a page length followed by the checksum of every
+/// byte before it.
+pub const FLAG_CHECKSUMS: u8 = 0b0000_0010;
+
+/// Feature flags this version of the library understands.
+const SUPPORTED_FLAGS: u8 = FLAG_COMPRESSED | FLAG_CHECKSUMS;
+
+/// Page length marking the end of the pages in 2dba files with checksums.
+pub const END_OF_PAGES: u32 = u32::MAX;
+
+/// Layout versions of the binary formats.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FormatVersion {
+    /// The original headerless layout. Absent strings are written as empty ones because
+    /// readers of this layout don't know [`ABSENT_STRING_LENGTH`].
+    V1,
+    /// The magic signature, a version byte and a feature flags byte, followed by the v1
+    /// layout.
+    V2,
+}
+
+impl FormatVersion {
+    pub fn from_number(number: u8) -> Option<FormatVersion> {
+        return match number {
+            1 => Some(FormatVersion::V1),
+            2 => Some(FormatVersion::V2),
+            _ => None,
+        };
+    }
+
+    pub fn number(&self) -> u8 {
+        return match self {
+            FormatVersion::V1 => 1,
+            FormatVersion::V2 => 2,
+        };
+    }
+}
+
+impl Img2d {
+    /// Whether the label or tooltip is absent, which only [`FormatVersion::V2`] can tell
+    /// apart from an empty one.
+    pub fn has_absent_strings(&self) -> bool {
+        return self.label.is_none() || self.tooltip.is_none();
+    }
+}
+
+impl Img2dArray {
+    /// Whether the title or a string of any page is absent, see
+    /// [`Img2d::has_absent_strings`].
+    pub fn has_absent_strings(&self) -> bool {
+        return self.title.is_none() || self.pages.iter().any(Img2d::has_absent_strings);
+    }
+}
+
+/// Packs an `[r, g, b]` colour into the `0xRRGGBB` form used by palettes.
+pub fn pack_rgb(rgb: [u8; 3]) -> u32 {
+    return (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+}
+
+/// Unpacks a `0xRRGGBB` palette colour into `[r, g, b]`, ignoring the top byte.
+pub fn unpack_rgb(color: u32) -> [u8; 3] {
+    return [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+}
+
+/// Parses a `RRGGBB` hex colour, optionally prefixed with `#` or `0x`.
+pub fn parse_hex_color(color: &str) -> Option<u32> {
+    let digits = color
+        .strip_prefix('#')
+        .or_else(|| color.strip_prefix("0x"))
+        .unwrap_or(color);
+    if digits.len() != 6 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    return u32::from_str_radix(digits, 16).ok();
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Img2d {
@@ -35,436 +136,729 @@ impl fmt::Display for Img2dArray {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq)]
 pub enum ImgFormat {
     Binary,
     JSON,
+    PNG,
 }
 
-fn read_file_as_bytes(file: &PathBuf) -> Result<Vec<u8>, io::Error> {
-    let f = File::open(&file)?;
-
-    let mut reader = BufReader::new(f);
-    let mut buffer: Vec<u8> = Vec::new();
-    reader.read_to_end(&mut buffer)?;
-
-    return Ok(buffer);
+/// The four poster encodings, named after their file extensions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PosterFormat {
+    /// 2dj, a single image as JSON.
+    Json,
+    /// 2dja, an image array as JSON.
+    JsonArray,
+    /// 2db, a single image in the binary format.
+    Binary,
+    /// 2dba, an image array in the binary format.
+    BinaryArray,
 }
 
-fn read_file_as_string(file: &PathBuf) -> Result<String, io::Error> {
-    let f = File::open(&file)?;
+impl PosterFormat {
+    /// Parses a file extension (`2dj`, `2dja`, `2db` or `2dba`), ignoring case.
+    pub fn from_extension(extension: &str) -> Option<PosterFormat> {
+        return match extension.to_ascii_lowercase().as_str() {
+            "2dj" => Some(PosterFormat::Json),
+            "2dja" => Some(PosterFormat::JsonArray),
+            "2db" => Some(PosterFormat::Binary),
+            "2dba" => Some(PosterFormat::BinaryArray),
+            _ => None,
+        };
+    }
+
+    pub fn extension(&self) -> &'static str {
+        return match self {
+            PosterFormat::Json => "2dj",
+            PosterFormat::JsonArray => "2dja",
+            PosterFormat::Binary => "2db",
+            PosterFormat::BinaryArray => "2dba",
+        };
+    }
 
-    let mut reader = BufReader::new(f);
-    let mut data: String = String::new();
-    reader.read_to_string(&mut data)?;
+    pub fn encoding(&self) -> ImgFormat {
+        return match self {
+            PosterFormat::Json | PosterFormat::JsonArray => ImgFormat::JSON,
+            PosterFormat::Binary | PosterFormat::BinaryArray => ImgFormat::Binary,
+        };
+    }
 
-    return Ok(data);
+    pub fn is_array(&self) -> bool {
+        return matches!(self, PosterFormat::JsonArray | PosterFormat::BinaryArray);
+    }
 }
 
-fn parse_byte_array_as_image(bytes: Vec<u8>) -> Result<Img2d, io::Error> {
-    let bytes_length = bytes.len();
+/// The binary format whose magic signature `bytes` start with, if any.
+pub fn detect_magic(bytes: &[u8]) -> Option<PosterFormat> {
+    if bytes.starts_with(&IMAGE_MAGIC) {
+        return Some(PosterFormat::Binary);
+    } else if bytes.starts_with(&ARRAY_MAGIC) {
+        return Some(PosterFormat::BinaryArray);
+    }
 
-    let mut image = Img2d {
-        label: None,
-        tooltip: None,
-        palette: vec![],
-        pixels: vec![],
-        width: 0,
-        height: 0,
-    };
+    return None;
+}
 
-    let mut ptr: usize = 0;
+/// Works out which format `bytes` are in by looking at their content.
+///
+/// v2 binary files are recognised by their magic signature. JSON objects are told apart by
+/// having `pages` (2dja) or `pixels` (2dj). Anything else is decoded as 2db and 2dba in
+/// strict mode, a 2db is preferred if both layouts happen to fit.
+pub fn detect_format(bytes: &[u8]) -> Option<PosterFormat> {
+    if let Some(format) = detect_magic(bytes) {
+        return Some(format);
+    }
 
-    //
-    // Label
-    //
-    let label_length: u16 = {
-        if ptr + 1 + 2 <= bytes_length {
-            u16::from_le_bytes(
-                bytes[ptr..ptr + 2]
-                    .try_into()
-                    .expect("Failed to get label length u16 (Input probably corrupted)"),
-            )
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get label length u16 (Buffer would overflow)",
-            ));
-        }
-    };
-    ptr += 2;
-
-    let mut label = String::new();
-    if label_length != 0 {
-        let start_ptr = ptr.clone();
-        if start_ptr + 1 + label_length as usize <= bytes_length {
-            for _ in start_ptr..start_ptr + label_length as usize {
-                let char = bytes[ptr] as char;
-                if !char.is_ascii_control() {
-                    label.push(char);
-                } else {
-                    println!("WARNING: Ignoring ASCII control character in label")
-                }
-                ptr += 1;
+    if bytes.trim_ascii_start().starts_with(b"{") {
+        if let Ok(serde_json::Value::Object(object)) =
+            serde_json::from_slice::<serde_json::Value>(bytes)
+        {
+            if object.contains_key("pages") {
+                return Some(PosterFormat::JsonArray);
+            } else if object.contains_key("pixels") {
+                return Some(PosterFormat::Json);
             }
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get label (Buffer would overflow)",
-            ));
+            return None;
         }
     }
-    // Label END
 
-    //
-    // Tooltip
-    //
-    let tooltip_length: u16 = {
-        if ptr + 1 + 2 <= bytes_length {
-            u16::from_le_bytes(
-                bytes[ptr..ptr + 2]
-                    .try_into()
-                    .expect("Failed to get tooltip length u16 (Input probably corrupted)"),
-            )
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get tooltip length u16 (Buffer would overflow)",
-            ));
+    let strict = DecodeOptions { strict: true };
+    if decode_2db(bytes, &strict).is_ok() {
+        return Some(PosterFormat::Binary);
+    } else if decode_2dba(bytes, &strict).is_ok() {
+        return Some(PosterFormat::BinaryArray);
+    }
+
+    return None;
+}
+
+/// Checks the version and feature flags bytes of a v2 header, which are right after the
+/// magic signature.
+fn check_header(version: u8, flags: u8) -> Result<FormatVersion, PosterError> {
+    let version = match FormatVersion::from_number(version) {
+        Some(FormatVersion::V2) => FormatVersion::V2,
+        _ => {
+            return Err(PosterError::UnsupportedVersion {
+                offset: IMAGE_MAGIC.len(),
+                version,
+            })
         }
     };
-    ptr += 2;
-
-    let mut tooltip = String::new();
-    if tooltip_length != 0 {
-        let start_ptr = ptr.clone();
-        if start_ptr + 1 + tooltip_length as usize <= bytes_length {
-            for _ in start_ptr..start_ptr + tooltip_length as usize {
-                let char = bytes[ptr] as char;
-                if !char.is_ascii_control() {
-                    tooltip.push(char);
-                } else {
-                    println!("WARNING: Ignoring ASCII control character in tooltip")
-                }
-                ptr += 1;
-            }
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get tooltip (Buffer would overflow)",
-            ));
-        }
+    if flags & !SUPPORTED_FLAGS != 0 {
+        return Err(PosterError::UnsupportedFlags {
+            offset: IMAGE_MAGIC.len() + 1,
+            flags: flags & !SUPPORTED_FLAGS,
+        });
+    }
+
+    return Ok(version);
+}
+
+/// Whether `signature` is the magic signature of `expected`, failing with
+/// [`PosterError::FormatMismatch`] if it's that of the other binary format.
+fn check_magic(signature: &[u8], expected: PosterFormat) -> Result<bool, PosterError> {
+    return match detect_magic(signature) {
+        None => Ok(false),
+        Some(found) if found == expected => Ok(true),
+        Some(found) => Err(PosterError::FormatMismatch { expected, found }),
+    };
+}
+
+/// Parses the header of a 2db file, returning its version, feature flags and length.
+pub(crate) fn parse_image_header(bytes: &[u8]) -> Result<(FormatVersion, u8, usize), PosterError> {
+    if !check_magic(bytes, PosterFormat::Binary)? {
+        return Ok((FormatVersion::V1, 0, 0));
+    }
+
+    let mut cursor = ByteCursor::new(bytes, 0);
+    cursor.bytes(IMAGE_MAGIC.len(), "magic")?;
+    let version = cursor.u8("version")?;
+    let flags = cursor.u8("flags")?;
+
+    return Ok((check_header(version, flags)?, flags, cursor.position()));
+}
+
+/// Fails with [`PosterError::ChecksumMismatch`] unless `bytes` have the CRC32 `stored`.
+pub(crate) fn check_checksum(
+    bytes: &[u8],
+    stored: u32,
+    offset: usize,
+    field: &'static str,
+) -> Result<(), PosterError> {
+    let computed = crc32fast::hash(bytes);
+    if computed != stored {
+        return Err(PosterError::ChecksumMismatch {
+            field,
+            offset,
+            stored,
+            computed,
+        });
+    }
+
+    return Ok(());
+}
+
+/// Reads a `u16` length prefixed string.
+fn read_string(
+    cursor: &mut ByteCursor,
+    length_field: &'static str,
+    field: &'static str,
+) -> Result<Option<String>, PosterError> {
+    let length = cursor.u16(length_field)?;
+    if length == ABSENT_STRING_LENGTH {
+        return Ok(None);
     }
-    // Tooltip END
+
+    return Ok(Some(charset::decode(cursor.bytes(length as usize, field)?)));
+}
+
+/// Reads the width and height of a 2db without its palette or pixels, to size an image up
+/// before decoding it.
+#[cfg(feature = "api")]
+pub(crate) fn peek_2db_size(bytes: &[u8]) -> Result<(u32, u32), PosterError> {
+    let (_, _, header_length) = parse_image_header(bytes)?;
+    let mut cursor = ByteCursor::new(&bytes[header_length..], header_length);
+    read_string(&mut cursor, "label length", "label")?;
+    read_string(&mut cursor, "tooltip length", "tooltip")?;
+
+    return Ok((cursor.u32("width")?, cursor.u32("height")?));
+}
+
+/// Parses a single 2db image, returning it together with the number of bytes it took up.
+/// `base_offset` is where `bytes` starts in the file and is only used for error reporting,
+/// `flags` are the feature flags from the file header.
+pub(crate) fn parse_byte_array_as_image(
+    bytes: &[u8],
+    base_offset: usize,
+    flags: u8,
+) -> Result<(Img2d, usize), PosterError> {
+    let mut cursor = ByteCursor::new(bytes, base_offset);
+
+    //
+    // Label and Tooltip
+    //
+    let label = read_string(&mut cursor, "label length", "label")?;
+    let tooltip = read_string(&mut cursor, "tooltip length", "tooltip")?;
+    // Label and Tooltip END
 
     //
     // Width and Height
     //
-    let width: u32 = {
-        if ptr + 1 + 4 <= bytes_length {
-            u32::from_le_bytes(
-                bytes[ptr..ptr + 4]
-                    .try_into()
-                    .expect("Failed to get width u32 (Input probably corrupted)"),
-            )
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get width u32 (Buffer would overflow)",
-            ));
-        }
-    };
-    ptr += 4;
-
-    let height: u32 = {
-        if ptr + 1 + 4 <= bytes_length {
-            u32::from_le_bytes(
-                bytes[ptr..ptr + 4]
-                    .try_into()
-                    .expect("Failed to get height u32 (Input probably corrupted)"),
-            )
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get height u32 (Buffer would overflow)",
-            ));
-        }
-    };
-    ptr += 4;
+    let width = cursor.u32("width")?;
+    let height = cursor.u32("height")?;
     // Width and Height END
 
     //
     // Palette
     //
-    let palette_length: u8 = {
-        if ptr + 1 + 1 <= bytes_length {
-            u8::from_le_bytes(
-                bytes[ptr..ptr + 1]
-                    .try_into()
-                    .expect("Failed to get palette length u8 (Input probably corrupted)"),
-            )
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get palette length u8 (Buffer would overflow)",
-            ));
-        }
-    };
-    ptr += 1;
-
-    let mut palette: Vec<u32> = Vec::new();
-    if ptr + 1 + (palette_length as usize * 4) <= bytes_length {
-        for i in (ptr..ptr + (palette_length as usize * 4)).step_by(4) {
-            palette.push(u32::from_le_bytes(
-                bytes[i..i + 4]
-                    .try_into()
-                    .expect("Failed to get palette (Input probably corrupted)"),
-            ));
-            ptr += 4;
-        }
-    } else {
-        return Err(io::Error::new(
-            ErrorKind::InvalidInput,
-            "Could not get palette (Buffer would overflow)",
-        ));
+    let palette_length = cursor.u8("palette length")? as usize;
+    let mut palette: Vec<u32> = Vec::with_capacity(palette_length);
+    for _ in 0..palette_length {
+        palette.push(cursor.u32("palette")?);
     }
     // Palette END
 
     //
     // Pixels
     //
-    let pixels_length: u32 = {
-        if ptr + 1 + 4 <= bytes_length {
-            u32::from_le_bytes(
-                bytes[ptr..ptr + 4]
-                    .try_into()
-                    .expect("Failed to get pixels length u32 (Input probably corrupted)"),
-            )
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get pixels length u32 (Buffer would overflow)",
-            ));
-        }
-    };
-    ptr += 4;
+    let mut compression = Compression::None;
+    if flags & FLAG_COMPRESSED != 0 {
+        let offset = cursor.offset();
+        let id = cursor.u8("compression")?;
+        compression = match Compression::from_id(id) {
+            Some(t) => t,
+            None => return Err(PosterError::UnsupportedCompression { offset, id }),
+        };
+    }
 
-    let mut pixels: Vec<u8> = Vec::new();
-    if ptr + pixels_length as usize <= bytes_length {
-        for i in ptr..ptr + (pixels_length as usize) {
-            pixels.push(bytes[i]);
+    let pixels_offset = cursor.offset();
+    let pixels_length = cursor.u32("pixels length")? as usize;
+
+    // Compressed pixels are followed by the length of their payload
+    let mut payload_length = pixels_length;
+    if flags & FLAG_COMPRESSED != 0 {
+        payload_length = cursor.u32("payload length")? as usize;
+
+        // A tiny payload can decompress to far more than it takes up, so it can't be trusted
+        // with more pixels than the image has. Uncompressed pixels have to be in the file.
+        let expected = width as u64 * height as u64;
+        if pixels_length as u64 > expected {
+            return Err(PosterError::PixelCountMismatch {
+                offset: pixels_offset,
+                declared: pixels_length,
+                expected,
+            });
         }
-    } else {
-        return Err(io::Error::new(
-            ErrorKind::InvalidInput,
-            "Could not get pixels (Buffer would overflow)",
-        ));
     }
+
+    let payload_offset = cursor.offset();
+    let payload = cursor.bytes(payload_length, "pixels")?;
+    let pixels = compression::decompress(payload, pixels_length, compression, payload_offset)?;
     // Pixels END
 
-    image.label = Some(label);
-    image.tooltip = Some(tooltip);
-    image.width = width;
-    image.height = height;
-    image.palette = palette;
-    image.pixels = pixels;
+    let image = Img2d {
+        label,
+        tooltip,
+        palette,
+        pixels,
+        width,
+        height,
+    };
 
-    return Ok(image);
+    return Ok((image, cursor.position()));
 }
 
-pub fn read_2dj(file: &PathBuf) -> Result<Img2d, io::Error> {
-    let data = read_file_as_string(file)?;
+/// Fails on the first pixel of `image` that has no palette entry.
+fn check_pixels(image: &Img2d) -> Result<(), PosterError> {
+    let palette_length = image.palette.len();
+    if let Some(index) = image
+        .pixels
+        .iter()
+        .position(|&pixel| pixel as usize >= palette_length)
+    {
+        return Err(PosterError::PixelOutOfRange {
+            index,
+            value: image.pixels[index],
+            palette_length,
+        });
+    }
 
-    let image: Img2d = serde_json::from_str(&data)?;
+    return Ok(());
+}
+
+pub fn decode_2dj<R: Read>(reader: R) -> Result<Img2d, PosterError> {
+    return Ok(serde_json::from_reader(reader)?);
+}
+
+pub fn decode_2dja<R: Read>(reader: R) -> Result<Img2dArray, PosterError> {
+    return Ok(serde_json::from_reader(reader)?);
+}
+
+/// Settings shared by the binary decoders.
+#[derive(Clone, Default)]
+pub struct DecodeOptions {
+    /// Fail on bytes left over after the image or the last page, on pages that are longer
+    /// than their content, on a partial page length at the end of a 2dba and on pixels that
+    /// have no palette entry. Lenient decoding ignores all of these to salvage what it can
+    /// from damaged or legacy files.
+    pub strict: bool,
+}
+
+/// Decodes a 2db image, reading `reader` until EOF.
+pub fn decode_2db<R: Read>(mut reader: R, options: &DecodeOptions) -> Result<Img2d, PosterError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let (_, flags, header_length) = parse_image_header(&bytes)?;
+    let (image, consumed) =
+        parse_byte_array_as_image(&bytes[header_length..], header_length, flags)?;
+
+    // The checksum follows the image and covers everything before it, like in the Lua reader
+    let mut end = header_length + consumed;
+    if flags & FLAG_CHECKSUMS != 0 {
+        let stored = ByteCursor::new(&bytes[end..], end).u32("file checksum")?;
+        check_checksum(&bytes[..end], stored, end, "file checksum")?;
+        end += 4;
+    }
+
+    if options.strict && end != bytes.len() {
+        return Err(PosterError::TrailingBytes {
+            offset: end,
+            count: bytes.len() - end,
+        });
+    }
+    if options.strict {
+        check_pixels(&image)?;
+    }
 
     return Ok(image);
 }
 
-pub fn read_2dja(file: &PathBuf) -> Result<Img2dArray, io::Error> {
-    let data = read_file_as_string(file)?;
+/// Decodes a whole 2dba image array, see [`Img2dArrayDecoder`] to read it page by page.
+pub fn decode_2dba<R: Read>(reader: R, options: &DecodeOptions) -> Result<Img2dArray, PosterError> {
+    let mut decoder = Img2dArrayDecoder::new(reader, options)?;
+    let pages = decoder
+        .by_ref()
+        .collect::<Result<Vec<Img2d>, PosterError>>()?;
+
+    return Ok(Img2dArray {
+        width: decoder.width,
+        height: decoder.height,
+        title: decoder.title,
+        pages,
+    });
+}
 
-    let image_array: Img2dArray = serde_json::from_str(&data)?;
+pub fn read_2dj(file: &Path) -> Result<Img2d, PosterError> {
+    return decode_2dj(BufReader::new(File::open(file)?));
+}
 
-    return Ok(image_array);
+pub fn read_2dja(file: &Path) -> Result<Img2dArray, PosterError> {
+    return decode_2dja(BufReader::new(File::open(file)?));
 }
 
-pub fn read_2db(file: &PathBuf) -> Result<Img2d, io::Error> {
-    let bytes = read_file_as_bytes(file)?;
+pub fn read_2db(file: &Path, options: &DecodeOptions) -> Result<Img2d, PosterError> {
+    return decode_2db(BufReader::new(File::open(file)?), options);
+}
 
-    let image: Img2d = parse_byte_array_as_image(bytes)?;
+pub fn read_2dba(file: &Path, options: &DecodeOptions) -> Result<Img2dArray, PosterError> {
+    return decode_2dba(BufReader::new(File::open(file)?), options);
+}
 
-    return Ok(image);
+/// Streams the pages of a 2dba image array, only holding one page in memory at a time.
+///
+/// The array header is read by [`Img2dArrayDecoder::new`], pages are then yielded by
+/// iterating the decoder. A page that fails to parse or doesn't match its checksum is
+/// yielded as an error and iteration carries on with the next one, any other error ends
+/// iteration.
+pub struct Img2dArrayDecoder<R: Read> {
+    pub version: FormatVersion,
+    /// Feature flags of v2 files, always 0 for v1.
+    pub flags: u8,
+    pub title: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    /// Starts with the bytes read while looking for the magic signature.
+    reader: ReadCursor<ChecksumReader<Chain<Cursor<Vec<u8>>, R>>>,
+    options: DecodeOptions,
+    finished: bool,
 }
 
-pub fn read_2dba(file: &PathBuf) -> Result<Img2dArray, io::Error> {
-    let bytes = read_file_as_bytes(file)?;
-    let bytes_length = bytes.len();
+/// The bytes of a page in a 2dba before parsing, with where they start in the file and the
+/// checksum stored after them.
+struct RawPage {
+    bytes: Vec<u8>,
+    offset: usize,
+    checksum: Option<u32>,
+}
 
-    let mut image_array = Img2dArray {
-        width: 0,
-        height: 0,
-        title: None,
-        pages: Vec::new(),
-    };
+/// Passes reads through while keeping the CRC32 of every byte read so far.
+struct ChecksumReader<R: Read> {
+    reader: R,
+    hasher: crc32fast::Hasher,
+}
 
-    let mut ptr: usize = 0;
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.hasher.update(&buf[..read]);
 
-    //
-    // Title
-    //
-    let title_length: u16 =
-        {
-            if ptr + 1 + 2 <= bytes_length {
-                u16::from_le_bytes(bytes[ptr..ptr + 2].try_into().expect(
-                    "Failed to get title length u16 of image array (Input probably corrupted)",
-                ))
-            } else {
-                return Err(io::Error::new(
-                    ErrorKind::InvalidInput,
-                    "Could not get title length u16 of image array (Buffer would overflow)",
-                ));
+        return Ok(read);
+    }
+}
+
+impl<R: Read> Img2dArrayDecoder<R> {
+    pub fn new(mut reader: R, options: &DecodeOptions) -> Result<Self, PosterError> {
+        //
+        // Header
+        //
+        let mut signature: Vec<u8> = Vec::new();
+        (&mut reader)
+            .take(ARRAY_MAGIC.len() as u64)
+            .read_to_end(&mut signature)?;
+        let has_magic = check_magic(&signature, PosterFormat::BinaryArray)?;
+
+        // Put the signature back in front so it's part of the checksum, or of the title in v1
+        let reader = ChecksumReader {
+            reader: Cursor::new(signature).chain(reader),
+            hasher: crc32fast::Hasher::new(),
+        };
+        let mut reader = ReadCursor::new(reader, 0);
+
+        let (mut version, mut flags) = (FormatVersion::V1, 0);
+        if has_magic {
+            reader.bytes(ARRAY_MAGIC.len(), "magic")?;
+            let header = reader.bytes(2, "version and flags")?;
+            version = check_header(header[0], header[1])?;
+            flags = header[1];
+        }
+        // Header END
+
+        //
+        // Title
+        //
+        let title = match reader.u16("array title length")? {
+            ABSENT_STRING_LENGTH => None,
+            title_length => Some(charset::decode(
+                &reader.bytes(title_length as usize, "array title")?,
+            )),
+        };
+        // Title END
+
+        //
+        // Width and Height
+        //
+        let width = reader.u32("array width")?;
+        let height = reader.u32("array height")?;
+        // Width and Height END
+
+        return Ok(Img2dArrayDecoder {
+            version,
+            flags,
+            title,
+            width,
+            height,
+            reader,
+            options: options.clone(),
+            finished: false,
+        });
+    }
+
+    /// Offset of the next page in the file.
+    pub(crate) fn offset(&self) -> usize {
+        return self.reader.offset();
+    }
+
+    /// Whether iteration has ended, either at the end of the pages or after an error that
+    /// left the rest of the file unreadable.
+    pub(crate) fn is_finished(&self) -> bool {
+        return self.finished;
+    }
+
+    /// Reads the bytes of the next page. Errors here leave the rest of the file unreadable.
+    fn read_page(&mut self) -> Result<Option<RawPage>, PosterError> {
+        let checksums = self.flags & FLAG_CHECKSUMS != 0;
+
+        // Files with checksums have to end with END_OF_PAGES, so running out is truncation
+        let page_length = if checksums {
+            self.reader.u32("page length")?
+        } else {
+            let offset = self.reader.offset();
+            let page_length = self.reader.bytes_up_to(4)?;
+            if page_length.len() < 4 {
+                if self.options.strict && !page_length.is_empty() {
+                    return Err(PosterError::TrailingBytes {
+                        offset,
+                        count: page_length.len(),
+                    });
+                }
+                return Ok(None);
             }
+            u32::from_le_bytes([
+                page_length[0],
+                page_length[1],
+                page_length[2],
+                page_length[3],
+            ])
         };
-    ptr += 2;
-
-    let mut title = String::new();
-    if title_length != 0 {
-        let start_ptr = ptr.clone();
-        if start_ptr + 1 + title_length as usize <= bytes_length {
-            for _ in start_ptr..start_ptr + title_length as usize {
-                let char = bytes[ptr] as char;
-                if !char.is_ascii_control() {
-                    title.push(char);
-                } else {
-                    println!("WARNING: Ignoring ASCII control character in image array title")
+
+        if checksums && page_length == END_OF_PAGES {
+            let computed = self.reader.get_ref().hasher.clone().finalize();
+            let stored_offset = self.reader.offset();
+            let stored = self.reader.u32("file checksum")?;
+            if stored != computed {
+                return Err(PosterError::ChecksumMismatch {
+                    field: "file checksum",
+                    offset: stored_offset,
+                    stored,
+                    computed,
+                });
+            }
+
+            let offset = self.reader.offset();
+            if self.options.strict {
+                let count = self.reader.skip_to_end()?;
+                if count != 0 {
+                    return Err(PosterError::TrailingBytes { offset, count });
                 }
-                ptr += 1;
             }
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get title of image array (Buffer would overflow)",
-            ));
+            return Ok(None);
         }
+
+        let page_offset = self.reader.offset();
+        let page = self.reader.bytes(page_length as usize, "page")?;
+
+        let mut checksum = None;
+        if checksums {
+            checksum = Some(self.reader.u32("page checksum")?);
+        }
+
+        return Ok(Some(RawPage {
+            bytes: page,
+            offset: page_offset,
+            checksum,
+        }));
     }
-    // Title END
 
-    //
-    // Width and Height
-    //
-    let width: u32 = {
-        if ptr + 1 + 4 <= bytes_length {
-            u32::from_le_bytes(
-                bytes[ptr..ptr + 4]
-                    .try_into()
-                    .expect("Failed to get width u32 of image array (Input probably corrupted)"),
-            )
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get width u32 of image array (Buffer would overflow)",
-            ));
+    /// Checks and parses a page read by [`Img2dArrayDecoder::read_page`]. Errors here only
+    /// affect this page.
+    fn parse_page(&self, page: &RawPage) -> Result<Img2d, PosterError> {
+        if let Some(stored) = page.checksum {
+            let checksum_offset = page.offset + page.bytes.len();
+            check_checksum(&page.bytes, stored, checksum_offset, "page checksum")?;
         }
-    };
-    ptr += 4;
-
-    let height: u32 = {
-        if ptr + 1 + 4 <= bytes_length {
-            u32::from_le_bytes(
-                bytes[ptr..ptr + 4]
-                    .try_into()
-                    .expect("Failed to get height u32 of image array (Input probably corrupted)"),
-            )
-        } else {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "Could not get height u32 of image array (Buffer would overflow)",
-            ));
+
+        let (image, consumed) = parse_byte_array_as_image(&page.bytes, page.offset, self.flags)?;
+        if self.options.strict && consumed != page.bytes.len() {
+            return Err(PosterError::PageLengthMismatch {
+                offset: page.offset,
+                declared: page.bytes.len(),
+                consumed,
+            });
+        }
+        if self.options.strict {
+            check_pixels(&image)?;
         }
-    };
-    ptr += 4;
-    // Width and Height END
 
-    loop {
-        if ptr + 1 + 4 <= bytes_length {
-            let page_length: u32 = {
-                if ptr + 1 + 4 <= bytes_length {
-                    u32::from_le_bytes(bytes[ptr..ptr + 4].try_into().expect("Failed to get page length u32 of image in array (Input probably corrupted)"))
-                } else {
-                    return Err(io::Error::new(
-                        ErrorKind::InvalidInput,
-                        "Could not get page length u32 of image in array (Buffer would overflow)",
-                    ));
-                }
-            };
-            ptr += 4;
+        return Ok(image);
+    }
+}
+
+impl<R: Read> Iterator for Img2dArrayDecoder<R> {
+    type Item = Result<Img2d, PosterError>;
 
-            if ptr + page_length as usize > bytes_length {
-                return Err(io::Error::new(
-                    ErrorKind::InvalidInput,
-                    "Failed to read page of image in array (Buffer would overflow)",
-                ));
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        return match self.read_page() {
+            Ok(Some(page)) => Some(self.parse_page(&page)),
+            Ok(None) => {
+                self.finished = true;
+                None
             }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        };
+    }
+}
 
-            let image: Img2d =
-                parse_byte_array_as_image(bytes[ptr..ptr + page_length as usize].to_vec())?;
-            ptr += page_length as usize;
+/// Settings shared by the encoders.
+#[derive(Clone)]
+pub struct EncodeOptions {
+    /// Run [`Img2d::validate`] or [`Img2dArray::validate`] before writing anything.
+    pub validate: bool,
+    /// How characters outside the ComputerCraft charset are written in the binary format.
+    pub unrepresentable: Unrepresentable,
+    /// Layout of the binary format, v1 by default so readers that predate the v2 header can
+    /// read the output.
+    pub version: FormatVersion,
+    /// Compression of the pixels in the binary format, only available in v2.
+    pub compression: Compression,
+    /// Write CRC32 checksums in the binary format, only available in v2.
+    pub checksums: bool,
+}
 
-            image_array.pages.push(image);
-        } else {
-            break;
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        return EncodeOptions {
+            validate: true,
+            unrepresentable: Unrepresentable::Error,
+            version: FormatVersion::V1,
+            compression: Compression::None,
+            checksums: false,
+        };
+    }
+}
+
+impl EncodeOptions {
+    /// Feature flags written to the v2 header.
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.compression != Compression::None {
+            flags |= FLAG_COMPRESSED;
+        }
+        if self.checksums {
+            flags |= FLAG_CHECKSUMS;
         }
+
+        return flags;
     }
 
-    image_array.title = Some(title);
-    image_array.width = width;
-    image_array.height = height;
+    /// Fails with [`PosterError::VersionTooOld`] if a feature doesn't fit the chosen version.
+    pub(crate) fn check_version(&self) -> Result<(), PosterError> {
+        if self.version == FormatVersion::V1 && self.compression != Compression::None {
+            return Err(PosterError::VersionTooOld {
+                feature: "pixel compression",
+            });
+        }
+        if self.version == FormatVersion::V1 && self.checksums {
+            return Err(PosterError::VersionTooOld {
+                feature: "writing checksums",
+            });
+        }
 
-    return Ok(image_array);
+        return Ok(());
+    }
 }
 
-pub fn img_2d_to_string(image: &Img2d) -> Result<String, io::Error> {
-    return Ok(serde_json::to_string(image)?);
-}
+pub fn img_2d_to_string(image: &Img2d) -> Result<String, PosterError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    encode_2dj(image, &mut bytes, &EncodeOptions::default())?;
 
-pub fn img_2d_array_to_string(image: &Img2dArray) -> Result<String, io::Error> {
-    return Ok(serde_json::to_string(image)?);
+    return Ok(String::from_utf8(bytes).expect("serde_json produced invalid UTF-8"));
 }
 
-pub fn img_2d_to_bytes(image: &Img2d) -> Result<Vec<u8>, io::Error> {
+pub fn img_2d_array_to_string(image: &Img2dArray) -> Result<String, PosterError> {
     let mut bytes: Vec<u8> = Vec::new();
+    encode_2dja(image, &mut bytes, &EncodeOptions::default())?;
 
-    //
-    // Label
-    //
-    if image.label.is_some() {
-        let label = image.label.as_ref().unwrap();
-        bytes.extend((label.len() as u16).to_le_bytes());
+    return Ok(String::from_utf8(bytes).expect("serde_json produced invalid UTF-8"));
+}
+
+/// Appends `string` with its `u16` length prefix, encoded in the ComputerCraft charset.
+/// `None` is written as [`ABSENT_STRING_LENGTH`] with nothing after it, or as an empty
+/// string in v1.
+fn write_string(
+    bytes: &mut Vec<u8>,
+    string: &Option<String>,
+    field: &'static str,
+    options: &EncodeOptions,
+) -> Result<(), PosterError> {
+    match string {
+        Some(string) => {
+            let encoded = charset::encode(string, field, bytes.len() + 2, options.unrepresentable)?;
+            if encoded.len() > MAX_STRING_LENGTH {
+                return Err(PosterError::StringTooLong {
+                    field,
+                    length: encoded.len(),
+                    max: MAX_STRING_LENGTH,
+                });
+            }
 
-        for c in label.chars() {
-            bytes.push(c as u8)
+            bytes.extend((encoded.len() as u16).to_le_bytes());
+            bytes.extend(encoded);
+        }
+        None if options.version == FormatVersion::V1 => {
+            bytes.extend(0u16.to_le_bytes());
+        }
+        None => {
+            bytes.extend(ABSENT_STRING_LENGTH.to_le_bytes());
         }
-    } else {
-        bytes.push(0);
-        bytes.push(0);
     }
-    // Label END
+
+    return Ok(());
+}
+
+pub fn img_2d_to_bytes(image: &Img2d) -> Result<Vec<u8>, PosterError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    encode_2db(image, &mut bytes, &EncodeOptions::default())?;
+
+    return Ok(bytes);
+}
+
+/// Appends the v2 header, the magic signature followed by the version and flags.
+fn write_header(bytes: &mut Vec<u8>, magic: [u8; 4], options: &EncodeOptions) {
+    bytes.extend(magic);
+    bytes.push(options.version.number());
+    bytes.push(options.flags());
+}
+
+/// Serializes a single image without validating it first.
+fn serialize_image(image: &Img2d, options: &EncodeOptions) -> Result<Vec<u8>, PosterError> {
+    let mut bytes: Vec<u8> = Vec::new();
 
     //
-    // Tooltip
+    // Label and Tooltip
     //
-    if image.tooltip.is_some() {
-        let tooltip = image.tooltip.as_ref().unwrap();
-        bytes.extend((tooltip.len() as u16).to_le_bytes());
-
-        for c in tooltip.chars() {
-            bytes.push(c as u8)
-        }
-    } else {
-        bytes.push(0);
-        bytes.push(0);
-    }
-    // Tooltip END
+    write_string(&mut bytes, &image.label, "label", options)?;
+    write_string(&mut bytes, &image.tooltip, "tooltip", options)?;
+    // Label and Tooltip END
 
     //
     // Width and Height
@@ -476,6 +870,12 @@ pub fn img_2d_to_bytes(image: &Img2d) -> Result<Vec<u8>, io::Error> {
     //
     // Palette
     //
+    if image.palette.len() > MAX_PALETTE_LENGTH {
+        return Err(PosterError::PaletteOverflow {
+            length: image.palette.len(),
+            max: MAX_PALETTE_LENGTH,
+        });
+    }
     bytes.push(image.palette.len() as u8);
     for v in image.palette.iter() {
         bytes.extend(v.to_le_bytes());
@@ -485,47 +885,900 @@ pub fn img_2d_to_bytes(image: &Img2d) -> Result<Vec<u8>, io::Error> {
     //
     // Pixels
     //
-    bytes.extend((image.pixels.len() as u32).to_le_bytes());
-    for v in image.pixels.iter() {
-        bytes.push(v.to_le_bytes()[0]);
+    if options.compression == Compression::None {
+        bytes.extend((image.pixels.len() as u32).to_le_bytes());
+        bytes.extend(image.pixels.iter());
+    } else {
+        let payload = compression::compress(&image.pixels, options.compression);
+        bytes.push(options.compression.id());
+        bytes.extend((image.pixels.len() as u32).to_le_bytes());
+        bytes.extend((payload.len() as u32).to_le_bytes());
+        bytes.extend(payload);
     }
     // Pixels END
 
     return Ok(bytes);
 }
 
-pub fn img_2d_array_to_bytes(image: &Img2dArray) -> Result<Vec<u8>, io::Error> {
+pub fn img_2d_array_to_bytes(image: &Img2dArray) -> Result<Vec<u8>, PosterError> {
     let mut bytes: Vec<u8> = Vec::new();
+    encode_2dba(image, &mut bytes, &EncodeOptions::default())?;
 
-    //
-    // Title
-    //
-    if image.title.is_some() {
-        let title = image.title.as_ref().unwrap();
-        bytes.extend((title.len() as u16).to_le_bytes());
+    return Ok(bytes);
+}
 
-        for c in title.chars() {
-            bytes.push(c as u8)
-        }
-    } else {
-        bytes.push(0);
-        bytes.push(0);
+pub fn encode_2dj<W: Write>(
+    image: &Img2d,
+    writer: W,
+    options: &EncodeOptions,
+) -> Result<(), PosterError> {
+    if options.validate {
+        image.validate()?;
     }
-    // Title END
 
-    //
-    // Width and Height
-    //
-    bytes.extend(image.width.to_le_bytes());
-    bytes.extend(image.height.to_le_bytes());
-    // Width and Height END
+    return Ok(serde_json::to_writer(writer, image)?);
+}
+
+pub fn encode_2dja<W: Write>(
+    image: &Img2dArray,
+    writer: W,
+    options: &EncodeOptions,
+) -> Result<(), PosterError> {
+    if options.validate {
+        image.validate()?;
+    }
+
+    return Ok(serde_json::to_writer(writer, image)?);
+}
+
+pub fn encode_2db<W: Write>(
+    image: &Img2d,
+    mut writer: W,
+    options: &EncodeOptions,
+) -> Result<(), PosterError> {
+    options.check_version()?;
+    if options.validate {
+        image.validate()?;
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+    if options.version == FormatVersion::V2 {
+        write_header(&mut bytes, IMAGE_MAGIC, options);
+    }
+    bytes.extend(serialize_image(image, options)?);
+    if options.checksums {
+        bytes.extend(crc32fast::hash(&bytes).to_le_bytes());
+    }
+
+    writer.write_all(&bytes)?;
 
+    return Ok(());
+}
+
+/// Encodes a whole 2dba image array, see [`Img2dArrayEncoder`] to write it page by page.
+pub fn encode_2dba<W: Write>(
+    image: &Img2dArray,
+    writer: W,
+    options: &EncodeOptions,
+) -> Result<(), PosterError> {
+    // Validate everything up front so every problem is reported, not just the first page's
+    if options.validate {
+        image.validate()?;
+    }
+    let mut options = options.clone();
+    options.validate = false;
+
+    let mut encoder =
+        Img2dArrayEncoder::new(writer, &image.title, image.width, image.height, &options)?;
     for page in image.pages.iter() {
-        let serialized_page = img_2d_to_bytes(page)?;
+        encoder.write_page(page)?;
+    }
+    encoder.finish()?;
+
+    return Ok(());
+}
 
-        bytes.extend((serialized_page.len() as u32).to_le_bytes());
-        bytes.extend(serialized_page);
+pub fn write_2dj(file: &Path, image: &Img2d, options: &EncodeOptions) -> Result<(), PosterError> {
+    // Validate before creating the file so an invalid image doesn't leave an empty one behind
+    if options.validate {
+        image.validate()?;
     }
+    let mut options = options.clone();
+    options.validate = false;
 
-    return Ok(bytes);
+    let mut writer = BufWriter::new(File::create(file)?);
+    encode_2dj(image, &mut writer, &options)?;
+    writer.flush()?;
+
+    return Ok(());
+}
+
+pub fn write_2dja(
+    file: &Path,
+    image: &Img2dArray,
+    options: &EncodeOptions,
+) -> Result<(), PosterError> {
+    if options.validate {
+        image.validate()?;
+    }
+    let mut options = options.clone();
+    options.validate = false;
+
+    let mut writer = BufWriter::new(File::create(file)?);
+    encode_2dja(image, &mut writer, &options)?;
+    writer.flush()?;
+
+    return Ok(());
+}
+
+pub fn write_2db(file: &Path, image: &Img2d, options: &EncodeOptions) -> Result<(), PosterError> {
+    options.check_version()?;
+    if options.validate {
+        image.validate()?;
+    }
+    let mut options = options.clone();
+    options.validate = false;
+
+    let mut writer = BufWriter::new(File::create(file)?);
+    encode_2db(image, &mut writer, &options)?;
+    writer.flush()?;
+
+    return Ok(());
+}
+
+pub fn write_2dba(
+    file: &Path,
+    image: &Img2dArray,
+    options: &EncodeOptions,
+) -> Result<(), PosterError> {
+    options.check_version()?;
+    if options.validate {
+        image.validate()?;
+    }
+    let mut options = options.clone();
+    options.validate = false;
+
+    let mut writer = BufWriter::new(File::create(file)?);
+    encode_2dba(image, &mut writer, &options)?;
+    writer.flush()?;
+
+    return Ok(());
+}
+
+/// Writes a 2dba image array one page at a time.
+///
+/// The array header is written by [`Img2dArrayEncoder::new`], call
+/// [`Img2dArrayEncoder::finish`] once every page has been written.
+pub struct Img2dArrayEncoder<W: Write> {
+    writer: W,
+    options: EncodeOptions,
+    pages: usize,
+    /// CRC32 of everything written so far, for the file checksum.
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Img2dArrayEncoder<W> {
+    pub fn new(
+        writer: W,
+        title: &Option<String>,
+        width: u32,
+        height: u32,
+        options: &EncodeOptions,
+    ) -> Result<Self, PosterError> {
+        options.check_version()?;
+        if options.validate {
+            let violations = title_violations(title);
+            if !violations.is_empty() {
+                return Err(PosterError::Invalid(violations));
+            }
+        }
+
+        let mut bytes: Vec<u8> = Vec::new();
+
+        //
+        // Header
+        //
+        if options.version == FormatVersion::V2 {
+            write_header(&mut bytes, ARRAY_MAGIC, options);
+        }
+        // Header END
+
+        //
+        // Title
+        //
+        write_string(&mut bytes, title, "array title", options)?;
+        // Title END
+
+        //
+        // Width and Height
+        //
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        // Width and Height END
+
+        let mut encoder = Img2dArrayEncoder {
+            writer,
+            options: options.clone(),
+            pages: 0,
+            hasher: crc32fast::Hasher::new(),
+        };
+        encoder.write(&bytes)?;
+
+        return Ok(encoder);
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), PosterError> {
+        self.hasher.update(bytes);
+        self.writer.write_all(bytes)?;
+
+        return Ok(());
+    }
+
+    pub fn write_page(&mut self, page: &Img2d) -> Result<(), PosterError> {
+        if self.options.validate {
+            let violations = image_violations(page, Some(self.pages));
+            if !violations.is_empty() {
+                return Err(PosterError::Invalid(violations));
+            }
+        }
+
+        let serialized_page = serialize_image(page, &self.options)?;
+        self.pages += 1;
+
+        self.write(&(serialized_page.len() as u32).to_le_bytes())?;
+        self.write(&serialized_page)?;
+        if self.options.checksums {
+            self.write(&crc32fast::hash(&serialized_page).to_le_bytes())?;
+        }
+
+        return Ok(());
+    }
+
+    /// Ends the pages (with the file checksum if checksums are on), flushes and returns the
+    /// underlying writer.
+    pub fn finish(mut self) -> Result<W, PosterError> {
+        if self.options.checksums {
+            self.write(&END_OF_PAGES.to_le_bytes())?;
+            let checksum = self.hasher.clone().finalize();
+            self.writer.write_all(&checksum.to_le_bytes())?;
+        }
+        self.writer.flush()?;
+
+        return Ok(self.writer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{book, encode_book, page_offset};
+
+    fn options(version: FormatVersion) -> EncodeOptions {
+        return EncodeOptions {
+            version,
+            ..Default::default()
+        };
+    }
+
+    fn image(label: Option<&str>, tooltip: Option<&str>) -> Img2d {
+        return Img2d {
+            label: label.map(String::from),
+            tooltip: tooltip.map(String::from),
+            palette: vec![0x000000, 0xFFFFFF],
+            pixels: vec![0, 1, 1, 0],
+            width: 2,
+            height: 2,
+        };
+    }
+
+    /// A v1 2db: label, tooltip, width, height, palette and pixels, 31 bytes in all.
+    fn image_bytes() -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(
+            &image(Some("ab"), None),
+            &mut bytes,
+            &options(FormatVersion::V1),
+        )
+        .unwrap();
+        assert_eq!(bytes.len(), 31);
+
+        return bytes;
+    }
+
+    fn v2_image_bytes() -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(
+            &image(Some("ab"), None),
+            &mut bytes,
+            &options(FormatVersion::V2),
+        )
+        .unwrap();
+
+        return bytes;
+    }
+
+    fn v2_array_bytes() -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2dba(&array(), &mut bytes, &options(FormatVersion::V2)).unwrap();
+
+        return bytes;
+    }
+
+    #[test]
+    fn round_trips_binary_images() {
+        let bytes = image_bytes();
+        let (decoded, consumed) = parse_byte_array_as_image(&bytes, 0, 0).unwrap();
+        assert_eq!(consumed, 31);
+        assert_eq!(decoded.label.as_deref(), Some("ab"));
+        assert_eq!(decoded.tooltip.as_deref(), Some(""));
+        assert_eq!(decoded.palette, vec![0x000000, 0xFFFFFF]);
+        assert_eq!(decoded.pixels, vec![0, 1, 1, 0]);
+        assert_eq!((decoded.width, decoded.height), (2, 2));
+    }
+
+    #[test]
+    fn reports_the_truncated_field_and_offset() {
+        let bytes = image_bytes();
+
+        assert!(matches!(
+            parse_byte_array_as_image(&bytes[..29], 0, 0),
+            Err(PosterError::Truncated {
+                field: "pixels",
+                offset: 27,
+                needed: 4,
+                available: 2
+            })
+        ));
+        // Pages report offsets from the start of the file
+        assert!(matches!(
+            parse_byte_array_as_image(&bytes[..29], 100, 0),
+            Err(PosterError::Truncated { offset: 127, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_unrepresentable_characters() {
+        let result = encode_2db(
+            &image(Some("a€"), None),
+            Vec::new(),
+            &options(FormatVersion::V1),
+        );
+        assert!(matches!(
+            result,
+            Err(PosterError::Charset {
+                field: "label",
+                offset: 3,
+                character: '€'
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_palettes_too_long_for_their_length() {
+        let mut image = image(None, None);
+        image.palette = vec![0; MAX_PALETTE_LENGTH + 1];
+
+        assert!(matches!(
+            img_2d_to_bytes(&image),
+            Err(PosterError::Invalid(_))
+        ));
+
+        // Even without validation
+        let options = EncodeOptions {
+            validate: false,
+            ..Default::default()
+        };
+        assert!(matches!(
+            encode_2db(&image, Vec::new(), &options),
+            Err(PosterError::PaletteOverflow {
+                length: 256,
+                max: 255
+            })
+        ));
+    }
+
+    fn array() -> Img2dArray {
+        return Img2dArray {
+            width: 3,
+            height: 1,
+            title: Some("book".to_string()),
+            pages: vec![
+                image(Some("p1"), None),
+                image(Some("p2"), None),
+                image(Some("p3"), None),
+            ],
+        };
+    }
+
+    #[test]
+    fn streams_array_pages() {
+        let array = array();
+        let mut encoder = Img2dArrayEncoder::new(
+            Vec::new(),
+            &array.title,
+            array.width,
+            array.height,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        for page in array.pages.iter() {
+            encoder.write_page(page).unwrap();
+        }
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(bytes, img_2d_array_to_bytes(&array).unwrap());
+
+        let decoder = Img2dArrayDecoder::new(bytes.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(decoder.title.as_deref(), Some("book"));
+        assert_eq!((decoder.width, decoder.height), (3, 1));
+        let labels: Vec<String> = decoder.map(|page| page.unwrap().label.unwrap()).collect();
+        assert_eq!(labels, vec!["p1", "p2", "p3"]);
+    }
+
+    #[test]
+    fn stops_after_a_truncated_page() {
+        let bytes = img_2d_array_to_bytes(&array()).unwrap();
+        let mut decoder =
+            Img2dArrayDecoder::new(&bytes[..bytes.len() - 2], &DecodeOptions::default()).unwrap();
+
+        assert!(decoder.next().unwrap().is_ok());
+        assert!(decoder.next().unwrap().is_ok());
+        assert!(matches!(
+            decoder.next(),
+            Some(Err(PosterError::Truncated { field: "page", .. }))
+        ));
+        assert!(decoder.next().is_none());
+        assert!(decode_2dba(&bytes[..bytes.len() - 2], &DecodeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(
+            &image(Some("ab"), None),
+            &mut bytes,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            decode_2db(bytes.as_slice(), &DecodeOptions::default())
+                .unwrap()
+                .pixels,
+            vec![0, 1, 1, 0]
+        );
+
+        let mut json: Vec<u8> = Vec::new();
+        encode_2dja(&array(), &mut json, &EncodeOptions::default()).unwrap();
+        assert_eq!(decode_2dja(json.as_slice()).unwrap().pages.len(), 3);
+    }
+
+    #[test]
+    fn detects_formats_from_content() {
+        let mut json: Vec<u8> = Vec::new();
+        encode_2dj(
+            &image(Some("ab"), None),
+            &mut json,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(detect_format(&json), Some(PosterFormat::Json));
+        json.clear();
+        encode_2dja(&array(), &mut json, &EncodeOptions::default()).unwrap();
+        assert_eq!(detect_format(&json), Some(PosterFormat::JsonArray));
+
+        let bytes = img_2d_to_bytes(&image(Some("ab"), None)).unwrap();
+        assert_eq!(detect_format(&bytes), Some(PosterFormat::Binary));
+        let bytes = img_2d_array_to_bytes(&array()).unwrap();
+        assert_eq!(detect_format(&bytes), Some(PosterFormat::BinaryArray));
+
+        assert_eq!(detect_format(b"{\"title\": null}"), None);
+        assert_eq!(detect_format(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn parses_extensions() {
+        assert_eq!(
+            PosterFormat::from_extension("2DBA"),
+            Some(PosterFormat::BinaryArray)
+        );
+        assert_eq!(PosterFormat::from_extension("png"), None);
+        assert!(PosterFormat::JsonArray.is_array());
+        assert!(PosterFormat::Binary.encoding() == ImgFormat::Binary);
+    }
+
+    fn round_trip(image: &Img2d, version: FormatVersion) -> Img2d {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(image, &mut bytes, &options(version)).unwrap();
+
+        return decode_2db(bytes.as_slice(), &DecodeOptions::default()).unwrap();
+    }
+
+    fn round_trip_array(title: Option<&str>, version: FormatVersion) -> Img2dArray {
+        let array = Img2dArray {
+            width: 1,
+            height: 1,
+            title: title.map(String::from),
+            pages: vec![image(None, Some(""))],
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2dba(&array, &mut bytes, &options(version)).unwrap();
+
+        return decode_2dba(bytes.as_slice(), &DecodeOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn keeps_absent_strings_apart_from_empty_ones_in_v2() {
+        for (label, tooltip) in [(None, Some("")), (Some(""), None), (None, None)] {
+            let decoded = round_trip(&image(label, tooltip), FormatVersion::V2);
+            assert_eq!(decoded.label.as_deref(), label);
+            assert_eq!(decoded.tooltip.as_deref(), tooltip);
+        }
+
+        for title in [None, Some("")] {
+            let decoded = round_trip_array(title, FormatVersion::V2);
+            assert_eq!(decoded.title.as_deref(), title);
+            assert_eq!(decoded.pages[0].label, None);
+            assert_eq!(decoded.pages[0].tooltip.as_deref(), Some(""));
+        }
+    }
+
+    #[test]
+    fn writes_absent_strings_as_empty_ones_in_v1() {
+        let decoded = round_trip(&image(None, Some("")), FormatVersion::V1);
+        assert_eq!(decoded.label.as_deref(), Some(""));
+        assert_eq!(decoded.tooltip.as_deref(), Some(""));
+
+        let decoded = round_trip_array(None, FormatVersion::V1);
+        assert_eq!(decoded.title.as_deref(), Some(""));
+        assert_eq!(decoded.pages[0].label.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn finds_absent_strings() {
+        assert!(!image(Some(""), Some("")).has_absent_strings());
+        assert!(image(None, Some("")).has_absent_strings());
+        assert!(image(Some(""), None).has_absent_strings());
+
+        let mut array = Img2dArray {
+            width: 1,
+            height: 1,
+            title: Some(String::new()),
+            pages: vec![image(Some(""), Some(""))],
+        };
+        assert!(!array.has_absent_strings());
+        array.pages[0].tooltip = None;
+        assert!(array.has_absent_strings());
+        array.pages[0].tooltip = Some(String::new());
+        array.title = None;
+        assert!(array.has_absent_strings());
+    }
+
+    #[test]
+    fn writes_absent_length_prefix() {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(
+            &image(None, Some("")),
+            &mut bytes,
+            &options(FormatVersion::V2),
+        )
+        .unwrap();
+        assert_eq!(&bytes[6..8], &ABSENT_STRING_LENGTH.to_le_bytes());
+        assert_eq!(&bytes[8..10], &[0, 0]);
+    }
+
+    #[test]
+    fn writes_the_v2_header() {
+        let bytes = v2_image_bytes();
+        assert_eq!(&bytes[..6], &[0x89, b'2', b'D', b'B', 2, 0]);
+        assert_eq!(bytes.len(), 31 + 6);
+        assert_eq!(detect_magic(&bytes), Some(PosterFormat::Binary));
+
+        let bytes = v2_array_bytes();
+        assert_eq!(&bytes[..6], &[0x89, b'2', b'D', b'A', 2, 0]);
+        assert_eq!(detect_magic(&bytes), Some(PosterFormat::BinaryArray));
+        assert_eq!(detect_magic(&image_bytes()), None);
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_flags() {
+        let mut bytes = v2_image_bytes();
+        bytes[4] = 3;
+        assert!(matches!(
+            decode_2db(bytes.as_slice(), &DecodeOptions::default()),
+            Err(PosterError::UnsupportedVersion {
+                offset: 4,
+                version: 3
+            })
+        ));
+
+        // Only the flags it doesn't know are reported
+        bytes[4] = 2;
+        bytes[5] = FLAG_COMPRESSED | 0b100;
+        assert!(matches!(
+            decode_2db(bytes.as_slice(), &DecodeOptions::default()),
+            Err(PosterError::UnsupportedFlags {
+                offset: 5,
+                flags: 0b100
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_the_other_binary_format() {
+        let bytes = v2_image_bytes();
+        assert!(matches!(
+            decode_2dba(bytes.as_slice(), &DecodeOptions::default()),
+            Err(PosterError::FormatMismatch {
+                expected: PosterFormat::BinaryArray,
+                found: PosterFormat::Binary
+            })
+        ));
+
+        let bytes = v2_array_bytes();
+        assert!(matches!(
+            decode_2db(bytes.as_slice(), &DecodeOptions::default()),
+            Err(PosterError::FormatMismatch {
+                expected: PosterFormat::Binary,
+                found: PosterFormat::BinaryArray
+            })
+        ));
+    }
+
+    #[test]
+    fn round_trips_compressed_pixels() {
+        for compression in [Compression::RunLength, Compression::Deflate] {
+            let options = EncodeOptions {
+                compression,
+                version: FormatVersion::V2,
+                ..Default::default()
+            };
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_2dba(&array(), &mut bytes, &options).unwrap();
+            assert_eq!(bytes[5], FLAG_COMPRESSED);
+
+            let decoded = decode_2dba(bytes.as_slice(), &DecodeOptions::default()).unwrap();
+            for page in &decoded.pages {
+                assert_eq!(page.pixels, vec![0, 1, 1, 0]);
+            }
+        }
+    }
+
+    #[test]
+    fn only_compresses_in_v2() {
+        let options = EncodeOptions {
+            version: FormatVersion::V1,
+            compression: Compression::Deflate,
+            ..Default::default()
+        };
+        assert!(matches!(
+            encode_2db(&image(None, None), Vec::new(), &options),
+            Err(PosterError::VersionTooOld {
+                feature: "pixel compression"
+            })
+        ));
+    }
+
+    #[test]
+    fn reports_truncation_offsets() {
+        let bytes = image_bytes();
+        // Cut right at the start of a field, and one byte into it
+        let cases = [
+            (0, "label length", 0, 0),
+            (1, "label length", 0, 1),
+            (2, "label", 2, 0),
+            (4, "tooltip length", 4, 0),
+            (6, "width", 6, 0),
+            (13, "height", 10, 3),
+            (14, "palette length", 14, 0),
+            (15, "palette", 15, 0),
+            (23, "pixels length", 23, 0),
+            (30, "pixels", 27, 3),
+        ];
+        for (length, field, offset, available) in cases {
+            match decode_2db(&bytes[..length], &DecodeOptions::default()) {
+                Err(PosterError::Truncated {
+                    field: found_field,
+                    offset: found_offset,
+                    available: found_available,
+                    ..
+                }) => assert_eq!(
+                    (found_field, found_offset, found_available),
+                    (field, offset, available),
+                    "cut at {}",
+                    length
+                ),
+                _ => panic!("cut at {} isn't reported as truncated", length),
+            }
+        }
+    }
+
+    #[test]
+    fn truncation_is_reported_for_every_cut() {
+        let bytes = array_bytes();
+        let page_starts: Vec<usize> = (0..3).map(|index| page_offset(&bytes, index)).collect();
+        for length in 0..bytes.len() {
+            let result = decode_2dba(&bytes[..length], &DecodeOptions::default());
+            // A cut in or right before a page length is an array without that page and the rest
+            if let Some(pages) = page_starts
+                .iter()
+                .position(|&start| (start..start + 4).contains(&length))
+            {
+                assert_eq!(result.unwrap().pages.len(), pages);
+                continue;
+            }
+            assert!(
+                matches!(result, Err(PosterError::Truncated { .. })),
+                "cut at {}",
+                length
+            );
+        }
+    }
+
+    fn strict() -> DecodeOptions {
+        return DecodeOptions { strict: true };
+    }
+
+    fn array_bytes() -> Vec<u8> {
+        return encode_book(&options(FormatVersion::V1));
+    }
+
+    #[test]
+    fn only_strict_rejects_trailing_bytes() {
+        let mut bytes = image_bytes();
+        bytes.extend([7, 7]);
+
+        assert_eq!(
+            decode_2db(bytes.as_slice(), &DecodeOptions::default())
+                .unwrap()
+                .pixels,
+            vec![0, 1, 1, 0]
+        );
+        assert!(matches!(
+            decode_2db(bytes.as_slice(), &strict()),
+            Err(PosterError::TrailingBytes {
+                offset: 31,
+                count: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn only_strict_rejects_pixels_outside_the_palette() {
+        let mut bytes = image_bytes();
+        bytes[29] = 5;
+
+        assert_eq!(
+            decode_2db(bytes.as_slice(), &DecodeOptions::default())
+                .unwrap()
+                .pixels,
+            vec![0, 1, 5, 0]
+        );
+        assert!(matches!(
+            decode_2db(bytes.as_slice(), &strict()),
+            Err(PosterError::PixelOutOfRange {
+                index: 2,
+                value: 5,
+                palette_length: 2
+            })
+        ));
+
+        let mut array = book();
+        array.pages[1].pixels[3] = 9;
+        let mut bytes: Vec<u8> = Vec::new();
+        let options = EncodeOptions {
+            validate: false,
+            ..Default::default()
+        };
+        encode_2dba(&array, &mut bytes, &options).unwrap();
+
+        assert!(decode_2dba(bytes.as_slice(), &DecodeOptions::default()).is_ok());
+        let pages: Vec<Result<Img2d, PosterError>> =
+            Img2dArrayDecoder::new(bytes.as_slice(), &strict())
+                .unwrap()
+                .collect();
+        assert!(pages[0].is_ok() && pages[2].is_ok());
+        assert!(matches!(
+            pages[1],
+            Err(PosterError::PixelOutOfRange {
+                index: 3,
+                value: 9,
+                palette_length: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn reads_the_checksum_right_after_the_image() {
+        let options = EncodeOptions {
+            version: FormatVersion::V2,
+            checksums: true,
+            ..Default::default()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(&image(Some("ab"), None), &mut bytes, &options).unwrap();
+        let end = bytes.len();
+        bytes.extend([7, 7]);
+
+        let decoded = decode_2db(bytes.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.label.as_deref(), Some("ab"));
+        assert!(matches!(
+            decode_2db(bytes.as_slice(), &strict()),
+            Err(PosterError::TrailingBytes { offset, count: 2 }) if offset == end
+        ));
+
+        // Without its checksum the image is cut short
+        assert!(matches!(
+            decode_2db(&bytes[..end - 2], &DecodeOptions::default()),
+            Err(PosterError::Truncated {
+                field: "file checksum",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn only_strict_rejects_partial_page_lengths() {
+        let mut bytes = array_bytes();
+        let end = bytes.len();
+        bytes.extend([1, 0]);
+
+        let decoded = decode_2dba(bytes.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.pages.len(), 3);
+        assert!(matches!(
+            decode_2dba(bytes.as_slice(), &strict()),
+            Err(PosterError::TrailingBytes { offset, count: 2 }) if offset == end
+        ));
+    }
+
+    #[test]
+    fn only_strict_rejects_page_length_mismatches() {
+        // The last page claims one more byte than it has
+        let mut bytes = array_bytes();
+        let start = page_offset(&bytes, 2);
+        let page_length = u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+        bytes[start..start + 4].copy_from_slice(&(page_length + 1).to_le_bytes());
+        bytes.push(7);
+
+        let decoded = decode_2dba(bytes.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.pages[2].label.as_deref(), Some("p3"));
+        let result = decode_2dba(bytes.as_slice(), &strict());
+        match result {
+            Err(PosterError::PageLengthMismatch {
+                offset,
+                declared,
+                consumed,
+            }) => assert_eq!(
+                (offset, declared, consumed),
+                (start + 4, page_length as usize + 1, page_length as usize)
+            ),
+            _ => panic!("page length mismatch isn't reported"),
+        }
+    }
+
+    #[test]
+    fn rejects_compressed_pixels_beyond_the_image_size() {
+        let options = EncodeOptions {
+            version: FormatVersion::V2,
+            compression: Compression::Deflate,
+            ..Default::default()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(&image(None, None), &mut bytes, &options).unwrap();
+
+        // A megabyte of zeroes deflates to about a kilobyte, declared for the 2x2 image
+        let payload = compression::compress(&vec![0; 1 << 20], Compression::Deflate);
+        bytes.truncate(28);
+        bytes.extend((1u32 << 20).to_le_bytes());
+        bytes.extend((payload.len() as u32).to_le_bytes());
+        bytes.extend(payload);
+
+        let result = decode_2db(bytes.as_slice(), &DecodeOptions::default());
+        assert!(matches!(
+            result,
+            Err(PosterError::PixelCountMismatch {
+                offset: 28,
+                declared: 1048576,
+                expected: 4,
+            })
+        ));
+    }
 }
@@ -0,0 +1,273 @@
+//! Rendering posters to the terminal.
+//!
+//! Every character cell shows two pixels stacked on top of each other, the upper one as the
+//! foreground colour of a `▀` and the lower one as the background colour.
+
+use crate::error::PosterError;
+use crate::export::{render_image, ExportOptions};
+use crate::poster::{unpack_rgb, Img2d};
+use image::imageops::{self, FilterType};
+use image::Rgba;
+use std::env;
+use std::fmt::Write;
+
+/// Levels of each channel in the 6x6x6 colour cube of the 256 colour palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorMode {
+    /// 24-bit colour escapes, drawing every palette colour exactly.
+    TrueColor,
+    /// The closest colour of the xterm 256 colour palette, for terminals without truecolor.
+    Ansi256,
+}
+
+impl ColorMode {
+    /// Parses the name of a mode as given on the command line.
+    pub fn from_name(name: &str) -> Option<ColorMode> {
+        return match name.to_ascii_lowercase().as_str() {
+            "truecolor" | "24bit" => Some(ColorMode::TrueColor),
+            "256" => Some(ColorMode::Ansi256),
+            _ => None,
+        };
+    }
+
+    /// Truecolor if `COLORTERM` says the terminal supports it, 256 colours otherwise.
+    pub fn detect() -> ColorMode {
+        return match env::var("COLORTERM") {
+            Ok(value) if value == "truecolor" || value == "24bit" => ColorMode::TrueColor,
+            _ => ColorMode::Ansi256,
+        };
+    }
+}
+
+pub struct PreviewOptions {
+    /// Width in columns the image is downscaled to fit, it's never upscaled.
+    pub max_width: u32,
+    pub color_mode: ColorMode,
+    /// Packed RGB colour drawn for pixels that index past the end of the palette.
+    pub error_color: u32,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        return PreviewOptions {
+            max_width: 80,
+            color_mode: ColorMode::TrueColor,
+            error_color: 0xFF00FF,
+        };
+    }
+}
+
+/// Index of the xterm 256 colour palette entry closest to `[r, g, b]`, either from the colour
+/// cube or the greyscale ramp.
+pub fn nearest_ansi256([r, g, b]: [u8; 3]) -> u8 {
+    let level = |value: u8| -> usize {
+        return match value {
+            0..=47 => 0,
+            48..=114 => 1,
+            _ => (value as usize - 35) / 40,
+        };
+    };
+    let distance = |[r2, g2, b2]: [u8; 3]| -> i32 {
+        let (dr, dg, db) = (
+            r as i32 - r2 as i32,
+            g as i32 - g2 as i32,
+            b as i32 - b2 as i32,
+        );
+        return dr * dr + dg * dg + db * db;
+    };
+
+    let (cr, cg, cb) = (level(r), level(g), level(b));
+    let cube = [CUBE_LEVELS[cr], CUBE_LEVELS[cg], CUBE_LEVELS[cb]];
+
+    // The ramp runs from 8 to 238 in steps of 10
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let grey_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let grey = 8 + grey_index * 10;
+
+    if distance([grey, grey, grey]) < distance(cube) {
+        return 232 + grey_index;
+    }
+
+    return 16 + (cr * 36 + cg * 6 + cb) as u8;
+}
+
+fn push_color(output: &mut String, color: Rgba<u8>, layer: u8, mode: ColorMode) {
+    let Rgba([r, g, b, _]) = color;
+    match mode {
+        ColorMode::TrueColor => write!(output, "\x1b[{};2;{};{};{}m", layer, r, g, b),
+        ColorMode::Ansi256 => write!(output, "\x1b[{};5;{}m", layer, nearest_ansi256([r, g, b])),
+    }
+    .expect("writing to a String can't fail");
+}
+
+/// Two spaces on a background of the packed RGB `color`, for showing palette entries.
+pub fn swatch(color: u32, mode: ColorMode) -> String {
+    let [r, g, b] = unpack_rgb(color);
+    let mut output = String::new();
+    push_color(&mut output, Rgba([r, g, b, 255]), 48, mode);
+    output.push_str("  \x1b[0m");
+
+    return output;
+}
+
+/// Renders `image` as lines of `▀` coloured with ANSI escapes, downscaling it to fit
+/// `max_width` columns.
+///
+/// Every line ends by resetting the colours. An odd last row of pixels is drawn on the
+/// terminal's own background. Fails if the image can't be rendered, see [`render_image`].
+pub fn render_preview(image: &Img2d, options: &PreviewOptions) -> Result<String, PosterError> {
+    let mut pixels = render_image(
+        image,
+        &ExportOptions {
+            error_color: options.error_color,
+        },
+    )?;
+
+    let max_width = options.max_width.max(1);
+    if pixels.width() > max_width {
+        let height = (pixels.height() as u64 * max_width as u64 / pixels.width() as u64).max(1);
+        pixels = imageops::resize(&pixels, max_width, height as u32, FilterType::Triangle);
+    }
+
+    let mut output = String::new();
+    for y in (0..pixels.height()).step_by(2) {
+        for x in 0..pixels.width() {
+            push_color(&mut output, *pixels.get_pixel(x, y), 38, options.color_mode);
+            if y + 1 < pixels.height() {
+                push_color(
+                    &mut output,
+                    *pixels.get_pixel(x, y + 1),
+                    48,
+                    options.color_mode,
+                );
+            } else {
+                output.push_str("\x1b[49m");
+            }
+            output.push('▀');
+        }
+        output.push_str("\x1b[0m\n");
+    }
+
+    return Ok(output);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::page;
+
+    fn image(width: u32, height: u32, pixels: Vec<u8>) -> Img2d {
+        return Img2d {
+            label: None,
+            tooltip: None,
+            palette: vec![0x000000, 0xFFFFFF, 0x102030],
+            pixels,
+            width,
+            height,
+        };
+    }
+
+    #[test]
+    fn stacks_two_pixels_per_cell() {
+        let preview =
+            render_preview(&image(2, 2, vec![0, 1, 2, 7]), &PreviewOptions::default()).unwrap();
+        assert_eq!(
+            preview,
+            concat!(
+                "\x1b[38;2;0;0;0m\x1b[48;2;16;32;48m▀",
+                "\x1b[38;2;255;255;255m\x1b[48;2;255;0;255m▀",
+                "\x1b[0m\n"
+            )
+        );
+    }
+
+    #[test]
+    fn draws_an_odd_last_row_on_the_terminal_background() {
+        let preview =
+            render_preview(&image(1, 3, vec![1, 1, 2]), &PreviewOptions::default()).unwrap();
+        assert_eq!(
+            preview,
+            concat!(
+                "\x1b[38;2;255;255;255m\x1b[48;2;255;255;255m▀\x1b[0m\n",
+                "\x1b[38;2;16;32;48m\x1b[49m▀\x1b[0m\n"
+            )
+        );
+    }
+
+    #[test]
+    fn uses_the_256_colour_palette() {
+        let options = PreviewOptions {
+            color_mode: ColorMode::Ansi256,
+            error_color: 0xFF0000,
+            ..Default::default()
+        };
+        let preview = render_preview(&image(1, 2, vec![1, 9]), &options).unwrap();
+        assert_eq!(preview, "\x1b[38;5;231m\x1b[48;5;196m▀\x1b[0m\n");
+    }
+
+    #[test]
+    fn downscales_to_fit() {
+        let mut wide = page("wide", 1);
+        (wide.width, wide.height) = (200, 100);
+        wide.pixels = vec![1; 200 * 100];
+        let options = PreviewOptions {
+            max_width: 40,
+            ..Default::default()
+        };
+        let preview = render_preview(&wide, &options).unwrap();
+
+        // 40 by 20 pixels, two rows per line
+        let lines: Vec<&str> = preview.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert!(lines
+            .iter()
+            .all(|line| line.chars().filter(|&c| c == '▀').count() == 40));
+
+        // Smaller images aren't upscaled
+        let preview = render_preview(&page("p1", 1), &options).unwrap();
+        assert_eq!(preview.lines().count(), 1);
+        assert_eq!(preview.chars().filter(|&c| c == '▀').count(), 3);
+    }
+
+    #[test]
+    fn fails_for_images_it_cant_render() {
+        assert!(render_preview(&image(2, 2, vec![0]), &PreviewOptions::default()).is_err());
+    }
+
+    #[test]
+    fn finds_the_nearest_256_colour() {
+        assert_eq!(nearest_ansi256([0, 0, 0]), 16);
+        assert_eq!(nearest_ansi256([255, 255, 255]), 231);
+        assert_eq!(nearest_ansi256([255, 0, 0]), 196);
+        assert_eq!(nearest_ansi256([95, 135, 175]), 16 + 36 + 2 * 6 + 3);
+        // Greys between the cube levels come from the ramp
+        assert_eq!(nearest_ansi256([128, 128, 128]), 244);
+        assert_eq!(nearest_ansi256([8, 8, 8]), 232);
+        assert_eq!(nearest_ansi256([238, 238, 238]), 255);
+    }
+
+    #[test]
+    fn draws_swatches() {
+        assert_eq!(
+            swatch(0x102030, ColorMode::TrueColor),
+            "\x1b[48;2;16;32;48m  \x1b[0m"
+        );
+        assert_eq!(
+            swatch(0xFFFFFF, ColorMode::Ansi256),
+            "\x1b[48;5;231m  \x1b[0m"
+        );
+    }
+
+    #[test]
+    fn parses_color_modes() {
+        assert_eq!(
+            ColorMode::from_name("TrueColor"),
+            Some(ColorMode::TrueColor)
+        );
+        assert_eq!(ColorMode::from_name("24bit"), Some(ColorMode::TrueColor));
+        assert_eq!(ColorMode::from_name("256"), Some(ColorMode::Ansi256));
+        assert_eq!(ColorMode::from_name("16"), None);
+    }
+}
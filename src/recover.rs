@@ -0,0 +1,239 @@
+use crate::cursor::ByteCursor;
+use crate::error::PosterError;
+use crate::poster::{
+    check_checksum, parse_byte_array_as_image, DecodeOptions, FormatVersion, Img2d, Img2dArray,
+    Img2dArrayDecoder, END_OF_PAGES, FLAG_CHECKSUMS,
+};
+use std::fs;
+use std::path::Path;
+
+/// A damaged stretch of a 2dba that [`recover_2dba`] had to skip.
+pub struct SkippedRange {
+    /// Where the stretch starts in the file.
+    pub offset: usize,
+    pub length: usize,
+    /// Number of pages recovered before the stretch.
+    pub after_page: usize,
+    /// Why the page at `offset` couldn't be read.
+    pub error: PosterError,
+}
+
+/// The pages [`recover_2dba`] could decode and what it had to skip to get them.
+pub struct Recovery {
+    pub version: FormatVersion,
+    pub flags: u8,
+    /// The array with every page that could be decoded, in file order.
+    pub image: Img2dArray,
+    pub skipped: Vec<SkippedRange>,
+    /// Mismatch of the file checksum, which also covers the title, width and height.
+    pub file_error: Option<PosterError>,
+}
+
+impl Recovery {
+    pub fn is_complete(&self) -> bool {
+        return self.skipped.is_empty() && self.file_error.is_none();
+    }
+}
+
+/// Where a page or the end of the pages was found.
+enum Found {
+    Page(Img2d, usize),
+    End(Option<PosterError>),
+}
+
+/// Reads the page (or the end of the pages) at `offset` in strict mode, returning it with the
+/// offset right after it.
+fn read_page_at(bytes: &[u8], offset: usize, flags: u8) -> Result<Found, PosterError> {
+    let checksums = flags & FLAG_CHECKSUMS != 0;
+    let mut cursor = ByteCursor::new(&bytes[offset..], offset);
+
+    let page_length = cursor.u32("page length")?;
+    if checksums && page_length == END_OF_PAGES {
+        let stored_offset = cursor.offset();
+        let stored = cursor.u32("file checksum")?;
+        if cursor.remaining() != 0 {
+            return Err(PosterError::TrailingBytes {
+                offset: cursor.offset(),
+                count: cursor.remaining(),
+            });
+        }
+
+        let error = check_checksum(
+            &bytes[..stored_offset],
+            stored,
+            stored_offset,
+            "file checksum",
+        );
+        return Ok(Found::End(error.err()));
+    }
+
+    let page_offset = cursor.offset();
+    let page = cursor.bytes(page_length as usize, "page")?;
+    if checksums {
+        let checksum_offset = cursor.offset();
+        check_checksum(
+            page,
+            cursor.u32("page checksum")?,
+            checksum_offset,
+            "page checksum",
+        )?;
+    }
+
+    let (image, consumed) = parse_byte_array_as_image(page, page_offset, flags)?;
+    if consumed != page.len() {
+        return Err(PosterError::PageLengthMismatch {
+            offset: page_offset,
+            declared: page.len(),
+            consumed,
+        });
+    }
+
+    return Ok(Found::Page(image, cursor.offset()));
+}
+
+/// Whether a page that resynchronisation found at some offset is believable. Random bytes
+/// rarely parse exactly, but short ones can, so the page also has to be valid.
+fn is_plausible(found: &Result<Found, PosterError>) -> bool {
+    return match found {
+        Ok(Found::Page(image, _)) => image.validate().is_ok(),
+        Ok(Found::End(_)) => true,
+        Err(_) => false,
+    };
+}
+
+/// Decodes as many pages of a damaged 2dba as possible.
+///
+/// Pages that fail to parse or don't match their checksum are skipped. When a damaged page
+/// length makes the next page impossible to find, the file is scanned byte by byte for the
+/// next offset at which a valid page (or the end of the pages) can be read. Only fails if the
+/// array header can't be read.
+pub fn recover_2dba(bytes: &[u8]) -> Result<Recovery, PosterError> {
+    let decoder = Img2dArrayDecoder::new(bytes, &DecodeOptions::default())?;
+    let flags = decoder.flags;
+    let mut recovery = Recovery {
+        version: decoder.version,
+        flags,
+        image: Img2dArray {
+            width: decoder.width,
+            height: decoder.height,
+            title: decoder.title.clone(),
+            pages: Vec::new(),
+        },
+        skipped: Vec::new(),
+        file_error: None,
+    };
+
+    let checksum_length = if flags & FLAG_CHECKSUMS != 0 { 4 } else { 0 };
+    let mut offset = decoder.offset();
+    while offset < bytes.len() {
+        let error = match read_page_at(bytes, offset, flags) {
+            Ok(Found::Page(image, next)) => {
+                recovery.image.pages.push(image);
+                offset = next;
+                continue;
+            }
+            Ok(Found::End(file_error)) => {
+                recovery.file_error = file_error;
+                break;
+            }
+            Err(e) => e,
+        };
+
+        // If the page length is intact only this page is lost, otherwise resynchronise
+        let declared_end = ByteCursor::new(&bytes[offset..], offset)
+            .u32("page length")
+            .map(|page_length| offset + 4 + page_length as usize + checksum_length);
+        let next = match declared_end {
+            Ok(end)
+                if end == bytes.len()
+                    || (end < bytes.len() && is_plausible(&read_page_at(bytes, end, flags))) =>
+            {
+                end
+            }
+            _ => (offset + 1..bytes.len())
+                .find(|&candidate| is_plausible(&read_page_at(bytes, candidate, flags)))
+                .unwrap_or(bytes.len()),
+        };
+
+        recovery.skipped.push(SkippedRange {
+            offset,
+            length: next - offset,
+            after_page: recovery.image.pages.len(),
+            error,
+        });
+        offset = next;
+    }
+
+    return Ok(recovery);
+}
+
+pub fn recover_2dba_file(file: &Path) -> Result<Recovery, PosterError> {
+    return recover_2dba(&fs::read(file)?);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poster::EncodeOptions;
+    use crate::testing::{encode_book, page_offset};
+
+    fn array_bytes() -> Vec<u8> {
+        return encode_book(&EncodeOptions {
+            version: FormatVersion::V2,
+            checksums: true,
+            ..Default::default()
+        });
+    }
+
+    fn labels(recovery: &Recovery) -> Vec<&str> {
+        return recovery
+            .image
+            .pages
+            .iter()
+            .map(|page| page.label.as_deref().unwrap())
+            .collect();
+    }
+
+    #[test]
+    fn recovers_intact_array() {
+        let recovery = recover_2dba(&array_bytes()).unwrap();
+        assert!(recovery.is_complete());
+        assert_eq!(labels(&recovery), vec!["p1", "p2", "p3"]);
+    }
+
+    #[test]
+    fn skips_page_failing_its_checksum() {
+        let mut bytes = array_bytes();
+        let (start, end) = (page_offset(&bytes, 1), page_offset(&bytes, 2));
+        bytes[end - 5] ^= 1;
+
+        let recovery = recover_2dba(&bytes).unwrap();
+        assert_eq!(labels(&recovery), vec!["p1", "p3"]);
+        assert_eq!(recovery.skipped.len(), 1);
+        let skipped = &recovery.skipped[0];
+        assert_eq!((skipped.offset, skipped.length), (start, end - start));
+        assert_eq!(skipped.after_page, 1);
+        assert!(matches!(
+            skipped.error,
+            PosterError::ChecksumMismatch {
+                field: "page checksum",
+                ..
+            }
+        ));
+        assert!(recovery.file_error.is_some());
+    }
+
+    #[test]
+    fn resynchronises_after_damaged_page_length() {
+        let mut bytes = array_bytes();
+        let (start, end) = (page_offset(&bytes, 1), page_offset(&bytes, 2));
+        bytes[start + 3] = 0xFF;
+
+        let recovery = recover_2dba(&bytes).unwrap();
+        assert_eq!(labels(&recovery), vec!["p1", "p3"]);
+        assert_eq!(recovery.skipped.len(), 1);
+        assert_eq!(recovery.skipped[0].offset, start);
+        assert_eq!(recovery.skipped[0].length, end - start);
+        assert_eq!(recovery.skipped[0].after_page, 1);
+    }
+}
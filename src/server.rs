@@ -0,0 +1,839 @@
+//! A small HTTP server handing out the posters in a directory, converted on the fly to the
+//! format each request asks for.
+//!
+//! - `GET /art/castle.2db` serves the poster `art/castle` as a 2db, whatever format its file
+//!   is in.
+//! - `GET /art/castle?format=2dj` does the same with the format given in the query.
+//! - `GET /book/lore?page=3` serves page 3 of the image array `book/lore` as a single image.
+//! - `GET /index.json` lists every poster below the root, `/art/index.json` those below `art`.
+//!
+//! Symlinks are followed as long as they lead to somewhere below the root, anything else is
+//! answered as if it didn't exist.
+//!
+//! Responses carry an `ETag` and a `Last-Modified` derived from the source file, requests
+//! with a matching `If-None-Match` or `If-Modified-Since` get `304 Not Modified`.
+//!
+//! With the `api` feature, `POST /convert` also converts uploaded images, see [`crate::api`].
+
+use crate::error::PosterError;
+use crate::export::{encode_png, render_contact_sheet, render_image};
+use crate::export::{ContactSheetOptions, ExportOptions};
+use crate::import::{decode_image, is_image_path, ImportOptions};
+use crate::poster::{
+    decode_2db, decode_2dba, decode_2dj, decode_2dja, detect_format, encode_2db, encode_2dba,
+    encode_2dj, encode_2dja, DecodeOptions, EncodeOptions, Img2d, Img2dArray, PosterFormat,
+};
+use std::fs;
+use std::io;
+use std::io::{Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Response, Server};
+
+/// Largest request body accepted, bigger uploads are answered with `413`.
+pub const MAX_BODY_LENGTH: usize = 16 * 1024 * 1024;
+
+pub struct ServeOptions {
+    /// Directory the posters are served from.
+    pub root: PathBuf,
+    /// Address to listen on, like `127.0.0.1:8080`.
+    pub address: String,
+    /// How posters are encoded when they're converted.
+    pub encode: EncodeOptions,
+    /// How raster images in the directory are imported.
+    pub import: ImportOptions,
+}
+
+/// The parts of a request [`respond`] looks at.
+pub struct Request<'a> {
+    pub method: &'a str,
+    /// Path and query, like `/book/lore?page=3`.
+    pub url: &'a str,
+    pub if_none_match: Option<&'a str>,
+    pub if_modified_since: Option<&'a str>,
+    /// The body of a `POST`, empty for other methods.
+    pub body: &'a [u8],
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Reply {
+    fn text(status: u16, message: &str) -> Self {
+        return Reply {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", message).into_bytes(),
+            etag: None,
+            last_modified: None,
+        };
+    }
+
+    fn json(value: &serde_json::Value) -> Self {
+        return Reply {
+            status: 200,
+            content_type: "application/json",
+            body: serde_json::to_vec_pretty(value).expect("serializing JSON can't fail"),
+            etag: None,
+            last_modified: None,
+        };
+    }
+}
+
+/// What a poster can be served as.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Target {
+    Poster(PosterFormat),
+    Png,
+}
+
+impl Target {
+    pub(crate) fn from_name(name: &str) -> Option<Target> {
+        if name.eq_ignore_ascii_case("png") {
+            return Some(Target::Png);
+        }
+
+        return PosterFormat::from_extension(name).map(Target::Poster);
+    }
+
+    fn name(&self) -> &'static str {
+        return match self {
+            Target::Poster(format) => format.extension(),
+            Target::Png => "png",
+        };
+    }
+
+    pub(crate) fn content_type(&self) -> &'static str {
+        return match self {
+            Target::Poster(PosterFormat::Json | PosterFormat::JsonArray) => "application/json",
+            Target::Poster(PosterFormat::Binary | PosterFormat::BinaryArray) => {
+                "application/octet-stream"
+            }
+            Target::Png => "image/png",
+        };
+    }
+}
+
+/// A decoded source file.
+pub(crate) enum Loaded {
+    Image(Img2d),
+    Array(Img2dArray),
+}
+
+/// Decodes percent escapes, and `+` as a space if `plus_as_space`.
+pub(crate) fn percent_decode(text: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    return String::from_utf8(decoded).ok();
+}
+
+/// Joins a decoded URL path onto `root`, `None` if it tries to leave it.
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    return Some(root.join(relative));
+}
+
+/// Whether `path` is still below `root` (which has to be canonical) once symlinks are
+/// followed, so a link in the served directory can't hand out files from elsewhere.
+fn is_within(root: &Path, path: &Path) -> bool {
+    return path.canonicalize().is_ok_and(|path| path.starts_with(root));
+}
+
+/// Whether `file` is in a format posters can be served from.
+fn is_source(file: &Path) -> bool {
+    let is_poster = file
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(PosterFormat::from_extension)
+        .is_some();
+
+    return is_poster || is_image_path(file);
+}
+
+/// Finds the file a poster is served from: `file` itself if it exists, otherwise a file with
+/// the same name and a poster (preferred) or raster extension.
+fn find_source(file: &Path) -> Option<PathBuf> {
+    if file.is_file() {
+        return Some(file.to_path_buf());
+    }
+
+    let stem = file.file_stem()?;
+    let mut candidates: Vec<PathBuf> = fs::read_dir(file.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.file_stem() == Some(stem) && is_source(path))
+        .collect();
+    candidates.sort_by_key(|path| (is_image_path(path), path.clone()));
+
+    return candidates.into_iter().next();
+}
+
+fn load(
+    bytes: &[u8],
+    format: Option<PosterFormat>,
+    import: &ImportOptions,
+) -> Result<Loaded, PosterError> {
+    let decode = DecodeOptions::default();
+
+    return match format {
+        None => decode_image(Cursor::new(bytes), import).map(Loaded::Image),
+        Some(PosterFormat::Json) => decode_2dj(bytes).map(Loaded::Image),
+        Some(PosterFormat::Binary) => decode_2db(bytes, &decode).map(Loaded::Image),
+        Some(PosterFormat::JsonArray) => decode_2dja(bytes).map(Loaded::Array),
+        Some(PosterFormat::BinaryArray) => decode_2dba(bytes, &decode).map(Loaded::Array),
+    };
+}
+
+pub(crate) fn encode(
+    loaded: &Loaded,
+    target: Target,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, PosterError> {
+    let mut body: Vec<u8> = Vec::new();
+    match (loaded, target) {
+        (Loaded::Image(image), Target::Poster(PosterFormat::Json)) => {
+            encode_2dj(image, &mut body, options)?
+        }
+        (Loaded::Image(image), Target::Poster(PosterFormat::Binary)) => {
+            encode_2db(image, &mut body, options)?
+        }
+        (Loaded::Image(image), Target::Png) => {
+            encode_png(&render_image(image, &ExportOptions::default())?, &mut body)?
+        }
+        (Loaded::Array(image), Target::Poster(PosterFormat::JsonArray)) => {
+            encode_2dja(image, &mut body, options)?
+        }
+        (Loaded::Array(image), Target::Poster(PosterFormat::BinaryArray)) => {
+            encode_2dba(image, &mut body, options)?
+        }
+        (Loaded::Array(image), Target::Png) => encode_png(
+            &render_contact_sheet(
+                image,
+                &ExportOptions::default(),
+                &ContactSheetOptions::default(),
+            )?,
+            &mut body,
+        )?,
+        _ => unreachable!("Mismatched targets are rejected before encoding"),
+    }
+
+    return Ok(body);
+}
+
+/// Strong validator for a response, from the source file and everything the body depends on.
+fn etag(
+    file: &Path,
+    metadata: &fs::Metadata,
+    target: Target,
+    page: Option<usize>,
+    options: &EncodeOptions,
+    import: &ImportOptions,
+) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    let key = format!(
+        "{}:{}:{}:{}:{:?}:{}:{}:{}:{}:{:?}:{:?}:{}:{:?}:{:?}:{}:{:?}:{:?}",
+        file.display(),
+        metadata.len(),
+        modified,
+        target.name(),
+        page,
+        options.version.number(),
+        options.compression.id(),
+        options.checksums,
+        options.validate,
+        options.unrepresentable,
+        import.quantizer,
+        import.max_colors,
+        import.palette,
+        import.dither,
+        import.dither_strength,
+        import.label,
+        import.tooltip
+    );
+
+    return format!(
+        "\"{:x}-{:08x}\"",
+        metadata.len(),
+        crc32fast::hash(key.as_bytes())
+    );
+}
+
+/// Whether the client's cached copy is still current.
+fn is_fresh(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = request.if_none_match {
+        return if_none_match
+            .split(',')
+            .any(|tag| tag.trim() == etag || tag.trim() == "*");
+    }
+
+    return match (request.if_modified_since, modified) {
+        (Some(since), Some(modified)) => httpdate::parse_http_date(since).is_ok_and(|since| {
+            modified
+                .duration_since(since)
+                .map_or(true, |d| d.as_secs() == 0)
+        }),
+        _ => false,
+    };
+}
+
+/// Lists the posters below `directory`, recursing into subdirectories. Entries that lead
+/// out of `canonical_root` are left out and symlinked directories aren't followed, so a link
+/// can't make the listing loop.
+fn list(
+    root: &Path,
+    canonical_root: &Path,
+    directory: &Path,
+    entries: &mut Vec<serde_json::Value>,
+) -> io::Result<()> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            !path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        if !is_within(canonical_root, &path) {
+            continue;
+        }
+        if path.is_dir() {
+            if !path.is_symlink() {
+                list(root, canonical_root, &path, entries)?;
+            }
+            continue;
+        }
+        if !is_source(&path) {
+            continue;
+        }
+
+        let metadata = fs::metadata(&path)?;
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let url_path = relative
+            .with_extension("")
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+            .join("/");
+        let format = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(PosterFormat::from_extension);
+
+        entries.push(serde_json::json!({
+            "path": url_path,
+            "file": relative.to_string_lossy().replace('\\', "/"),
+            "format": format.map_or("image", |format| format.extension()),
+            "array": format.is_some_and(|format| format.is_array()),
+            "size": metadata.len(),
+            "modified": metadata.modified().ok().map(httpdate::fmt_http_date),
+        }));
+    }
+
+    return Ok(());
+}
+
+/// Answers a single request, see the module documentation for the URLs understood.
+pub fn respond(request: &Request, options: &ServeOptions) -> Reply {
+    let (path, query) = request.url.split_once('?').unwrap_or((request.url, ""));
+    #[cfg(feature = "api")]
+    if request.method == "POST" && path == "/convert" {
+        return crate::api::convert(query, request.body, options);
+    }
+    if request.method != "GET" && request.method != "HEAD" {
+        return Reply::text(405, "Only GET and HEAD are supported.");
+    }
+
+    let path = match percent_decode(path, false) {
+        Some(t) => t,
+        None => return Reply::text(400, "Invalid path."),
+    };
+    let file = match resolve(&options.root, &path) {
+        Some(t) => t,
+        None => return Reply::text(404, "Not found."),
+    };
+    let canonical_root = match options.root.canonicalize() {
+        Ok(t) => t,
+        Err(e) => return Reply::text(500, &format!("Failed to read the root: {}", e)),
+    };
+
+    let mut format: Option<String> = None;
+    let mut page: Option<String> = None;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = match percent_decode(value, true) {
+            Some(t) => t,
+            None => return Reply::text(400, "Invalid query."),
+        };
+        match key {
+            "format" => format = Some(value),
+            "page" => page = Some(value),
+            _ => {}
+        }
+    }
+
+    if file.file_name().is_some_and(|name| name == "index.json") {
+        let directory = file.parent().unwrap_or(&options.root);
+        if directory.is_dir() && is_within(&canonical_root, directory) {
+            let mut entries: Vec<serde_json::Value> = Vec::new();
+            return match list(&options.root, &canonical_root, directory, &mut entries) {
+                Ok(()) => Reply::json(&serde_json::Value::Array(entries)),
+                Err(e) => Reply::text(500, &format!("Failed to list posters: {}", e)),
+            };
+        }
+    }
+
+    let page = match page.map(|page| page.parse::<usize>()) {
+        Some(Ok(page)) if page >= 1 => Some(page),
+        Some(_) => return Reply::text(400, "Invalid page, pages are numbered from 1."),
+        None => None,
+    };
+    let requested = match format {
+        Some(format) => match Target::from_name(&format) {
+            Some(t) => Some(t),
+            None => {
+                return Reply::text(
+                    400,
+                    "Invalid format, valid formats are (2dj,2dja,2db,2dba,png).",
+                )
+            }
+        },
+        None => file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Target::from_name),
+    };
+
+    let source = match find_source(&file) {
+        Some(t) if is_within(&canonical_root, &t) => t,
+        _ => return Reply::text(404, &format!("No poster at {}.", path)),
+    };
+    let (bytes, metadata) =
+        match fs::read(&source).and_then(|bytes| Ok((bytes, fs::metadata(&source)?))) {
+            Ok(t) => t,
+            Err(e) => return Reply::text(500, &format!("Failed to read {}: {}", path, e)),
+        };
+    // The format comes from the extension, or from the content for a file served as is
+    let by_extension = source
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(PosterFormat::from_extension);
+    let source_format = match by_extension.or_else(|| detect_format(&bytes)) {
+        Some(format) => Some(format),
+        None if is_image_path(&source) => None,
+        None => return Reply::text(415, &format!("{} isn't a poster or raster image.", path)),
+    };
+
+    // Without a format, posters are served as they are and raster images as 2dj
+    let single = |format: Option<PosterFormat>| match format {
+        Some(PosterFormat::JsonArray) => PosterFormat::Json,
+        Some(PosterFormat::BinaryArray) => PosterFormat::Binary,
+        Some(format) => format,
+        None => PosterFormat::Json,
+    };
+    let target = requested.unwrap_or(Target::Poster(match page {
+        Some(_) => single(source_format),
+        None => source_format.unwrap_or(PosterFormat::Json),
+    }));
+
+    let modified = metadata.modified().ok();
+    let etag = etag(
+        &source,
+        &metadata,
+        target,
+        page,
+        &options.encode,
+        &options.import,
+    );
+    if is_fresh(request, &etag, modified) {
+        return Reply {
+            status: 304,
+            content_type: target.content_type(),
+            body: Vec::new(),
+            etag: Some(etag),
+            last_modified: modified,
+        };
+    }
+
+    let body = if page.is_none() && Some(target) == source_format.map(Target::Poster) {
+        bytes
+    } else {
+        let loaded = match load(&bytes, source_format, &options.import) {
+            Ok(t) => t,
+            Err(e) => return Reply::text(500, &format!("Failed to read {}: {}", path, e)),
+        };
+        let loaded = match (loaded, page) {
+            (Loaded::Array(mut image), Some(page)) if page <= image.pages.len() => {
+                Loaded::Image(image.pages.swap_remove(page - 1))
+            }
+            (Loaded::Array(image), Some(page)) => {
+                return Reply::text(
+                    404,
+                    &format!(
+                        "Page {} doesn't exist, {} has {} pages.",
+                        page,
+                        path,
+                        image.pages.len()
+                    ),
+                )
+            }
+            (Loaded::Image(_), Some(_)) => {
+                return Reply::text(400, &format!("{} is a single image without pages.", path))
+            }
+            (loaded, None) => loaded,
+        };
+
+        match (&loaded, target) {
+            (Loaded::Image(_), Target::Poster(format)) if format.is_array() => {
+                return Reply::text(
+                    400,
+                    &format!(
+                        "{} is a single image, it can't be served as {}.",
+                        path,
+                        format.extension()
+                    ),
+                )
+            }
+            (Loaded::Array(_), Target::Poster(format)) if !format.is_array() => {
+                return Reply::text(
+                    400,
+                    &format!(
+                        "{} is an image array, ask for a page or for 2dja/2dba.",
+                        path
+                    ),
+                )
+            }
+            _ => {}
+        }
+
+        match encode(&loaded, target, &options.encode) {
+            Ok(t) => t,
+            Err(e) => return Reply::text(500, &format!("Failed to convert {}: {}", path, e)),
+        }
+    };
+
+    return Reply {
+        status: 200,
+        content_type: target.content_type(),
+        body,
+        etag: Some(etag),
+        last_modified: modified,
+    };
+}
+
+fn header(field: &str, value: &str) -> Header {
+    return Header::from_bytes(field.as_bytes(), value.as_bytes())
+        .expect("header fields and values are ASCII");
+}
+
+/// Serves `options.root` until the process is stopped, calling `log` with the method, URL
+/// and status of every request.
+pub fn serve<F>(options: &ServeOptions, mut log: F) -> Result<(), PosterError>
+where
+    F: FnMut(&str, &str, u16),
+{
+    let server = Server::http(&options.address).map_err(io::Error::other)?;
+
+    for mut request in server.incoming_requests() {
+        // Reading one byte past the limit lets the API tell a body that's too large apart
+        let mut body: Vec<u8> = Vec::new();
+        if request.method().as_str() == "POST" {
+            let mut reader = request.as_reader().take(MAX_BODY_LENGTH as u64 + 1);
+            if let Err(e) = reader.read_to_end(&mut body) {
+                log(request.method().as_str(), request.url(), 400);
+                let _ = request.respond(
+                    Response::from_string(format!("Failed to read the body: {}\n", e))
+                        .with_status_code(400),
+                );
+                continue;
+            }
+        }
+
+        let find = |name: &'static str| {
+            request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv(name))
+                .map(|header| header.value.as_str())
+        };
+        let method = request.method().as_str().to_string();
+        let url = request.url().to_string();
+
+        let reply = respond(
+            &Request {
+                method: &method,
+                url: &url,
+                if_none_match: find("If-None-Match"),
+                if_modified_since: find("If-Modified-Since"),
+                body: &body,
+            },
+            options,
+        );
+        log(&method, &url, reply.status);
+
+        let mut response = Response::from_data(reply.body)
+            .with_status_code(reply.status)
+            .with_header(header("Content-Type", reply.content_type));
+        if let Some(etag) = &reply.etag {
+            response.add_header(header("ETag", etag));
+        }
+        if let Some(modified) = reply.last_modified {
+            response.add_header(header("Last-Modified", &httpdate::fmt_http_date(modified)));
+        }
+
+        // A client hanging up early is its own problem, keep serving the others
+        let _ = request.respond(response);
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{book, page, TempDir};
+
+    fn options(root: &TempDir) -> ServeOptions {
+        return ServeOptions {
+            root: root.path.clone(),
+            address: String::new(),
+            encode: EncodeOptions::default(),
+            import: ImportOptions::default(),
+        };
+    }
+
+    fn get(url: &str) -> Request<'_> {
+        return Request {
+            method: "GET",
+            url,
+            if_none_match: None,
+            if_modified_since: None,
+            body: &[],
+        };
+    }
+
+    /// A root with the image `art/castle` as a 2dj and the array `book/lore` as a 2dba.
+    fn root() -> TempDir {
+        let root = TempDir::new();
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2dj(&page("castle", 1), &mut bytes, &EncodeOptions::default()).unwrap();
+        root.write("art/castle.2dj", &bytes);
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2dba(&book(), &mut bytes, &EncodeOptions::default()).unwrap();
+        root.write("book/lore.2dba", &bytes);
+
+        return root;
+    }
+
+    fn listed_paths(reply: &Reply) -> Vec<String> {
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&reply.body).unwrap();
+
+        return entries
+            .iter()
+            .map(|entry| entry["path"].as_str().unwrap().to_string())
+            .collect();
+    }
+
+    #[test]
+    fn serves_posters_as_they_are_or_converted() {
+        let root = root();
+        let options = options(&root);
+
+        let reply = respond(&get("/art/castle.2dj"), &options);
+        assert_eq!(
+            (reply.status, reply.content_type),
+            (200, "application/json")
+        );
+        assert_eq!(reply.body, fs::read(root.join("art/castle.2dj")).unwrap());
+
+        let reply = respond(&get("/art/castle.2db"), &options);
+        assert_eq!(reply.content_type, "application/octet-stream");
+        let image = decode_2db(reply.body.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(image.label.as_deref(), Some("castle"));
+
+        let reply = respond(&get("/art/castle?format=png"), &options);
+        assert_eq!((reply.status, reply.content_type), (200, "image/png"));
+        assert_eq!(
+            image::load_from_memory(&reply.body).unwrap().width(),
+            page("castle", 1).width
+        );
+
+        assert_eq!(
+            respond(&get("/art/castle?format=gif"), &options).status,
+            400
+        );
+        assert_eq!(respond(&get("/art/missing.2dj"), &options).status, 404);
+    }
+
+    #[test]
+    fn rejects_other_methods() {
+        let root = root();
+        let request = Request {
+            method: "DELETE",
+            ..get("/art/castle.2dj")
+        };
+        assert_eq!(respond(&request, &options(&root)).status, 405);
+    }
+
+    #[test]
+    fn serves_single_pages() {
+        let root = root();
+        let options = options(&root);
+
+        let reply = respond(&get("/book/lore?page=2"), &options);
+        assert_eq!(reply.status, 200);
+        let image = decode_2db(reply.body.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(image.label.as_deref(), Some("p2"));
+
+        let reply = respond(&get("/book/lore.2dj?page=3"), &options);
+        let image = decode_2dj(reply.body.as_slice()).unwrap();
+        assert_eq!(image.label.as_deref(), Some("p3"));
+
+        assert_eq!(respond(&get("/book/lore?page=4"), &options).status, 404);
+        assert_eq!(respond(&get("/book/lore?page=0"), &options).status, 400);
+        assert_eq!(respond(&get("/book/lore?page=x"), &options).status, 400);
+        assert_eq!(respond(&get("/art/castle?page=1"), &options).status, 400);
+        // A whole array can't be served as a single image
+        assert_eq!(respond(&get("/book/lore.2db"), &options).status, 400);
+    }
+
+    #[test]
+    fn stays_within_the_root() {
+        let outside = TempDir::new();
+        outside.write("secret.2dj", b"{}");
+        let root = root();
+        let root_name = root.path.file_name().unwrap().to_string_lossy();
+        let outside_name = outside.path.file_name().unwrap().to_string_lossy();
+        let options = options(&root);
+
+        for url in [
+            format!("/../{}/secret.2dj", outside_name),
+            format!("/art/%2e%2e/%2e%2e/{}/secret.2dj", outside_name),
+            format!("/art/..%2f..%2f{}/secret.2dj", outside_name),
+            format!("/{}/../../{}/secret.2dj", root_name, outside_name),
+        ] {
+            assert_eq!(respond(&get(&url), &options).status, 404, "{}", url);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn doesnt_follow_symlinks_out_of_the_root() {
+        let outside = TempDir::new();
+        let secret = outside.write("secret.2dj", b"{}");
+        let root = root();
+        std::os::unix::fs::symlink(&secret, root.join("link.2dj")).unwrap();
+        std::os::unix::fs::symlink(&outside.path, root.join("elsewhere")).unwrap();
+        // Links within the root are fine
+        std::os::unix::fs::symlink(root.join("art/castle.2dj"), root.join("alias.2dj")).unwrap();
+        let options = options(&root);
+
+        assert_eq!(respond(&get("/link.2dj"), &options).status, 404);
+        assert_eq!(respond(&get("/link"), &options).status, 404);
+        assert_eq!(respond(&get("/elsewhere/secret.2dj"), &options).status, 404);
+        assert_eq!(respond(&get("/elsewhere/index.json"), &options).status, 404);
+        assert_eq!(respond(&get("/alias.2dj"), &options).status, 200);
+
+        let reply = respond(&get("/index.json"), &options);
+        assert_eq!(
+            listed_paths(&reply),
+            vec!["alias", "art/castle", "book/lore"]
+        );
+    }
+
+    #[test]
+    fn lists_posters() {
+        let root = root();
+        root.write("notes.txt", b"not a poster");
+        root.write(".hidden.2dj", b"{}");
+        let options = options(&root);
+
+        let reply = respond(&get("/index.json"), &options);
+        assert_eq!(
+            (reply.status, reply.content_type),
+            (200, "application/json")
+        );
+        assert_eq!(listed_paths(&reply), vec!["art/castle", "book/lore"]);
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&reply.body).unwrap();
+        assert_eq!(entries[1]["file"], "book/lore.2dba");
+        assert_eq!(entries[1]["format"], "2dba");
+        assert_eq!(entries[1]["array"], true);
+
+        let reply = respond(&get("/art/index.json"), &options);
+        assert_eq!(listed_paths(&reply), vec!["art/castle"]);
+    }
+
+    #[test]
+    fn answers_cached_copies_with_not_modified() {
+        let root = root();
+        let options = options(&root);
+
+        let reply = respond(&get("/art/castle.2db"), &options);
+        let etag = reply.etag.unwrap();
+        let modified = httpdate::fmt_http_date(reply.last_modified.unwrap());
+
+        let cached = |if_none_match, if_modified_since| {
+            return respond(
+                &Request {
+                    if_none_match,
+                    if_modified_since,
+                    ..get("/art/castle.2db")
+                },
+                &options,
+            );
+        };
+        let reply = cached(Some(&etag), None);
+        assert_eq!(reply.status, 304);
+        assert!(reply.body.is_empty());
+        assert_eq!(reply.etag.as_deref(), Some(etag.as_str()));
+        assert_eq!(cached(Some("\"other\", *"), None).status, 304);
+        assert_eq!(cached(None, Some(&modified)).status, 304);
+        assert_eq!(cached(Some("\"other\""), None).status, 200);
+        // If-None-Match wins over If-Modified-Since
+        assert_eq!(cached(Some("\"other\""), Some(&modified)).status, 200);
+
+        // Another format or page is another response
+        let other = respond(&get("/art/castle.2dj"), &options);
+        assert_ne!(other.etag.as_deref(), Some(etag.as_str()));
+        let first = respond(&get("/book/lore?page=1"), &options).etag;
+        let second = respond(&get("/book/lore?page=2"), &options).etag;
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,115 @@
+//! Fixtures shared by the unit tests.
+
+use crate::poster::{
+    encode_2dba, EncodeOptions, Img2d, Img2dArray, ABSENT_STRING_LENGTH, ARRAY_MAGIC,
+    FLAG_CHECKSUMS,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A black and white 3x2 page labelled `label` with every pixel set to `value`.
+pub fn page(label: &str, value: u8) -> Img2d {
+    return Img2d {
+        label: Some(label.to_string()),
+        tooltip: None,
+        palette: vec![0x000000, 0xFFFFFF],
+        pixels: vec![value; 6],
+        width: 3,
+        height: 2,
+    };
+}
+
+/// The pages `p1` to `p3` in a row titled `book`, the middle one white.
+pub fn book() -> Img2dArray {
+    return Img2dArray {
+        width: 3,
+        height: 1,
+        title: Some("book".to_string()),
+        pages: vec![page("p1", 0), page("p2", 1), page("p3", 0)],
+    };
+}
+
+pub fn encode_book(options: &EncodeOptions) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    encode_2dba(&book(), &mut bytes, options).unwrap();
+
+    return bytes;
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+}
+
+/// Offset of the length of page `index` in an encoded 2dba of either version, found by
+/// following the page lengths before it.
+pub fn page_offset(bytes: &[u8], index: usize) -> usize {
+    let (mut offset, checksums) = match bytes.starts_with(&ARRAY_MAGIC) {
+        true => (ARRAY_MAGIC.len() + 2, bytes[5] & FLAG_CHECKSUMS != 0),
+        false => (0, false),
+    };
+
+    // The title and the array width and height
+    let title_length = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    if title_length != ABSENT_STRING_LENGTH {
+        offset += title_length as usize;
+    }
+    offset += 2 + 8;
+
+    for _ in 0..index {
+        offset += 4 + u32_at(bytes, offset) as usize;
+        if checksums {
+            offset += 4;
+        }
+    }
+
+    return offset;
+}
+
+/// Offset of the last byte of page `index` in an encoded 2dba, the last of its pixels.
+pub fn page_end(bytes: &[u8], index: usize) -> usize {
+    let offset = page_offset(bytes, index);
+
+    return offset + 4 + u32_at(bytes, offset) as usize - 1;
+}
+
+/// A fresh directory below the system temporary directory, removed with everything in it
+/// when dropped.
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "2db-test-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir_all(&path).unwrap();
+
+        return TempDir { path };
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        return self.path.join(path);
+    }
+
+    /// Writes `contents` to `path` below the directory, creating the directories it's in.
+    pub fn write<P: AsRef<Path>>(&self, path: P, contents: &[u8]) -> PathBuf {
+        let file = self.join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, contents).unwrap();
+
+        return file;
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,529 @@
+use crate::error::PosterError;
+use crate::import::{dither_to_palette, nearest_color, quantize, Dither, Quantizer};
+use crate::poster::{unpack_rgb, Img2d, Img2dArray, MAX_PALETTE_LENGTH};
+use crate::validate::{check_canvas_size, check_pixel_count};
+
+pub struct TileOptions {
+    pub page_width: u32,
+    pub page_height: u32,
+    /// Base of the page labels, `mural` labels the pages `mural (r1,c1)`, `mural (r1,c2)`, ...
+    /// Falls back to the label of the image being tiled, without either the pages are
+    /// labelled `(r1,c1)`, `(r1,c2)`, ...
+    pub label: Option<String>,
+    /// Title of the array, falls back to the label of the image being tiled.
+    pub title: Option<String>,
+    /// Packed RGB colour used to fill edge pages past the end of the image.
+    pub pad_color: u32,
+}
+
+impl Default for TileOptions {
+    fn default() -> Self {
+        return TileOptions {
+            page_width: 128,
+            page_height: 128,
+            label: None,
+            title: None,
+            pad_color: 0x000000,
+        };
+    }
+}
+
+/// Parses a page size given as `WIDTHxHEIGHT` or as a single number for square pages.
+pub fn parse_page_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = match size.split_once(['x', 'X']) {
+        Some((width, height)) => (width.trim().parse().ok()?, height.trim().parse().ok()?),
+        None => {
+            let side: u32 = size.trim().parse().ok()?;
+            (side, side)
+        }
+    };
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    return Some((width, height));
+}
+
+/// Splits `image` into a grid of `page_width` by `page_height` pages, row by row.
+///
+/// Every page shares the palette of `image`. Edge pages are padded to full size with the
+/// pad colour, which is added to the palette if it isn't in it yet (or mapped to the closest
+/// entry if the palette is full). The width and height of the array are the number of
+/// columns and rows in the grid.
+///
+/// Fails if the padded pages would be too large to hold.
+pub fn tile_image(image: &Img2d, options: &TileOptions) -> Result<Img2dArray, PosterError> {
+    if image.palette.len() > MAX_PALETTE_LENGTH {
+        return Err(PosterError::PaletteOverflow {
+            length: image.palette.len(),
+            max: MAX_PALETTE_LENGTH,
+        });
+    }
+
+    let (page_width, page_height) = (options.page_width.max(1), options.page_height.max(1));
+    let columns = image.width.div_ceil(page_width);
+    let rows = image.height.div_ceil(page_height);
+
+    let mut palette = image.palette.clone();
+    let needs_padding = !image.width.is_multiple_of(page_width)
+        || !image.height.is_multiple_of(page_height)
+        || (image.pixels.len() as u64) < image.width as u64 * image.height as u64;
+    let pad = match palette.iter().position(|&color| color == options.pad_color) {
+        Some(index) => index as u8,
+        None if needs_padding && palette.len() < MAX_PALETTE_LENGTH => {
+            palette.push(options.pad_color);
+            (palette.len() - 1) as u8
+        }
+        None => {
+            let palette_rgb: Vec<[u8; 3]> = palette.iter().map(|&c| unpack_rgb(c)).collect();
+            nearest_color(&palette_rgb, unpack_rgb(options.pad_color)) as u8
+        }
+    };
+
+    let label = options
+        .label
+        .clone()
+        .or_else(|| image.label.clone())
+        .unwrap_or_default();
+
+    // The pages hold the image padded to whole pages, which may be larger than it is
+    let (width, height) = (image.width as u64, image.height as u64);
+    let too_large = || PosterError::ImageTooLarge {
+        width: columns as u64 * page_width as u64,
+        height: rows as u64 * page_height as u64,
+    };
+    let page_length = usize::try_from(page_width as u64 * page_height as u64);
+    let page_count = usize::try_from(columns as u64 * rows as u64);
+    let (page_length, page_count) = match (page_length, page_count) {
+        (Ok(page_length), Ok(page_count))
+            if page_length
+                .checked_mul(page_count)
+                .is_some_and(|length| isize::try_from(length).is_ok()) =>
+        {
+            (page_length, page_count)
+        }
+        _ => return Err(too_large()),
+    };
+
+    let mut pages: Vec<Img2d> = Vec::new();
+    if pages.try_reserve_exact(page_count).is_err() {
+        return Err(too_large());
+    }
+    for row in 0..rows as u64 {
+        for column in 0..columns as u64 {
+            let mut pixels: Vec<u8> = Vec::new();
+            if pixels.try_reserve_exact(page_length).is_err() {
+                return Err(too_large());
+            }
+            for y in row * page_height as u64..(row + 1) * page_height as u64 {
+                for x in column * page_width as u64..(column + 1) * page_width as u64 {
+                    let pixel = if x < width && y < height {
+                        usize::try_from(y * width + x)
+                            .ok()
+                            .and_then(|index| image.pixels.get(index))
+                            .copied()
+                    } else {
+                        None
+                    };
+                    pixels.push(pixel.unwrap_or(pad));
+                }
+            }
+
+            let position = format!("(r{},c{})", row + 1, column + 1);
+            pages.push(Img2d {
+                label: Some(if label.is_empty() {
+                    position
+                } else {
+                    format!("{} {}", label, position)
+                }),
+                tooltip: image.tooltip.clone(),
+                palette: palette.clone(),
+                pixels,
+                width: page_width,
+                height: page_height,
+            });
+        }
+    }
+
+    return Ok(Img2dArray {
+        width: columns,
+        height: rows,
+        title: options.title.clone().or_else(|| image.label.clone()),
+        pages,
+    });
+}
+
+pub struct StitchOptions {
+    /// Used if the pages have more than [`MAX_PALETTE_LENGTH`] distinct colours between them.
+    pub quantizer: Quantizer,
+    pub dither: Dither,
+    pub dither_strength: f32,
+    /// Label of the stitched image, falls back to the title of the array.
+    pub label: Option<String>,
+    /// Packed RGB colour for gaps left by smaller pages and for pixels outside a page palette.
+    pub fill_color: u32,
+}
+
+impl Default for StitchOptions {
+    fn default() -> Self {
+        return StitchOptions {
+            quantizer: Quantizer::MedianCut,
+            dither: Dither::None,
+            dither_strength: 1.0,
+            label: None,
+            fill_color: 0x000000,
+        };
+    }
+}
+
+/// Stitches the pages of a tiled `image` back into one image, the inverse of [`tile_image`].
+///
+/// The width and height of the array give the grid, pages are placed row by row. Each
+/// column is as wide as its widest page and each row as high as its highest. The page
+/// palettes are merged into one, identical colours are only kept once and the result is
+/// quantized again if more than [`MAX_PALETTE_LENGTH`] colours remain.
+///
+/// Fails if a page doesn't have exactly `width * height` pixels or if pages of very different
+/// sizes would make the stitched image too large, see [`check_canvas_size`].
+pub fn stitch_pages(image: &Img2dArray, options: &StitchOptions) -> Result<Img2d, PosterError> {
+    let (columns, rows) = (image.width as usize, image.height as usize);
+    let cells = columns.saturating_mul(rows);
+    if columns == 0 || rows == 0 || cells < image.pages.len() {
+        return Err(PosterError::GridMismatch {
+            columns: image.width,
+            rows: image.height,
+            pages: image.pages.len(),
+        });
+    }
+    for (index, page) in image.pages.iter().enumerate() {
+        check_pixel_count(page, Some(index))?;
+    }
+
+    // Cells past the last page are empty, leave them out so a huge grid isn't walked
+    let columns = columns.min(image.pages.len());
+    let rows = rows.min(image.pages.len().div_ceil(columns));
+
+    let page = |row: usize, column: usize| image.pages.get(row * columns + column);
+    let column_widths: Vec<u32> = (0..columns)
+        .map(|column| {
+            (0..rows)
+                .filter_map(|row| page(row, column).map(|page| page.width))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let row_heights: Vec<u32> = (0..rows)
+        .map(|row| {
+            (0..columns)
+                .filter_map(|column| page(row, column).map(|page| page.height))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let width = column_widths.iter().map(|&width| width as u64).sum::<u64>();
+    let height = row_heights.iter().map(|&height| height as u64).sum::<u64>();
+    check_canvas_size(width, height, &image.pages)?;
+    let (width, height) = (width as usize, height as usize);
+    let fill = unpack_rgb(options.fill_color);
+    let mut colors: Vec<[u8; 3]> = vec![fill; width * height];
+
+    let mut top = 0;
+    for (row, &row_height) in row_heights.iter().enumerate() {
+        let mut left = 0;
+        for (column, &column_width) in column_widths.iter().enumerate() {
+            if let Some(page) = page(row, column) {
+                for y in 0..page.height as usize {
+                    for x in 0..page.width as usize {
+                        let color = page
+                            .pixels
+                            .get(y * page.width as usize + x)
+                            .and_then(|&pixel| page.palette.get(pixel as usize))
+                            .map_or(fill, |&color| unpack_rgb(color));
+                        colors[(top + y) * width + left + x] = color;
+                    }
+                }
+            }
+            left += column_width as usize;
+        }
+        top += row_height as usize;
+    }
+
+    let palette = quantize(&colors, MAX_PALETTE_LENGTH, options.quantizer);
+    let pixels = dither_to_palette(
+        &colors,
+        width,
+        &palette,
+        options.dither,
+        options.dither_strength,
+    );
+
+    return Ok(Img2d {
+        label: options.label.clone().or_else(|| image.title.clone()),
+        tooltip: image.pages.first().and_then(|page| page.tooltip.clone()),
+        palette,
+        pixels,
+        width: width as u32,
+        height: height as u32,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32) -> Img2d {
+        return Img2d {
+            label: Some("mural".to_string()),
+            tooltip: None,
+            palette: vec![0x000000, 0xFF0000, 0x00FF00, 0x0000FF],
+            pixels: (0..width * height).map(|index| (index % 4) as u8).collect(),
+            width,
+            height,
+        };
+    }
+
+    fn solid_page(width: u32, height: u32, color: u32) -> Img2d {
+        return Img2d {
+            label: None,
+            tooltip: None,
+            palette: vec![color],
+            pixels: vec![0; (width * height) as usize],
+            width,
+            height,
+        };
+    }
+
+    fn array(width: u32, height: u32, pages: Vec<Img2d>) -> Img2dArray {
+        return Img2dArray {
+            width,
+            height,
+            title: None,
+            pages,
+        };
+    }
+
+    /// The colour of every pixel of `image`, whatever its palette.
+    fn colors(image: &Img2d) -> Vec<u32> {
+        return image
+            .pixels
+            .iter()
+            .map(|&pixel| image.palette[pixel as usize])
+            .collect();
+    }
+
+    #[test]
+    fn stitches_tiled_pages_back() {
+        let original = image(5, 3);
+        let options = TileOptions {
+            page_width: 2,
+            page_height: 2,
+            ..Default::default()
+        };
+        let tiled = tile_image(&original, &options).unwrap();
+        assert_eq!((tiled.width, tiled.height, tiled.pages.len()), (3, 2, 6));
+
+        // The padding comes back as part of the image
+        let stitched = stitch_pages(&tiled, &StitchOptions::default()).unwrap();
+        assert_eq!((stitched.width, stitched.height), (6, 4));
+        assert_eq!(stitched.label.as_deref(), Some("mural"));
+        let stitched_colors = colors(&stitched);
+        let original_colors = colors(&original);
+        for y in 0..3 {
+            assert_eq!(
+                stitched_colors[y * 6..y * 6 + 5],
+                original_colors[y * 5..y * 5 + 5]
+            );
+        }
+    }
+
+    #[test]
+    fn fills_gaps_left_by_smaller_pages() {
+        let pages = vec![
+            solid_page(2, 2, 0xFF0000),
+            solid_page(1, 1, 0x00FF00),
+            solid_page(1, 1, 0x0000FF),
+        ];
+        let options = StitchOptions {
+            fill_color: 0xFFFFFF,
+            ..Default::default()
+        };
+        let stitched = stitch_pages(&array(2, 2, pages), &options).unwrap();
+
+        // The last cell of the grid has no page, its column and row still take no room
+        assert_eq!((stitched.width, stitched.height), (3, 3));
+        assert_eq!(
+            colors(&stitched),
+            vec![
+                0xFF0000, 0xFF0000, 0x00FF00, //
+                0xFF0000, 0xFF0000, 0xFFFFFF, //
+                0x0000FF, 0xFFFFFF, 0xFFFFFF,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_grids_too_small_for_the_pages() {
+        for (width, height) in [(0, 1), (1, 0), (1, 2)] {
+            let pages = (0..3).map(|_| solid_page(1, 1, 0)).collect();
+            assert!(matches!(
+                stitch_pages(&array(width, height, pages), &StitchOptions::default()),
+                Err(PosterError::GridMismatch { columns, rows, pages: 3 })
+                    if (columns, rows) == (width, height)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_pages_with_the_wrong_pixel_count() {
+        let mut pages = vec![solid_page(2, 2, 0), solid_page(2, 2, 0)];
+        pages[1].pixels.pop();
+        assert!(matches!(
+            stitch_pages(&array(2, 1, pages), &StitchOptions::default()),
+            Err(PosterError::Invalid(violations)) if violations[0].page == Some(1)
+        ));
+    }
+
+    #[test]
+    fn rejects_pages_of_very_different_sizes() {
+        // A wide and a tall page in one row make a 20001 by 20000 image out of 40000 pixels
+        let pages = vec![solid_page(20000, 1, 0), solid_page(1, 20000, 0)];
+        assert!(matches!(
+            stitch_pages(&array(2, 1, pages), &StitchOptions::default()),
+            Err(PosterError::ImageTooLarge {
+                width: 20001,
+                height: 20000
+            })
+        ));
+    }
+
+    fn tile(image: &Img2d, options: TileOptions) -> Img2dArray {
+        return tile_image(
+            image,
+            &TileOptions {
+                page_width: 2,
+                page_height: 2,
+                ..options
+            },
+        )
+        .unwrap();
+    }
+
+    fn labels(array: &Img2dArray) -> Vec<&str> {
+        return array
+            .pages
+            .iter()
+            .map(|page| page.label.as_deref().unwrap())
+            .collect();
+    }
+
+    #[test]
+    fn pads_edge_pages() {
+        let original = image(5, 3);
+        let options = TileOptions {
+            pad_color: 0xFFFFFF,
+            ..Default::default()
+        };
+        let tiled = tile(&original, options);
+        assert_eq!((tiled.width, tiled.height), (3, 2));
+
+        // The pad colour is added to the palette of every page
+        let pad = 4;
+        for page in &tiled.pages {
+            assert_eq!((page.width, page.height), (2, 2));
+            assert_eq!(page.palette[..4], original.palette[..]);
+            assert_eq!(page.palette[pad as usize], 0xFFFFFF);
+        }
+        let pixel = |x: usize, y: usize| original.pixels[y * 5 + x];
+        assert_eq!(
+            tiled.pages[0].pixels,
+            vec![pixel(0, 0), pixel(1, 0), pixel(0, 1), pixel(1, 1)]
+        );
+        assert_eq!(
+            tiled.pages[2].pixels,
+            vec![pixel(4, 0), pad, pixel(4, 1), pad]
+        );
+        assert_eq!(
+            tiled.pages[3].pixels,
+            vec![pixel(0, 2), pixel(1, 2), pad, pad]
+        );
+        assert_eq!(tiled.pages[5].pixels, vec![pixel(4, 2), pad, pad, pad]);
+    }
+
+    #[test]
+    fn reuses_the_palette_for_padding() {
+        // Already in the palette
+        let tiled = tile(
+            &image(3, 3),
+            TileOptions {
+                pad_color: 0x00FF00,
+                ..Default::default()
+            },
+        );
+        assert_eq!(tiled.pages[0].palette.len(), 4);
+        assert_eq!(tiled.pages[3].pixels, vec![0, 2, 2, 2]);
+
+        // No room left, the closest colour is used
+        let mut full = image(3, 3);
+        full.palette = (0..255).map(|index| index << 16).collect();
+        let tiled = tile(
+            &full,
+            TileOptions {
+                pad_color: 0xFE0101,
+                ..Default::default()
+            },
+        );
+        assert_eq!(tiled.pages[0].palette.len(), 255);
+        assert_eq!(tiled.pages[3].pixels[1], 254);
+
+        // Pages that fit exactly don't need it
+        let tiled = tile(
+            &image(4, 2),
+            TileOptions {
+                pad_color: 0xFFFFFF,
+                ..Default::default()
+            },
+        );
+        assert_eq!(tiled.pages[0].palette.len(), 4);
+    }
+
+    #[test]
+    fn labels_pages_by_position() {
+        let tiled = tile(&image(3, 3), TileOptions::default());
+        assert_eq!(tiled.title.as_deref(), Some("mural"));
+        assert_eq!(
+            labels(&tiled),
+            vec![
+                "mural (r1,c1)",
+                "mural (r1,c2)",
+                "mural (r2,c1)",
+                "mural (r2,c2)"
+            ]
+        );
+
+        let options = TileOptions {
+            label: Some("wall".to_string()),
+            title: Some("The wall".to_string()),
+            ..Default::default()
+        };
+        let tiled = tile(&image(3, 1), options);
+        assert_eq!(tiled.title.as_deref(), Some("The wall"));
+        assert_eq!(labels(&tiled), vec!["wall (r1,c1)", "wall (r1,c2)"]);
+
+        let mut unlabelled = image(1, 3);
+        unlabelled.label = None;
+        let tiled = tile(&unlabelled, TileOptions::default());
+        assert_eq!(tiled.title, None);
+        assert_eq!(labels(&tiled), vec!["(r1,c1)", "(r2,c1)"]);
+    }
+
+    #[test]
+    fn parses_page_sizes() {
+        assert_eq!(parse_page_size("128"), Some((128, 128)));
+        assert_eq!(parse_page_size("64x32"), Some((64, 32)));
+        assert_eq!(parse_page_size("64X32"), Some((64, 32)));
+        assert_eq!(parse_page_size("0x32"), None);
+        assert_eq!(parse_page_size("64x"), None);
+        assert_eq!(parse_page_size("big"), None);
+    }
+}
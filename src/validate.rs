@@ -0,0 +1,452 @@
+use crate::error::PosterError;
+use crate::poster::{Img2d, Img2dArray, ABSENT_STRING_LENGTH, MAX_PALETTE_LENGTH};
+use std::fmt;
+use std::fmt::Formatter;
+
+/// Longest label, tooltip or title the binary format can store. Its length is a `u16`, the
+/// largest of which marks the string as absent.
+pub const MAX_STRING_LENGTH: usize = ABSENT_STRING_LENGTH as usize - 1;
+
+/// Pixels a stitched image or contact sheet can always have, however small its pages are.
+pub const CANVAS_BASE_PIXELS: u64 = 4096 * 4096;
+
+/// How many times the pixels of its pages a larger stitched image or contact sheet can have.
+/// Pages of very different sizes leave most of the canvas empty, this keeps a small file
+/// from asking for a huge one.
+pub const CANVAS_GROWTH: u64 = 4;
+
+/// A structural problem found by [`Img2d::validate`] or [`Img2dArray::validate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Index of the page the problem is in, `None` for single images and array headers.
+    pub page: Option<usize>,
+    pub kind: ViolationKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViolationKind {
+    /// `pixels` doesn't have exactly `width * height` entries.
+    PixelCount { expected: u64, actual: usize },
+    /// `count` pixels (the first at `first_index`) use `value`, which has no palette entry.
+    PixelOutOfRange {
+        value: u8,
+        count: usize,
+        first_index: usize,
+        palette_length: usize,
+    },
+    /// The palette has more entries than its `u8` length can describe.
+    PaletteOverflow { length: usize },
+    /// A palette entry has bits set above the packed `0xRRGGBB` colour.
+    PaletteColor { index: usize, color: u32 },
+    /// A label, tooltip or title is longer than its `u16` length can describe.
+    StringTooLong { field: &'static str, length: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(page) = self.page {
+            write!(f, "page {}: ", page + 1)?;
+        }
+
+        match &self.kind {
+            ViolationKind::PixelCount { expected, actual } => write!(
+                f,
+                "has {} pixels but width * height is {}",
+                actual, expected
+            ),
+            ViolationKind::PixelOutOfRange {
+                value,
+                count,
+                first_index,
+                palette_length,
+            } => write!(
+                f,
+                "pixel value {} is used {} times (first at pixel {}) but the palette only has {} entries",
+                value, count, first_index, palette_length
+            ),
+            ViolationKind::PaletteOverflow { length } => write!(
+                f,
+                "palette has {} entries, at most {} are allowed",
+                length, MAX_PALETTE_LENGTH
+            ),
+            ViolationKind::PaletteColor { index, color } => write!(
+                f,
+                "palette entry {} ({:#x}) is not a 0xRRGGBB color",
+                index, color
+            ),
+            ViolationKind::StringTooLong { field, length } => write!(
+                f,
+                "{} is {} characters long, at most {} are allowed",
+                field, length, MAX_STRING_LENGTH
+            ),
+        }
+    }
+}
+
+fn check_string(
+    violations: &mut Vec<Violation>,
+    page: Option<usize>,
+    field: &'static str,
+    string: &Option<String>,
+) {
+    if let Some(string) = string {
+        let length = string.chars().count();
+        if length > MAX_STRING_LENGTH {
+            violations.push(Violation {
+                page,
+                kind: ViolationKind::StringTooLong { field, length },
+            });
+        }
+    }
+}
+
+pub(crate) fn image_violations(image: &Img2d, page: Option<usize>) -> Vec<Violation> {
+    let mut violations: Vec<Violation> = Vec::new();
+
+    check_string(&mut violations, page, "label", &image.label);
+    check_string(&mut violations, page, "tooltip", &image.tooltip);
+
+    let expected = image.width as u64 * image.height as u64;
+    if image.pixels.len() as u64 != expected {
+        violations.push(Violation {
+            page,
+            kind: ViolationKind::PixelCount {
+                expected,
+                actual: image.pixels.len(),
+            },
+        });
+    }
+
+    if image.palette.len() > MAX_PALETTE_LENGTH {
+        violations.push(Violation {
+            page,
+            kind: ViolationKind::PaletteOverflow {
+                length: image.palette.len(),
+            },
+        });
+    }
+
+    for (index, &color) in image.palette.iter().enumerate() {
+        if color > 0xFFFFFF {
+            violations.push(Violation {
+                page,
+                kind: ViolationKind::PaletteColor { index, color },
+            });
+        }
+    }
+
+    // Group out of range pixels by value so a broken image doesn't report every pixel.
+    let mut out_of_range: Vec<(u8, usize, usize)> = Vec::new();
+    for (index, &value) in image.pixels.iter().enumerate() {
+        if (value as usize) < image.palette.len() {
+            continue;
+        }
+
+        match out_of_range.iter_mut().find(|(v, _, _)| *v == value) {
+            Some((_, count, _)) => *count += 1,
+            None => out_of_range.push((value, 1, index)),
+        }
+    }
+    for (value, count, first_index) in out_of_range {
+        violations.push(Violation {
+            page,
+            kind: ViolationKind::PixelOutOfRange {
+                value,
+                count,
+                first_index,
+                palette_length: image.palette.len(),
+            },
+        });
+    }
+
+    return violations;
+}
+
+/// Fails unless `image` has exactly `width * height` pixels, for code that lays pixels out by
+/// the dimensions. Those come straight from the file, so this is what keeps a tiny file from
+/// asking for a huge image. `page` is the page reported in the error.
+pub(crate) fn check_pixel_count(image: &Img2d, page: Option<usize>) -> Result<(), PosterError> {
+    let expected = (image.width as usize).checked_mul(image.height as usize);
+    if expected != Some(image.pixels.len()) {
+        return Err(PosterError::Invalid(vec![Violation {
+            page,
+            kind: ViolationKind::PixelCount {
+                expected: image.width as u64 * image.height as u64,
+                actual: image.pixels.len(),
+            },
+        }]));
+    }
+
+    return Ok(());
+}
+
+/// Fails with [`PosterError::ImageTooLarge`] if a `width` by `height` canvas for `pages` is
+/// larger than [`CANVAS_BASE_PIXELS`] and [`CANVAS_GROWTH`] allow, or a side doesn't fit a
+/// `u32`.
+pub(crate) fn check_canvas_size(
+    width: u64,
+    height: u64,
+    pages: &[Img2d],
+) -> Result<(), PosterError> {
+    let page_pixels: u64 = pages.iter().map(|page| page.pixels.len() as u64).sum();
+    let allowed = CANVAS_BASE_PIXELS.max(page_pixels.saturating_mul(CANVAS_GROWTH));
+    if width > u32::MAX as u64 || height > u32::MAX as u64 || width * height > allowed {
+        return Err(PosterError::ImageTooLarge { width, height });
+    }
+
+    return Ok(());
+}
+
+pub(crate) fn title_violations(title: &Option<String>) -> Vec<Violation> {
+    let mut violations: Vec<Violation> = Vec::new();
+    check_string(&mut violations, None, "title", title);
+
+    return violations;
+}
+
+impl Img2d {
+    /// Checks that the image can be written and read back as is, reporting every problem
+    /// as a [`PosterError::Invalid`].
+    pub fn validate(&self) -> Result<(), PosterError> {
+        let violations = image_violations(self, None);
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        return Err(PosterError::Invalid(violations));
+    }
+}
+
+impl Img2dArray {
+    /// Checks the title and every page, see [`Img2d::validate`].
+    pub fn validate(&self) -> Result<(), PosterError> {
+        let mut violations = title_violations(&self.title);
+        for (index, page) in self.pages.iter().enumerate() {
+            violations.extend(image_violations(page, Some(index)));
+        }
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        return Err(PosterError::Invalid(violations));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{book, page};
+
+    fn violations(result: Result<(), PosterError>) -> Vec<Violation> {
+        return match result {
+            Err(PosterError::Invalid(violations)) => violations,
+            _ => panic!("no violations reported"),
+        };
+    }
+
+    #[test]
+    fn accepts_valid_posters() {
+        page("p1", 1).validate().unwrap();
+        book().validate().unwrap();
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut image = page("x".repeat(MAX_STRING_LENGTH + 1).as_str(), 0);
+        image.pixels = vec![0, 1, 2, 2, 7];
+        image.palette = vec![0x000000, 0xFFFFFF, 0x1000000];
+
+        assert_eq!(
+            violations(image.validate()),
+            vec![
+                Violation {
+                    page: None,
+                    kind: ViolationKind::StringTooLong {
+                        field: "label",
+                        length: MAX_STRING_LENGTH + 1
+                    }
+                },
+                Violation {
+                    page: None,
+                    kind: ViolationKind::PixelCount {
+                        expected: 6,
+                        actual: 5
+                    }
+                },
+                Violation {
+                    page: None,
+                    kind: ViolationKind::PaletteColor {
+                        index: 2,
+                        color: 0x1000000
+                    }
+                },
+                Violation {
+                    page: None,
+                    kind: ViolationKind::PixelOutOfRange {
+                        value: 7,
+                        count: 1,
+                        first_index: 4,
+                        palette_length: 3
+                    }
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_out_of_range_pixels_by_value() {
+        let mut image = page("p1", 0);
+        image.pixels = vec![0, 5, 3, 5, 5, 3];
+
+        let kinds: Vec<ViolationKind> = violations(image.validate())
+            .into_iter()
+            .map(|violation| violation.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ViolationKind::PixelOutOfRange {
+                    value: 5,
+                    count: 3,
+                    first_index: 1,
+                    palette_length: 2
+                },
+                ViolationKind::PixelOutOfRange {
+                    value: 3,
+                    count: 2,
+                    first_index: 2,
+                    palette_length: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_page_of_array_problems() {
+        let mut array = book();
+        array.title = Some("t".repeat(MAX_STRING_LENGTH + 1));
+        array.pages[2].palette = vec![0; MAX_PALETTE_LENGTH + 1];
+        array.pages[2].tooltip = Some("t".repeat(MAX_STRING_LENGTH));
+
+        let violations = violations(array.validate());
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].page, None);
+        assert!(matches!(
+            violations[0].kind,
+            ViolationKind::StringTooLong { field: "title", .. }
+        ));
+        assert_eq!(
+            violations[1],
+            Violation {
+                page: Some(2),
+                kind: ViolationKind::PaletteOverflow {
+                    length: MAX_PALETTE_LENGTH + 1
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        let image = page("é".repeat(MAX_STRING_LENGTH).as_str(), 0);
+        image.validate().unwrap();
+    }
+
+    #[test]
+    fn describes_violations() {
+        let describe = |page: Option<usize>, kind: ViolationKind| {
+            return Violation { page, kind }.to_string();
+        };
+        assert_eq!(
+            describe(
+                Some(0),
+                ViolationKind::PixelCount {
+                    expected: 6,
+                    actual: 5
+                }
+            ),
+            "page 1: has 5 pixels but width * height is 6"
+        );
+        assert_eq!(
+            describe(
+                None,
+                ViolationKind::PixelOutOfRange {
+                    value: 7,
+                    count: 2,
+                    first_index: 4,
+                    palette_length: 3
+                }
+            ),
+            "pixel value 7 is used 2 times (first at pixel 4) but the palette only has 3 entries"
+        );
+        assert_eq!(
+            describe(None, ViolationKind::PaletteOverflow { length: 300 }),
+            "palette has 300 entries, at most 255 are allowed"
+        );
+        assert_eq!(
+            describe(
+                Some(2),
+                ViolationKind::PaletteColor {
+                    index: 1,
+                    color: 0x1000000
+                }
+            ),
+            "page 3: palette entry 1 (0x1000000) is not a 0xRRGGBB color"
+        );
+        assert_eq!(
+            describe(
+                None,
+                ViolationKind::StringTooLong {
+                    field: "tooltip",
+                    length: 70000
+                }
+            ),
+            "tooltip is 70000 characters long, at most 65534 are allowed"
+        );
+    }
+
+    #[test]
+    fn checks_pixel_counts() {
+        let mut image = page("p1", 0);
+        check_pixel_count(&image, None).unwrap();
+
+        image.pixels.push(0);
+        let violations = violations(check_pixel_count(&image, Some(3)));
+        assert_eq!(
+            violations,
+            vec![Violation {
+                page: Some(3),
+                kind: ViolationKind::PixelCount {
+                    expected: 6,
+                    actual: 7
+                }
+            }]
+        );
+
+        // Dimensions whose product overflows never match
+        image.width = u32::MAX;
+        image.height = u32::MAX;
+        assert!(check_pixel_count(&image, None).is_err());
+    }
+
+    #[test]
+    fn checks_canvas_sizes() {
+        let pages = book().pages;
+        check_canvas_size(4096, 4096, &pages).unwrap();
+        assert!(matches!(
+            check_canvas_size(4097, 4096, &pages),
+            Err(PosterError::ImageTooLarge {
+                width: 4097,
+                height: 4096
+            })
+        ));
+        assert!(check_canvas_size(u32::MAX as u64 + 1, 1, &pages).is_err());
+
+        // Larger pages allow a larger canvas
+        let mut large = page("p1", 0);
+        (large.width, large.height) = (5000, 5000);
+        large.pixels = vec![0; 5000 * 5000];
+        check_canvas_size(10000, 10000, &[large]).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use crate::error::PosterError;
+use crate::poster::{
+    decode_2db, parse_image_header, DecodeOptions, Img2dArrayDecoder, FLAG_CHECKSUMS,
+};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// What [`verify_2db`] or [`verify_2dba`] found wrong with a binary file.
+pub struct Verification {
+    /// Whether the file has checksums, without them only damage that breaks parsing is found.
+    pub checksums: bool,
+    /// Number of pages found, damaged ones included. Always 1 for a 2db.
+    pub pages: usize,
+    /// Index and error of every page that failed its checksum or couldn't be parsed.
+    pub damaged_pages: Vec<(usize, PosterError)>,
+    /// Damage outside the pages, like a file checksum mismatch or a truncated page that
+    /// leaves the rest of the file unreadable.
+    pub file_error: Option<PosterError>,
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        return self.damaged_pages.is_empty() && self.file_error.is_none();
+    }
+}
+
+/// Checks a 2db image against its checksum, decoding it in strict mode. Only fails if the
+/// file can't be read at all.
+pub fn verify_2db<R: Read>(mut reader: R) -> Result<Verification, PosterError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let (_, flags, _) = parse_image_header(&bytes)?;
+
+    return Ok(Verification {
+        checksums: flags & FLAG_CHECKSUMS != 0,
+        pages: 1,
+        damaged_pages: Vec::new(),
+        file_error: decode_2db(bytes.as_slice(), &DecodeOptions { strict: true }).err(),
+    });
+}
+
+/// Checks every page of a 2dba image array against its checksum, decoding it in strict mode
+/// and carrying on past damaged pages as far as the page lengths allow. Only fails if the
+/// array header can't be read.
+pub fn verify_2dba<R: Read>(reader: R) -> Result<Verification, PosterError> {
+    let mut decoder = Img2dArrayDecoder::new(reader, &DecodeOptions { strict: true })?;
+    let mut verification = Verification {
+        checksums: decoder.flags & FLAG_CHECKSUMS != 0,
+        pages: 0,
+        damaged_pages: Vec::new(),
+        file_error: None,
+    };
+
+    while let Some(page) = decoder.next() {
+        let e = match page {
+            Ok(_) => {
+                verification.pages += 1;
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        // The decoder only carries on after errors confined to a single page
+        if decoder.is_finished() {
+            verification.file_error = Some(e);
+        } else {
+            verification.damaged_pages.push((verification.pages, e));
+            verification.pages += 1;
+        }
+    }
+
+    return Ok(verification);
+}
+
+pub fn verify_2db_file(file: &Path) -> Result<Verification, PosterError> {
+    return verify_2db(BufReader::new(File::open(file)?));
+}
+
+pub fn verify_2dba_file(file: &Path) -> Result<Verification, PosterError> {
+    return verify_2dba(BufReader::new(File::open(file)?));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poster::{encode_2db, EncodeOptions, FormatVersion};
+    use crate::testing::{encode_book, page, page_end};
+
+    fn checksum_options() -> EncodeOptions {
+        return EncodeOptions {
+            version: FormatVersion::V2,
+            checksums: true,
+            ..Default::default()
+        };
+    }
+
+    fn array_bytes() -> Vec<u8> {
+        return encode_book(&checksum_options());
+    }
+
+    #[test]
+    fn intact_array() {
+        let verification = verify_2dba(array_bytes().as_slice()).unwrap();
+        assert!(verification.checksums);
+        assert_eq!(verification.pages, 3);
+        assert!(verification.is_intact());
+    }
+
+    #[test]
+    fn finds_damaged_page() {
+        let mut bytes = array_bytes();
+        let pixel = page_end(&bytes, 1);
+        bytes[pixel] ^= 1;
+
+        let verification = verify_2dba(bytes.as_slice()).unwrap();
+        assert_eq!(verification.pages, 3);
+        assert_eq!(verification.damaged_pages.len(), 1);
+        let (index, e) = &verification.damaged_pages[0];
+        assert_eq!(*index, 1);
+        assert!(matches!(
+            e,
+            PosterError::ChecksumMismatch {
+                field: "page checksum",
+                ..
+            }
+        ));
+        // The file checksum covers the page too
+        assert!(matches!(
+            verification.file_error,
+            Some(PosterError::ChecksumMismatch {
+                field: "file checksum",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn finds_damaged_image() {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_2db(&page("p1", 1), &mut bytes, &checksum_options()).unwrap();
+        assert!(verify_2db(bytes.as_slice()).unwrap().is_intact());
+
+        let checksum_offset = bytes.len() - 4;
+        bytes[checksum_offset - 1] ^= 1;
+        let verification = verify_2db(bytes.as_slice()).unwrap();
+        assert!(matches!(
+            verification.file_error,
+            Some(PosterError::ChecksumMismatch {
+                field: "file checksum",
+                offset,
+                ..
+            }) if offset == checksum_offset
+        ));
+    }
+}
@@ -0,0 +1,251 @@
+//! Watching inputs for changes, so they can be converted again whenever they're saved.
+
+use crate::error::PosterError;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+/// A directory to watch.
+///
+/// Directories are watched rather than files because editors often save by writing a new
+/// file and renaming it over the old one, which a watch on the old file doesn't see.
+pub struct WatchRoot {
+    pub path: PathBuf,
+    /// Also watch every directory below `path`.
+    pub recursive: bool,
+}
+
+impl WatchRoot {
+    /// The directory `file` is in, watched without its subdirectories.
+    pub fn parent_of(file: &Path) -> WatchRoot {
+        let path = match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        return WatchRoot {
+            path,
+            recursive: false,
+        };
+    }
+}
+
+/// Watches `roots` until the process is stopped, calling `on_change` with the files that were
+/// created or modified once none have changed for `debounce`, so a burst of saves is handled
+/// once. Only files `filter` accepts are reported, in the order they first changed.
+///
+/// Errors while watching are passed to `on_error` and watching carries on, only failing to
+/// start watching is returned.
+pub fn watch<P, C, E>(
+    roots: &[WatchRoot],
+    debounce: Duration,
+    filter: P,
+    on_change: C,
+    on_error: E,
+) -> Result<(), PosterError>
+where
+    P: Fn(&Path) -> bool,
+    C: FnMut(Vec<PathBuf>),
+    E: FnMut(PosterError),
+{
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    for root in roots {
+        let mode = if root.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher
+            .watch(&root.path, mode)
+            .map_err(|e| io::Error::other(format!("{}: {}", root.path.display(), e)))?;
+    }
+
+    return debounce_events(&receiver, debounce, filter, on_change, on_error);
+}
+
+/// The event loop of [`watch`], returning once every sender of `receiver` is gone.
+fn debounce_events<P, C, E>(
+    receiver: &mpsc::Receiver<notify::Result<notify::Event>>,
+    debounce: Duration,
+    filter: P,
+    mut on_change: C,
+    mut on_error: E,
+) -> Result<(), PosterError>
+where
+    P: Fn(&Path) -> bool,
+    C: FnMut(Vec<PathBuf>),
+    E: FnMut(PosterError),
+{
+    let mut pending: Vec<PathBuf> = Vec::new();
+    loop {
+        let received = if pending.is_empty() {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            receiver.recv_timeout(debounce)
+        };
+
+        match received {
+            Ok(Ok(event)) => {
+                if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    continue;
+                }
+                for path in event.paths {
+                    if path.is_file() && filter(&path) && !pending.contains(&path) {
+                        pending.push(path);
+                    }
+                }
+            }
+            Ok(Err(e)) => on_error(io::Error::other(e).into()),
+            Err(RecvTimeoutError::Timeout) => on_change(std::mem::take(&mut pending)),
+            Err(RecvTimeoutError::Disconnected) => {
+                if !pending.is_empty() {
+                    on_change(pending);
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use notify::event::{CreateKind, ModifyKind, RemoveKind};
+    use notify::Event;
+    use std::fs;
+    use std::thread;
+
+    fn event(kind: EventKind, path: &Path) -> notify::Result<Event> {
+        return Ok(Event::new(kind).add_path(path.to_path_buf()));
+    }
+
+    /// Runs the event loop over `events` sent from another thread, returning every batch of
+    /// changes and the number of errors.
+    fn debounce<F>(send: F) -> (Vec<Vec<PathBuf>>, usize)
+    where
+        F: FnOnce(mpsc::Sender<notify::Result<Event>>) + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let sending = thread::spawn(move || send(sender));
+
+        let mut batches: Vec<Vec<PathBuf>> = Vec::new();
+        let mut errors = 0;
+        debounce_events(
+            &receiver,
+            Duration::from_millis(50),
+            |path| path.extension().is_some_and(|ext| ext == "2dj"),
+            |changed| batches.push(changed),
+            |_| errors += 1,
+        )
+        .unwrap();
+        sending.join().unwrap();
+
+        return (batches, errors);
+    }
+
+    #[test]
+    fn reports_each_changed_file_once() {
+        let dir = TempDir::new();
+        let a = dir.write("a.2dj", b"{}");
+        let b = dir.write("b.2dj", b"{}");
+        let ignored = dir.write("c.txt", b"");
+        let gone = dir.join("gone.2dj");
+        let directory = dir.join("sub.2dj");
+        fs::create_dir(&directory).unwrap();
+
+        let (a_sent, b_sent) = (a.clone(), b.clone());
+        let (batches, errors) = debounce(move |sender| {
+            let created = EventKind::Create(CreateKind::File);
+            let modified = EventKind::Modify(ModifyKind::Any);
+            for (kind, path) in [
+                (modified, &a_sent),
+                (created, &b_sent),
+                (modified, &a_sent),
+                (created, &ignored),
+                (created, &gone),
+                (created, &directory),
+                (EventKind::Remove(RemoveKind::File), &b_sent),
+            ] {
+                sender.send(event(kind, path)).unwrap();
+            }
+        });
+        assert_eq!(batches, vec![vec![a, b]]);
+        assert_eq!(errors, 0);
+    }
+
+    #[test]
+    fn waits_for_changes_to_settle() {
+        let dir = TempDir::new();
+        let a = dir.write("a.2dj", b"{}");
+        let b = dir.write("b.2dj", b"{}");
+
+        let (a_sent, b_sent) = (a.clone(), b.clone());
+        let (batches, _) = debounce(move |sender| {
+            let modified = EventKind::Modify(ModifyKind::Any);
+            sender.send(event(modified, &a_sent)).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            sender.send(event(modified, &b_sent)).unwrap();
+            sender.send(event(modified, &a_sent)).unwrap();
+        });
+        assert_eq!(batches, vec![vec![a.clone()], vec![b, a]]);
+    }
+
+    #[test]
+    fn carries_on_after_errors() {
+        let dir = TempDir::new();
+        let a = dir.write("a.2dj", b"{}");
+
+        let a_sent = a.clone();
+        let (batches, errors) = debounce(move |sender| {
+            sender.send(Err(notify::Error::generic("lost"))).unwrap();
+            sender
+                .send(event(EventKind::Modify(ModifyKind::Any), &a_sent))
+                .unwrap();
+        });
+        assert_eq!(batches, vec![vec![a]]);
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn watches_directories() {
+        let dir = TempDir::new();
+        let roots = [WatchRoot {
+            path: dir.path.clone(),
+            recursive: true,
+        }];
+        let (sender, receiver) = mpsc::channel();
+        // Watching never ends, the thread is left behind when the test is done
+        thread::spawn(move || {
+            watch(
+                &roots,
+                Duration::from_millis(50),
+                |_| true,
+                |changed| {
+                    let _ = sender.send(changed);
+                },
+                |_| {},
+            )
+            .unwrap();
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        let file = dir.write("castle.2dj", b"{}");
+        let changed = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(changed
+            .iter()
+            .any(|path| path.file_name() == file.file_name()));
+    }
+
+    #[test]
+    fn watches_the_directory_of_files() {
+        let root = WatchRoot::parent_of(Path::new("art/castle.2dj"));
+        assert_eq!((root.path, root.recursive), (PathBuf::from("art"), false));
+        let root = WatchRoot::parent_of(Path::new("castle.2dj"));
+        assert_eq!(root.path, PathBuf::from("."));
+    }
+}